@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use crate::cue;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
@@ -20,12 +21,7 @@ impl FileEntry {
     pub fn name(&self) -> String {
         match self {
             FileEntry::ParentDirectory(_) => "..".to_string(),
-            _ => self
-                .path()
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("")
-                .to_string(),
+            _ => cue::display_name(self.path()),
         }
     }
 
@@ -62,25 +58,32 @@ impl Browser {
         let has_parent = self.current_dir.parent().is_some();
 
         // Read directory entries
+        let mut files = Vec::new();
         if let Ok(entries) = fs::read_dir(&self.current_dir) {
             for entry in entries.flatten() {
                 let path = entry.path();
                 if path.is_dir() {
                     self.entries.push(FileEntry::Directory(path));
-                } else if is_audio_file(&path) {
-                    self.entries.push(FileEntry::AudioFile(path));
+                } else {
+                    files.push(path);
                 }
             }
         }
+        self.entries.extend(
+            expand_cue_sheets(files)
+                .into_iter()
+                .map(FileEntry::AudioFile),
+        );
 
-        // Sort: directories first, then files, all alphabetically
+        // Sort: directories first, then files, all alphabetically. CUE tracks sort by
+        // their sheet, then by track number.
         self.entries.sort_by(|a, b| match (a, b) {
             (FileEntry::Directory(_), FileEntry::AudioFile(_)) => std::cmp::Ordering::Less,
             (FileEntry::AudioFile(_), FileEntry::Directory(_)) => std::cmp::Ordering::Greater,
-            (FileEntry::Directory(p1), FileEntry::Directory(p2))
-            | (FileEntry::AudioFile(p1), FileEntry::AudioFile(p2)) => {
+            (FileEntry::Directory(p1), FileEntry::Directory(p2)) => {
                 p1.file_name().cmp(&p2.file_name())
             }
+            (FileEntry::AudioFile(p1), FileEntry::AudioFile(p2)) => p1.cmp(p2),
             _ => std::cmp::Ordering::Equal,
         });
 
@@ -169,6 +172,12 @@ impl Browser {
         self.selected = self.selected.saturating_sub(1);
     }
 
+    pub fn select(&mut self, index: usize) {
+        if index < self.entries.len() {
+            self.selected = index;
+        }
+    }
+
     pub fn select_first(&mut self) {
         self.selected = 0;
     }
@@ -203,15 +212,41 @@ impl Browser {
             .filter_map(std::result::Result::ok)
         {
             let path = entry.path();
-            if path.is_file() && is_audio_file(path) {
+            if path.is_file() {
                 files.push(path.to_path_buf());
             }
         }
+        let mut files = expand_cue_sheets(files);
         files.sort();
         files
     }
 }
 
+// Keeps the audio files among `files`, replacing CUE sheets with their tracks and
+// dropping the images they split up
+fn expand_cue_sheets(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut tracks = Vec::new();
+    let mut split = HashSet::new();
+    for path in files.iter().filter(|path| cue::is_cue_file(path)) {
+        let Some(sheet) = cue::sheet(path) else {
+            continue;
+        };
+        split.extend(sheet.files().map(Path::to_path_buf));
+        tracks.extend(
+            sheet
+                .tracks
+                .iter()
+                .map(|track| cue::track_path(path, track.number)),
+        );
+    }
+
+    files
+        .into_iter()
+        .filter(|path| is_audio_file(path) && !split.contains(path))
+        .chain(tracks)
+        .collect()
+}
+
 pub fn is_audio_file(path: &Path) -> bool {
     path.extension()
         .and_then(|ext| ext.to_str())
@@ -0,0 +1,358 @@
+use crate::dynamics::{LIMITER_RELEASE_MS, smoothing};
+use rodio::Source;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Level of the center and surround channels in a stereo downmix (-3 dB, ITU-R BS.775)
+const SURROUND_LEVEL: f32 = std::f32::consts::FRAC_1_SQRT_2;
+// Level of a back center channel on each side, split between both surrounds
+const BACK_CENTER_LEVEL: f32 = SURROUND_LEVEL * std::f32::consts::FRAC_1_SQRT_2;
+
+/// How the decoded channels are mixed down to the stereo output.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChannelSettings {
+    /// -1.0 is fully left, 1.0 fully right
+    pub balance: f32,
+    /// Sums both sides into the middle, for one-ear listening
+    pub mono: bool,
+}
+
+impl ChannelSettings {
+    /// Describes the output for a source with `channels` channels, such as
+    /// "5.1 → stereo, balance L20%".
+    pub fn describe(&self, channels: u16) -> String {
+        let layout = layout_name(channels);
+        let mut text = if self.mono && channels > 1 {
+            format!("{layout} → mono")
+        } else if channels > 2 {
+            format!("{layout} → stereo")
+        } else {
+            layout
+        };
+
+        if self.balance_label() != "Center" {
+            text.push_str(&format!(", balance {}", self.balance_label()));
+        }
+        text
+    }
+
+    /// "Center", or the side the balance leans to, such as "L20%".
+    pub fn balance_label(&self) -> String {
+        let percent = (self.balance.abs() * 100.0).round();
+        if percent == 0.0 {
+            String::from("Center")
+        } else if self.balance < 0.0 {
+            format!("L{percent:.0}%")
+        } else {
+            format!("R{percent:.0}%")
+        }
+    }
+}
+
+/// Name of the usual layout with `channels` channels.
+pub fn layout_name(channels: u16) -> String {
+    match channels {
+        1 => String::from("Mono"),
+        2 => String::from("Stereo"),
+        6 => String::from("5.1"),
+        8 => String::from("7.1"),
+        n => format!("{n} ch"),
+    }
+}
+
+/// Channel settings shared with the playing source. The source compares the generation
+/// once per frame and only locks the settings when it changed.
+#[derive(Default)]
+pub struct ChannelControl {
+    settings: Mutex<ChannelSettings>,
+    generation: AtomicU64,
+}
+
+impl ChannelControl {
+    pub fn set(&self, settings: ChannelSettings) {
+        *self.settings.lock().unwrap() = settings;
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+// Left and right share of each input channel, in the order symphonia interleaves them
+// (front left, front right, front center, LFE, back left, back right, back center, side
+// left, side right, leaving out the ones a layout lacks). The LFE is left out, as is
+// usual for a stereo downmix. The shares are the ITU ones, so dialogue keeps its level
+// and the mixer limits whatever goes over full scale.
+fn downmix_matrix(channels: usize) -> Vec<(f32, f32)> {
+    const FL: (f32, f32) = (1.0, 0.0);
+    const FR: (f32, f32) = (0.0, 1.0);
+    const C: (f32, f32) = (SURROUND_LEVEL, SURROUND_LEVEL);
+    const LFE: (f32, f32) = (0.0, 0.0);
+    const SL: (f32, f32) = (SURROUND_LEVEL, 0.0);
+    const SR: (f32, f32) = (0.0, SURROUND_LEVEL);
+    const BC: (f32, f32) = (BACK_CENTER_LEVEL, BACK_CENTER_LEVEL);
+
+    match channels {
+        1 => vec![(1.0, 1.0)],
+        2 => vec![FL, FR],
+        3 => vec![FL, FR, C],
+        4 => vec![FL, FR, SL, SR],
+        5 => vec![FL, FR, C, SL, SR],
+        6 => vec![FL, FR, C, LFE, SL, SR],
+        // 6.1: the back center comes before the sides
+        7 => vec![FL, FR, C, LFE, BC, SL, SR],
+        8 => vec![FL, FR, C, LFE, SL, SR, SL, SR],
+        // Unknown layouts alternate between the sides
+        n => (0..n).map(|i| if i % 2 == 0 { FL } else { FR }).collect(),
+    }
+}
+
+/// Source turning any channel layout into stereo, then applying mono and balance.
+/// The output always has two channels.
+pub struct ChannelMixer<S> {
+    input: S,
+    control: Arc<ChannelControl>,
+    generation: u64,
+    input_channels: u16,
+    // Per input channel, its share of the left and right output with balance applied
+    matrix: Vec<(f32, f32)>,
+    frame: [f32; 2],
+    // Output sample to hand out next: 0 (left), 1 (right) or 2 (frame used up)
+    frame_pos: usize,
+    // Set when the matrix sums to more than full scale on a side
+    limit: bool,
+    limiter_release: f32,
+    limiter_gain: f32,
+}
+
+impl<S> ChannelMixer<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, control: Arc<ChannelControl>) -> Self {
+        Self {
+            input,
+            control,
+            // Force a lookup on the first frame
+            generation: u64::MAX,
+            input_channels: 0,
+            matrix: Vec::new(),
+            frame: [0.0; 2],
+            frame_pos: 2,
+            limit: false,
+            limiter_release: 0.0,
+            limiter_gain: 1.0,
+        }
+    }
+
+    fn update_matrix(&mut self) {
+        let generation = self.control.generation.load(Ordering::Acquire);
+        let channels = self.input.channels().max(1);
+        if generation == self.generation && channels == self.input_channels {
+            return;
+        }
+        self.generation = generation;
+        self.input_channels = channels;
+
+        let settings = *self.control.settings.lock().unwrap();
+        // The centered side stays at full level, the other one fades out
+        let balance = settings.balance.clamp(-1.0, 1.0);
+        let left_gain = (1.0 - balance).min(1.0);
+        let right_gain = (1.0 + balance).min(1.0);
+
+        self.matrix = downmix_matrix(channels as usize)
+            .into_iter()
+            .map(|(l, r)| {
+                // Mono sums before balancing, so the balance still picks the ear
+                let (l, r) = if settings.mono {
+                    (f32::midpoint(l, r), f32::midpoint(l, r))
+                } else {
+                    (l, r)
+                };
+                (l * left_gain, r * right_gain)
+            })
+            .collect();
+
+        let left: f32 = self.matrix.iter().map(|(l, _)| l).sum();
+        let right: f32 = self.matrix.iter().map(|(_, r)| r).sum();
+        self.limit = left.max(right) > 1.0;
+        self.limiter_release = smoothing(LIMITER_RELEASE_MS, self.input.sample_rate());
+    }
+
+    fn process_frame(&mut self) -> bool {
+        self.update_matrix();
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, (l, r)) in self.matrix.iter().enumerate() {
+            let Some(sample) = self.input.next() else {
+                // Sources end on frame boundaries, so this only happens at the start
+                if i == 0 {
+                    return false;
+                }
+                break;
+            };
+            left += sample * l;
+            right += sample * r;
+        }
+
+        if self.limit {
+            // Loud channels adding up hold back at once, and come back slowly
+            let peak = f32::max(left.abs(), right.abs());
+            let needed = if peak > 1.0 { 1.0 / peak } else { 1.0 };
+            let recovered = 1.0 + self.limiter_release * (self.limiter_gain - 1.0);
+            self.limiter_gain = recovered.min(needed);
+            left *= self.limiter_gain;
+            right *= self.limiter_gain;
+        }
+
+        self.frame = [left, right];
+        self.frame_pos = 0;
+        true
+    }
+}
+
+impl<S> Iterator for ChannelMixer<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame_pos >= 2 && !self.process_frame() {
+            return None;
+        }
+        let sample = self.frame[self.frame_pos];
+        self.frame_pos += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for ChannelMixer<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), rodio::source::SeekError> {
+        self.input.try_seek(pos)?;
+        self.frame_pos = 2;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn mix(channels: u16, frame: &[f32], settings: ChannelSettings) -> Vec<f32> {
+        let control = Arc::new(ChannelControl::default());
+        control.set(settings);
+        let input = SamplesBuffer::new(channels, 48_000, frame.to_vec());
+        ChannelMixer::new(input, control).collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-4, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn test_stereo_passes_through() {
+        let output = mix(2, &[0.5, -0.25, 0.1, 0.2], ChannelSettings::default());
+        assert_close(&output, &[0.5, -0.25, 0.1, 0.2]);
+    }
+
+    #[test]
+    fn test_mono_source_plays_on_both_sides() {
+        let output = mix(1, &[0.5, 0.25], ChannelSettings::default());
+        assert_close(&output, &[0.5, 0.5, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn test_downmix_5_1() {
+        // Center goes to both sides at -3 dB, the LFE is dropped
+        let output = mix(
+            6,
+            &[0.0, 0.0, 1.0, 1.0, 0.0, 0.0],
+            ChannelSettings::default(),
+        );
+        assert_close(&output, &[SURROUND_LEVEL, SURROUND_LEVEL]);
+
+        // Full scale on every channel is limited to full scale
+        let output = mix(6, &[1.0; 6], ChannelSettings::default());
+        assert_close(&output, &[1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_downmix_6_1_splits_the_back_center() {
+        let output = mix(
+            7,
+            &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+            ChannelSettings::default(),
+        );
+        assert_close(&output, &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_downmix_7_1_keeps_sides_apart() {
+        let output = mix(
+            8,
+            &[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            ChannelSettings::default(),
+        );
+        assert!(output[0] > 0.0);
+        assert_eq!(output[1], 0.0);
+    }
+
+    #[test]
+    fn test_mono_and_balance() {
+        let mono = ChannelSettings {
+            balance: 0.0,
+            mono: true,
+        };
+        assert_close(&mix(2, &[1.0, 0.0], mono), &[0.5, 0.5]);
+
+        let right = ChannelSettings {
+            balance: 0.5,
+            mono: false,
+        };
+        assert_close(&mix(2, &[1.0, 1.0], right), &[0.5, 1.0]);
+
+        let left_ear = ChannelSettings {
+            balance: -1.0,
+            mono: true,
+        };
+        assert_close(&mix(2, &[1.0, 0.0], left_ear), &[0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_describe() {
+        let settings = ChannelSettings {
+            balance: -0.2,
+            mono: false,
+        };
+        assert_eq!(settings.describe(6), "5.1 → stereo, balance L20%");
+        assert_eq!(ChannelSettings::default().describe(2), "Stereo");
+        let mono = ChannelSettings {
+            balance: 0.0,
+            mono: true,
+        };
+        assert_eq!(mono.describe(2), "Stereo → mono");
+    }
+}
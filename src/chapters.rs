@@ -0,0 +1,808 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+// Pressing previous-chapter this far into a chapter goes back to its start
+const RESTART_GRACE: Duration = Duration::from_secs(3);
+
+// Seeks land close to, not always exactly on, the requested position
+const SEEK_SLACK: Duration = Duration::from_millis(500);
+
+// Larger tags or boxes are not worth reading for their chapters
+const MAX_BLOCK_LEN: u64 = 64 * 1024 * 1024;
+
+/// A chapter marker, as read from MP4 chapters, ID3 CHAP frames or Matroska chapters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: String,
+}
+
+/// Chapters of the file at `path`, in playback order. Empty when it has none, or
+/// when its format carries none that impulse understands.
+pub fn read(path: &Path) -> Result<Vec<Chapter>> {
+    read_from(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_from<R: Read + Seek>(reader: &mut R) -> Result<Vec<Chapter>> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    reader.rewind()?;
+
+    let mut chapters = if magic.starts_with(b"ID3") {
+        id3::read(reader)?
+    } else if &magic[4..] == b"ftyp" {
+        mp4::read(reader)?
+    } else if magic.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        mkv::read(reader)?
+    } else {
+        Vec::new()
+    };
+
+    chapters.sort_by_key(|chapter| chapter.start);
+    chapters.dedup_by_key(|chapter| chapter.start);
+    for (index, chapter) in chapters.iter_mut().enumerate() {
+        if chapter.title.trim().is_empty() {
+            chapter.title = format!("Chapter {}", index + 1);
+        }
+    }
+    Ok(chapters)
+}
+
+/// Index of the chapter playing at `position`, `None` before the first one.
+pub fn index_at(chapters: &[Chapter], position: Duration) -> Option<usize> {
+    chapters
+        .iter()
+        .rposition(|chapter| chapter.start <= position + SEEK_SLACK)
+}
+
+/// Start of the chapter following the one at `position`.
+pub fn next_start(chapters: &[Chapter], position: Duration) -> Option<Duration> {
+    chapters
+        .iter()
+        .map(|chapter| chapter.start)
+        .find(|start| *start > position + SEEK_SLACK)
+}
+
+/// Start of the chapter at `position`, or of the one before when playback only just
+/// entered it.
+pub fn previous_start(chapters: &[Chapter], position: Duration) -> Option<Duration> {
+    let index = index_at(chapters, position)?;
+    let start = chapters[index].start;
+    if index == 0 || position.saturating_sub(start) > RESTART_GRACE {
+        Some(start)
+    } else {
+        Some(chapters[index - 1].start)
+    }
+}
+
+// Reads `len` bytes at the current position, refusing unreasonable lengths
+fn read_block<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    anyhow::ensure!(len <= MAX_BLOCK_LEN, "Block of {len} bytes is too large");
+    let mut data = vec![0; len as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// Skips `len` bytes, a size read from the file that may be anything
+fn skip<R: Seek>(reader: &mut R, len: u64) -> Result<()> {
+    let offset = i64::try_from(len).map_err(|_| anyhow::anyhow!("Cannot skip {len} bytes"))?;
+    reader.seek(SeekFrom::Current(offset))?;
+    Ok(())
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+// UTF-16 text with a byte order mark, big-endian without one
+fn decode_utf16(data: &[u8]) -> String {
+    let (data, little_endian) = match data {
+        [0xFF, 0xFE, rest @ ..] => (rest, true),
+        [0xFE, 0xFF, rest @ ..] => (rest, false),
+        _ => (data, false),
+    };
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| {
+            if little_endian {
+                u16::from_le_bytes([pair[0], pair[1]])
+            } else {
+                u16::from_be_bytes([pair[0], pair[1]])
+            }
+        })
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+mod id3 {
+    use super::{Chapter, decode_utf16, read_block, u32_at};
+    use anyhow::Result;
+    use std::io::Read;
+    use std::time::Duration;
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Vec<Chapter>> {
+        let mut header = [0u8; 10];
+        reader.read_exact(&mut header)?;
+        let version = header[3];
+        let flags = header[5];
+        // ID3v2.2 has no chapters
+        if !(3..=4).contains(&version) {
+            return Ok(Vec::new());
+        }
+
+        let mut tag = read_block(reader, u64::from(syncsafe(&header[6..10])))?;
+        if version == 3 && flags & 0x80 != 0 {
+            tag = resynchronise(&tag);
+        }
+        let mut start = 0;
+        if flags & 0x40 != 0 {
+            // Extended header, counted with its size field in v2.4 only
+            let size = u32_at(&tag, 0).unwrap_or(0);
+            start = if version == 4 {
+                syncsafe(&size.to_be_bytes()) as usize
+            } else {
+                size as usize + 4
+            };
+        }
+
+        let Some(frames) = tag.get(start..) else {
+            return Ok(Vec::new());
+        };
+        Ok(frames_of(frames, version)
+            .filter(|(id, _)| id == b"CHAP")
+            .filter_map(|(_, body)| chapter(body, version))
+            .collect())
+    }
+
+    fn syncsafe(bytes: &[u8]) -> u32 {
+        bytes
+            .iter()
+            .fold(0, |size, byte| (size << 7) | u32::from(byte & 0x7F))
+    }
+
+    // Undoes the 0xFF 0x00 escaping applied to a whole v2.3 tag
+    fn resynchronise(data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len());
+        for (i, &byte) in data.iter().enumerate() {
+            if byte == 0 && i > 0 && data[i - 1] == 0xFF {
+                continue;
+            }
+            output.push(byte);
+        }
+        output
+    }
+
+    fn frames_of(data: &[u8], version: u8) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            let id: [u8; 4] = data.get(pos..pos + 4)?.try_into().ok()?;
+            // Padding
+            if id[0] == 0 {
+                return None;
+            }
+            let size = data.get(pos + 4..pos + 8)?;
+            let size = if version == 4 {
+                syncsafe(size)
+            } else {
+                u32::from_be_bytes(size.try_into().ok()?)
+            } as usize;
+            let body = data.get(pos + 10..pos + 10 + size)?;
+            pos += 10 + size;
+            Some((id, body))
+        })
+    }
+
+    // Element ID, start and end times in ms, byte offsets, then sub-frames such as TIT2
+    fn chapter(body: &[u8], version: u8) -> Option<Chapter> {
+        let id_end = body.iter().position(|&byte| byte == 0)?;
+        let start_ms = u32_at(body, id_end + 1)?;
+        let title = body
+            .get(id_end + 17..)
+            .and_then(|frames| {
+                frames_of(frames, version)
+                    .find(|(id, _)| id == b"TIT2")
+                    .map(|(_, text)| decode_text(text))
+            })
+            .unwrap_or_default();
+        Some(Chapter {
+            start: Duration::from_millis(u64::from(start_ms)),
+            title,
+        })
+    }
+
+    fn decode_text(frame: &[u8]) -> String {
+        let Some((&encoding, text)) = frame.split_first() else {
+            return String::new();
+        };
+        let text = match encoding {
+            0 => text.iter().map(|&byte| char::from(byte)).collect(),
+            1 | 2 => decode_utf16(text),
+            _ => String::from_utf8_lossy(text).into_owned(),
+        };
+        text.trim_end_matches('\0').to_string()
+    }
+}
+
+mod mp4 {
+    use super::{Chapter, decode_utf16, read_block, skip, u16_at, u32_at, u64_at};
+    use anyhow::Result;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::time::Duration;
+
+    // Samples read from a chapter track at most, whatever its tables claim
+    const MAX_CHAPTERS: usize = 10_000;
+
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Vec<Chapter>> {
+        let Some(moov) = find_moov(reader)? else {
+            return Ok(Vec::new());
+        };
+
+        // Nero chapters are the simplest to read, and most chaptered files carry them
+        if let Some(chpl) = child(&moov, b"udta").and_then(|udta| child(udta, b"chpl")) {
+            let chapters = nero_chapters(chpl);
+            if !chapters.is_empty() {
+                return Ok(chapters);
+            }
+        }
+
+        // Otherwise a track referenced through `tref/chap` holds one text sample per chapter
+        let traks: Vec<&[u8]> = boxes(&moov)
+            .filter(|(kind, _)| kind == b"trak")
+            .map(|(_, body)| body)
+            .collect();
+        let Some(chapter_track) = traks
+            .iter()
+            .find_map(|trak| child(trak, b"tref").and_then(|tref| child(tref, b"chap")))
+            .and_then(|chap| u32_at(chap, 0))
+        else {
+            return Ok(Vec::new());
+        };
+        let Some(trak) = traks
+            .iter()
+            .find(|trak| track_id(trak) == Some(chapter_track))
+        else {
+            return Ok(Vec::new());
+        };
+        text_track_chapters(reader, trak)
+    }
+
+    // Walks the top-level boxes, skipping over media data, and reads the movie box
+    fn find_moov<R: Read + Seek>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+        loop {
+            let mut header = [0u8; 8];
+            if reader.read_exact(&mut header).is_err() {
+                return Ok(None);
+            }
+            let mut size = u64::from(u32::from_be_bytes(header[..4].try_into()?));
+            let mut header_len = 8;
+            if size == 1 {
+                let mut large = [0u8; 8];
+                reader.read_exact(&mut large)?;
+                size = u64::from_be_bytes(large);
+                header_len = 16;
+            }
+            if &header[4..] == b"moov" {
+                return Ok(Some(read_block(reader, size.saturating_sub(header_len))?));
+            }
+            if size < header_len {
+                return Ok(None);
+            }
+            skip(reader, size - header_len)?;
+        }
+    }
+
+    fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            let size = u32_at(data, pos)? as usize;
+            let kind: [u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+            let (header_len, size) = match size {
+                0 => (8, data.len() - pos),
+                1 => (16, usize::try_from(u64_at(data, pos + 8)?).ok()?),
+                size => (8, size),
+            };
+            let body = data.get(pos + header_len..pos.checked_add(size)?)?;
+            pos += size.max(header_len);
+            Some((kind, body))
+        })
+    }
+
+    fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+        boxes(data).find(|(k, _)| k == kind).map(|(_, body)| body)
+    }
+
+    fn path<'a>(data: &'a [u8], kinds: &[&[u8; 4]]) -> Option<&'a [u8]> {
+        kinds.iter().try_fold(data, |data, kind| child(data, kind))
+    }
+
+    // Version and flags, a reserved word in version 1, the count, then for each chapter
+    // its start in 100 ns units and a length-prefixed title
+    fn nero_chapters(chpl: &[u8]) -> Vec<Chapter> {
+        let mut pos = if chpl.first() == Some(&1) { 8 } else { 4 };
+        let Some(&count) = chpl.get(pos) else {
+            return Vec::new();
+        };
+        pos += 1;
+
+        let mut chapters = Vec::new();
+        for _ in 0..count {
+            let Some(start) = u64_at(chpl, pos) else {
+                break;
+            };
+            let Some(&len) = chpl.get(pos + 8) else {
+                break;
+            };
+            let Some(title) = chpl.get(pos + 9..pos + 9 + len as usize) else {
+                break;
+            };
+            chapters.push(Chapter {
+                start: Duration::from_nanos(start.saturating_mul(100)),
+                title: String::from_utf8_lossy(title).into_owned(),
+            });
+            pos += 9 + len as usize;
+        }
+        chapters
+    }
+
+    fn track_id(trak: &[u8]) -> Option<u32> {
+        let tkhd = child(trak, b"tkhd")?;
+        u32_at(tkhd, if tkhd.first() == Some(&1) { 20 } else { 12 })
+    }
+
+    fn text_track_chapters<R: Read + Seek>(reader: &mut R, trak: &[u8]) -> Result<Vec<Chapter>> {
+        let Some(samples) = text_samples(trak) else {
+            return Ok(Vec::new());
+        };
+
+        let mut chapters = Vec::new();
+        for (start, offset, size) in samples {
+            reader.seek(SeekFrom::Start(offset))?;
+            let sample = read_block(reader, u64::from(size))?;
+            // A 16-bit length, then UTF-8 or BOM-prefixed UTF-16 text
+            let len = u16_at(&sample, 0).unwrap_or(0) as usize;
+            let text = sample.get(2..2 + len).unwrap_or_default();
+            let title = if text.starts_with(&[0xFE, 0xFF]) || text.starts_with(&[0xFF, 0xFE]) {
+                decode_utf16(text)
+            } else {
+                String::from_utf8_lossy(text).into_owned()
+            };
+            chapters.push(Chapter { start, title });
+        }
+        Ok(chapters)
+    }
+
+    // Start time, file offset and size of every sample, from the sample tables
+    fn text_samples(trak: &[u8]) -> Option<Vec<(Duration, u64, u32)>> {
+        let mdhd = path(trak, &[b"mdia", b"mdhd"])?;
+        let timescale = u32_at(mdhd, if mdhd.first() == Some(&1) { 20 } else { 12 })?;
+        let stbl = path(trak, &[b"mdia", b"minf", b"stbl"])?;
+        if timescale == 0 {
+            return None;
+        }
+
+        let sizes = child(stbl, b"stsz")?;
+        let fixed_size = u32_at(sizes, 4)?;
+        let sample_count = (u32_at(sizes, 8)? as usize).min(MAX_CHAPTERS);
+
+        // Durations, as runs of samples sharing one. The runs may claim any number of
+        // samples, so no more starts are taken than there are samples.
+        let stts = child(stbl, b"stts")?;
+        let mut starts = Vec::new();
+        let mut time = 0u64;
+        for entry in 0..u32_at(stts, 4)? as usize {
+            let count = u32_at(stts, 8 + entry * 8)? as usize;
+            let delta = u64::from(u32_at(stts, 12 + entry * 8)?);
+            for _ in 0..count.min(sample_count - starts.len()) {
+                starts.push(time);
+                time = time.saturating_add(delta);
+            }
+        }
+        let size_of = |sample: usize| match fixed_size {
+            0 => u32_at(sizes, 12 + sample * 4),
+            size => Some(size),
+        };
+
+        let chunk_offsets: Vec<u64> = if let Some(stco) = child(stbl, b"stco") {
+            (0..u32_at(stco, 4)? as usize)
+                .map(|chunk| u32_at(stco, 8 + chunk * 4).map(u64::from))
+                .collect::<Option<_>>()?
+        } else {
+            let co64 = child(stbl, b"co64")?;
+            (0..u32_at(co64, 4)? as usize)
+                .map(|chunk| u64_at(co64, 8 + chunk * 8))
+                .collect::<Option<_>>()?
+        };
+
+        // Runs of chunks holding the same number of samples, by first chunk (from 1)
+        let chunk_runs = child(stbl, b"stsc")?;
+        let runs: Vec<(usize, u32)> = (0..u32_at(chunk_runs, 4)? as usize)
+            .map(|entry| {
+                Some((
+                    u32_at(chunk_runs, 8 + entry * 12)? as usize,
+                    u32_at(chunk_runs, 12 + entry * 12)?,
+                ))
+            })
+            .collect::<Option<_>>()?;
+
+        let mut samples = Vec::new();
+        for (chunk, &chunk_offset) in chunk_offsets.iter().enumerate() {
+            let per_chunk = runs
+                .iter()
+                .rev()
+                .find(|(first, _)| *first <= chunk + 1)
+                .map_or(0, |(_, count)| *count);
+            let mut offset = chunk_offset;
+            for _ in 0..per_chunk {
+                let sample = samples.len();
+                if sample >= sample_count.min(starts.len()) {
+                    return Some(samples);
+                }
+                let size = size_of(sample)?;
+                let secs = starts[sample] as f64 / f64::from(timescale);
+                let Ok(start) = Duration::try_from_secs_f64(secs) else {
+                    return Some(samples);
+                };
+                samples.push((start, offset, size));
+                offset = offset.saturating_add(u64::from(size));
+            }
+        }
+        Some(samples)
+    }
+}
+
+mod mkv {
+    use super::{Chapter, read_block, skip};
+    use anyhow::Result;
+    use std::io::{Read, Seek};
+    use std::time::Duration;
+
+    const SEGMENT: u32 = 0x1853_8067;
+    const CHAPTERS: u32 = 0x1043_A770;
+    const EDITION_ENTRY: u32 = 0x45B9;
+    const CHAPTER_ATOM: u32 = 0xB6;
+    const CHAPTER_TIME_START: u32 = 0x91;
+    const CHAPTER_FLAG_HIDDEN: u32 = 0x98;
+    const CHAPTER_DISPLAY: u32 = 0x80;
+    const CHAP_STRING: u32 = 0x85;
+
+    // Sizes with every value bit set mean the length is not known up front
+    const UNKNOWN_SIZE: u64 = u64::MAX;
+
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Vec<Chapter>> {
+        // EBML header, then the segment holding everything else
+        let (_, header_size) = read_header(reader)?;
+        skip(reader, header_size)?;
+        let (id, _) = read_header(reader)?;
+        if id != SEGMENT {
+            return Ok(Vec::new());
+        }
+
+        // Top-level elements of the segment, until the chapters or the end of the file
+        while let Ok((id, size)) = read_header(reader) {
+            if id == CHAPTERS {
+                return Ok(chapters(&read_block(reader, size)?));
+            }
+            // Clusters of unknown size, as written by live encoders, cannot be skipped
+            if size == UNKNOWN_SIZE {
+                break;
+            }
+            skip(reader, size)?;
+        }
+        Ok(Vec::new())
+    }
+
+    fn read_header<R: Read>(reader: &mut R) -> Result<(u32, u64)> {
+        let (id, _) = read_vint(reader)?;
+        let (size, len) = read_vint(reader)?;
+        let size = size & !(1 << (7 * len));
+        let all_ones = (1 << (7 * len)) - 1;
+        Ok((
+            id as u32,
+            if size == all_ones { UNKNOWN_SIZE } else { size },
+        ))
+    }
+
+    // Raw value with its length marker, and the number of bytes it took
+    fn read_vint<R: Read>(reader: &mut R) -> Result<(u64, u32)> {
+        let mut first = [0u8; 1];
+        reader.read_exact(&mut first)?;
+        let len = first[0].leading_zeros() + 1;
+        anyhow::ensure!(len <= 8, "Invalid EBML variable-length integer");
+        let mut value = u64::from(first[0]);
+        for _ in 1..len {
+            reader.read_exact(&mut first)?;
+            value = (value << 8) | u64::from(first[0]);
+        }
+        Ok((value, len))
+    }
+
+    fn elements(data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+        let mut reader = data;
+        std::iter::from_fn(move || {
+            let (id, size) = read_header(&mut reader).ok()?;
+            let size = usize::try_from(size).ok()?.min(reader.len());
+            let (body, rest) = reader.split_at(size);
+            reader = rest;
+            Some((id, body))
+        })
+    }
+
+    fn uint(data: &[u8]) -> u64 {
+        data.iter()
+            .fold(0, |value, &byte| (value << 8) | u64::from(byte))
+    }
+
+    // Visible top-level atoms of the first edition; nested ones are sub-chapters
+    fn chapters(data: &[u8]) -> Vec<Chapter> {
+        let Some((_, edition)) = elements(data).find(|(id, _)| *id == EDITION_ENTRY) else {
+            return Vec::new();
+        };
+        elements(edition)
+            .filter(|(id, _)| *id == CHAPTER_ATOM)
+            .filter_map(|(_, atom)| {
+                let mut start = None;
+                let mut title = String::new();
+                for (id, body) in elements(atom) {
+                    match id {
+                        CHAPTER_TIME_START => start = Some(Duration::from_nanos(uint(body))),
+                        CHAPTER_FLAG_HIDDEN if uint(body) == 1 => return None,
+                        CHAPTER_DISPLAY if title.is_empty() => {
+                            if let Some((_, text)) =
+                                elements(body).find(|(id, _)| *id == CHAP_STRING)
+                            {
+                                title = String::from_utf8_lossy(text)
+                                    .trim_end_matches('\0')
+                                    .to_string();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Chapter {
+                    start: start?,
+                    title,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chapter(secs: u64, title: &str) -> Chapter {
+        Chapter {
+            start: Duration::from_secs(secs),
+            title: title.to_string(),
+        }
+    }
+
+    fn id3_frame(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn id3_chap(id: &str, start_ms: u32, title: &str) -> Vec<u8> {
+        let mut body = id.as_bytes().to_vec();
+        body.push(0);
+        body.extend_from_slice(&start_ms.to_be_bytes());
+        body.extend_from_slice(&(start_ms + 1000).to_be_bytes());
+        body.extend_from_slice(&[0xFF; 8]);
+        let mut text = vec![3];
+        text.extend_from_slice(title.as_bytes());
+        body.extend(id3_frame(b"TIT2", &text));
+        id3_frame(b"CHAP", &body)
+    }
+
+    fn mp4_box(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn ebml(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        // Eight-byte sizes keep the helper simple
+        data.push(0x01);
+        data.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn test_reads_id3_chapters() {
+        let mut frames = id3_frame(b"TIT2", b"\x03Book");
+        frames.extend(id3_chap("ch1", 754_000, "Second"));
+        frames.extend(id3_chap("ch0", 0, "First"));
+        frames.extend([0; 16]);
+
+        let mut file = b"ID3\x03\x00\x00".to_vec();
+        let size = frames.len() as u32;
+        file.extend((0..4).rev().map(|i| ((size >> (7 * i)) & 0x7F) as u8));
+        file.extend(frames);
+        file.extend([0xFF, 0xFB, 0x90, 0x00]);
+
+        let chapters = read_from(&mut Cursor::new(file)).unwrap();
+        assert_eq!(chapters, vec![chapter(0, "First"), chapter(754, "Second")]);
+    }
+
+    #[test]
+    fn test_reads_nero_mp4_chapters() {
+        let mut chpl = vec![1, 0, 0, 0, 0, 0, 0, 0, 2];
+        for (start, title) in [(0u64, "Intro"), (90, "")] {
+            chpl.extend_from_slice(&(start * 10_000_000).to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend_from_slice(title.as_bytes());
+        }
+        let mut file = mp4_box(b"ftyp", b"M4B \x00\x00\x00\x00");
+        file.extend(mp4_box(b"mdat", &[0; 64]));
+        file.extend(mp4_box(
+            b"moov",
+            &mp4_box(b"udta", &mp4_box(b"chpl", &chpl)),
+        ));
+
+        let chapters = read_from(&mut Cursor::new(file)).unwrap();
+        assert_eq!(
+            chapters,
+            vec![chapter(0, "Intro"), chapter(90, "Chapter 2")]
+        );
+    }
+
+    // A file with a text track of two samples, timed by the `stts` table in `durations`
+    fn quicktime_file(durations: &[u32]) -> Vec<u8> {
+        let full = |version_flags: u32, rest: &[u8]| {
+            let mut body = version_flags.to_be_bytes().to_vec();
+            body.extend_from_slice(rest);
+            body
+        };
+        let words = |values: &[u32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect()
+        };
+
+        // Two text samples of one chunk, placed right after the file type box
+        let mut samples = Vec::new();
+        for title in ["Opening", "Part Two"] {
+            samples.extend_from_slice(&(title.len() as u16).to_be_bytes());
+            samples.extend_from_slice(title.as_bytes());
+        }
+        let ftyp = mp4_box(b"ftyp", b"M4A \x00\x00\x00\x00");
+        let mdat = mp4_box(b"mdat", &samples);
+        let offset = (ftyp.len() + 8) as u32;
+
+        let stbl = [
+            mp4_box(b"stts", &full(0, &words(durations))),
+            mp4_box(b"stsz", &full(0, &words(&[0, 2, 9, 10]))),
+            mp4_box(b"stsc", &full(0, &words(&[1, 1, 2, 1]))),
+            mp4_box(b"stco", &full(0, &words(&[1, offset]))),
+        ]
+        .concat();
+        let text_trak = [
+            mp4_box(b"tkhd", &full(0, &words(&[0, 0, 2]))),
+            mp4_box(
+                b"mdia",
+                &[
+                    mp4_box(b"mdhd", &full(0, &words(&[0, 0, 60]))),
+                    mp4_box(b"minf", &mp4_box(b"stbl", &stbl)),
+                ]
+                .concat(),
+            ),
+        ]
+        .concat();
+        let audio_trak = [
+            mp4_box(b"tkhd", &full(0, &words(&[0, 0, 1]))),
+            mp4_box(b"tref", &mp4_box(b"chap", &words(&[2]))),
+        ]
+        .concat();
+        let moov = [mp4_box(b"trak", &audio_trak), mp4_box(b"trak", &text_trak)].concat();
+
+        [ftyp, mdat, mp4_box(b"moov", &moov)].concat()
+    }
+
+    #[test]
+    fn test_reads_quicktime_chapter_track() {
+        let file = quicktime_file(&[2, 1, 600, 1, 600]);
+        let chapters = read_from(&mut Cursor::new(file)).unwrap();
+        assert_eq!(
+            chapters,
+            vec![chapter(0, "Opening"), chapter(10, "Part Two")]
+        );
+    }
+
+    #[test]
+    fn test_damaged_mp4_sizes_are_bounded() {
+        // One run claiming far more samples than the track has
+        let file = quicktime_file(&[1, u32::MAX, 600]);
+        let chapters = read_from(&mut Cursor::new(file)).unwrap();
+        assert_eq!(
+            chapters,
+            vec![chapter(0, "Opening"), chapter(10, "Part Two")]
+        );
+
+        // A 64-bit box size too large to seek over
+        let mut file = mp4_box(b"ftyp", b"M4A \x00\x00\x00\x00");
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(b"mdat");
+        file.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(read_from(&mut Cursor::new(file)).is_err());
+    }
+
+    #[test]
+    fn test_reads_matroska_chapters() {
+        let atom = |start_secs: u64, title: &str, hidden: bool| {
+            let mut body = ebml(&[0x73, 0xC4], &[1]);
+            body.extend(ebml(&[0x91], &(start_secs * 1_000_000_000).to_be_bytes()));
+            if hidden {
+                body.extend(ebml(&[0x98], &[1]));
+            }
+            body.extend(ebml(&[0x80], &ebml(&[0x85], title.as_bytes())));
+            ebml(&[0xB6], &body)
+        };
+        let edition = [
+            atom(0, "Side A", false),
+            atom(30, "Hidden", true),
+            atom(1200, "Side B", false),
+        ]
+        .concat();
+
+        let mut segment = ebml(&[0x15, 0x49, 0xA9, 0x66], &[0; 8]);
+        segment.extend(ebml(&[0x1F, 0x43, 0xB6, 0x75], &[0; 32]));
+        segment.extend(ebml(
+            &[0x10, 0x43, 0xA7, 0x70],
+            &ebml(&[0x45, 0xB9], &edition),
+        ));
+        let mut file = ebml(&[0x1A, 0x45, 0xDF, 0xA3], &ebml(&[0x42, 0x82], b"matroska"));
+        file.extend(ebml(&[0x18, 0x53, 0x80, 0x67], &segment));
+
+        let chapters = read_from(&mut Cursor::new(file)).unwrap();
+        assert_eq!(
+            chapters,
+            vec![chapter(0, "Side A"), chapter(1200, "Side B")]
+        );
+    }
+
+    #[test]
+    fn test_chapter_navigation() {
+        let chapters = vec![chapter(0, "One"), chapter(60, "Two"), chapter(120, "Three")];
+        let at = Duration::from_secs;
+
+        assert_eq!(index_at(&chapters, at(75)), Some(1));
+        assert_eq!(next_start(&chapters, at(75)), Some(at(120)));
+        assert_eq!(next_start(&chapters, at(130)), None);
+        // Landing just short of a chapter still counts as being in it
+        assert_eq!(
+            next_start(&chapters, Duration::from_millis(59_900)),
+            Some(at(120))
+        );
+
+        assert_eq!(previous_start(&chapters, at(75)), Some(at(60)));
+        assert_eq!(previous_start(&chapters, at(61)), Some(at(0)));
+        assert_eq!(previous_start(&chapters, at(1)), Some(at(0)));
+        assert_eq!(index_at(&[chapter(10, "Late")], at(2)), None);
+    }
+
+    #[test]
+    fn test_other_files_have_no_chapters() {
+        let file = b"RIFF\x24\x00\x00\x00WAVEfmt ".to_vec();
+        assert!(read_from(&mut Cursor::new(file)).unwrap().is_empty());
+    }
+}
@@ -1,5 +1,13 @@
+use crate::dynamics::DynamicsPreset;
+use crate::equalizer::{self, Bands};
+use crate::output::OutputBackend;
+use crate::player::{
+    DEFAULT_DECODE_ERROR_LIMIT, FadeCurve, MAX_CROSSFADE_SECS, MAX_PREAMP_DB, ReplayGainMode,
+};
+use crate::sleep;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
 
@@ -15,6 +23,32 @@ pub struct Config {
     pub lastfm: Option<LastfmConfig>,
     #[serde(default = "default_log_file")]
     pub log_file: PathBuf,
+    #[serde(default)]
+    pub crossfade_secs: f32,
+    #[serde(default)]
+    pub crossfade_curve: FadeCurve,
+    #[serde(default)]
+    pub replaygain: ReplayGainMode,
+    #[serde(default)]
+    pub replaygain_preamp: f32,
+    #[serde(default)]
+    pub output: OutputBackend,
+    #[serde(default = "equalizer::default_presets")]
+    pub eq_presets: BTreeMap<String, Bands>,
+    #[serde(default)]
+    pub dynamics: DynamicsPreset,
+    #[serde(default)]
+    pub balance: f32,
+    #[serde(default)]
+    pub mono: bool,
+    #[serde(default = "default_decode_error_limit")]
+    pub decode_error_limit: u32,
+    #[serde(default = "default_sleep_fade_secs")]
+    pub sleep_fade_secs: f32,
+    #[serde(default = "default_resume_min_secs")]
+    pub resume_min_secs: u64,
+    #[serde(default)]
+    pub resume_dirs: Vec<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -49,6 +83,18 @@ fn default_volume() -> f32 {
     0.5
 }
 
+fn default_decode_error_limit() -> u32 {
+    DEFAULT_DECODE_ERROR_LIMIT
+}
+
+fn default_sleep_fade_secs() -> f32 {
+    30.0
+}
+
+fn default_resume_min_secs() -> u64 {
+    20 * 60
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -57,6 +103,19 @@ impl Default for Config {
             volume: default_volume(),
             lastfm: None,
             log_file: default_log_file(),
+            crossfade_secs: 0.0,
+            crossfade_curve: FadeCurve::default(),
+            replaygain: ReplayGainMode::default(),
+            replaygain_preamp: 0.0,
+            output: OutputBackend::default(),
+            eq_presets: equalizer::default_presets(),
+            dynamics: DynamicsPreset::default(),
+            balance: 0.0,
+            mono: false,
+            decode_error_limit: default_decode_error_limit(),
+            sleep_fade_secs: default_sleep_fade_secs(),
+            resume_min_secs: default_resume_min_secs(),
+            resume_dirs: Vec::new(),
         }
     }
 }
@@ -67,8 +126,7 @@ impl Config {
 
         if config_path.exists() {
             let content = fs::read_to_string(&config_path)?;
-            let config: Config = toml::from_str(&content)?;
-            Ok(config)
+            Self::parse(&content)
         } else {
             let mut config = Config::default();
             config.save()?;
@@ -114,6 +172,34 @@ impl Config {
         Ok(())
     }
 
+    // Keeps hand-edited values in the range the player works with
+    fn parse(content: &str) -> Result<Self> {
+        let mut config: Config = toml::from_str(content)?;
+        config.crossfade_secs = if config.crossfade_secs.is_nan() {
+            0.0
+        } else {
+            config.crossfade_secs.clamp(0.0, MAX_CROSSFADE_SECS)
+        };
+        config.sleep_fade_secs = if config.sleep_fade_secs.is_nan() {
+            default_sleep_fade_secs()
+        } else {
+            config.sleep_fade_secs.clamp(0.0, sleep::MAX_FADE_SECS)
+        };
+        config.replaygain_preamp = if config.replaygain_preamp.is_nan() {
+            0.0
+        } else {
+            config
+                .replaygain_preamp
+                .clamp(-MAX_PREAMP_DB, MAX_PREAMP_DB)
+        };
+        config.balance = if config.balance.is_nan() {
+            0.0
+        } else {
+            config.balance.clamp(-1.0, 1.0)
+        };
+        Ok(config)
+    }
+
     fn config_path() -> PathBuf {
         dirs::config_dir()
             .unwrap_or_else(|| PathBuf::from("."))
@@ -144,6 +230,19 @@ mod tests {
             volume: 0.8,
             lastfm: None,
             log_file: PathBuf::from("/test/impulse.log"),
+            crossfade_secs: 0.0,
+            crossfade_curve: FadeCurve::default(),
+            replaygain: ReplayGainMode::default(),
+            replaygain_preamp: 0.0,
+            output: OutputBackend::default(),
+            eq_presets: BTreeMap::new(),
+            dynamics: DynamicsPreset::default(),
+            balance: 0.0,
+            mono: false,
+            decode_error_limit: 10,
+            sleep_fade_secs: 30.0,
+            resume_min_secs: 1200,
+            resume_dirs: Vec::new(),
         };
 
         let toml_string = toml::to_string(&config).unwrap();
@@ -170,6 +269,19 @@ mod tests {
             volume: 0.7,
             lastfm: Some(lastfm_config.clone()),
             log_file: PathBuf::from("/test/impulse.log"),
+            crossfade_secs: 0.0,
+            crossfade_curve: FadeCurve::default(),
+            replaygain: ReplayGainMode::default(),
+            replaygain_preamp: 0.0,
+            output: OutputBackend::default(),
+            eq_presets: BTreeMap::new(),
+            dynamics: DynamicsPreset::default(),
+            balance: 0.0,
+            mono: false,
+            decode_error_limit: 10,
+            sleep_fade_secs: 30.0,
+            resume_min_secs: 1200,
+            resume_dirs: Vec::new(),
         };
 
         let toml_string = toml::to_string(&config).unwrap();
@@ -193,6 +305,135 @@ mod tests {
         assert_eq!(config.music_dir, PathBuf::from("/custom/music"));
         assert_eq!(config.volume, 0.5); // default
         assert!(!config.playlist_dir.as_os_str().is_empty()); // default
+        assert_eq!(config.crossfade_secs, 0.0); // default
+    }
+
+    #[test]
+    fn test_config_crossfade() {
+        let toml_string = r#"
+            crossfade_secs = 4.5
+            crossfade_curve = "s-curve"
+        "#;
+
+        let config: Config = toml::from_str(toml_string).unwrap();
+        assert_eq!(config.crossfade_secs, 4.5);
+        assert_eq!(config.crossfade_curve, FadeCurve::SCurve);
+
+        let config = Config::parse("crossfade_secs = inf").unwrap();
+        assert_eq!(config.crossfade_secs, MAX_CROSSFADE_SECS);
+        let config = Config::parse("crossfade_secs = nan").unwrap();
+        assert_eq!(config.crossfade_secs, 0.0);
+    }
+
+    #[test]
+    fn test_config_replaygain() {
+        let toml_string = r#"
+            replaygain = "album"
+            replaygain_preamp = -2.5
+        "#;
+
+        let config: Config = toml::from_str(toml_string).unwrap();
+        assert_eq!(config.replaygain, ReplayGainMode::Album);
+        assert_eq!(config.replaygain_preamp, -2.5);
+
+        let config = Config::parse("replaygain_preamp = -40.0").unwrap();
+        assert_eq!(config.replaygain_preamp, -MAX_PREAMP_DB);
+        let config = Config::parse("replaygain_preamp = nan").unwrap();
+        assert_eq!(config.replaygain_preamp, 0.0);
+        assert_eq!(Config::default().replaygain, ReplayGainMode::Off);
+    }
+
+    #[test]
+    fn test_config_dynamics() {
+        let config: Config = toml::from_str(r#"dynamics = "night""#).unwrap();
+        assert_eq!(config.dynamics, DynamicsPreset::Night);
+        assert_eq!(Config::default().dynamics, DynamicsPreset::Off);
+    }
+
+    #[test]
+    fn test_config_channels() {
+        let toml_string = "
+            balance = -0.25
+            mono = true
+        ";
+
+        let config: Config = toml::from_str(toml_string).unwrap();
+        assert_eq!(config.balance, -0.25);
+        assert!(config.mono);
+        assert!(!Config::default().mono);
+
+        let config = Config::parse("balance = 3.0").unwrap();
+        assert_eq!(config.balance, 1.0);
+        let config = Config::parse("balance = nan").unwrap();
+        assert_eq!(config.balance, 0.0);
+    }
+
+    #[test]
+    fn test_config_decode_error_limit() {
+        let config: Config = toml::from_str("decode_error_limit = 3").unwrap();
+        assert_eq!(config.decode_error_limit, 3);
+        assert_eq!(
+            Config::default().decode_error_limit,
+            DEFAULT_DECODE_ERROR_LIMIT
+        );
+    }
+
+    #[test]
+    fn test_config_sleep_fade() {
+        let config: Config = toml::from_str("sleep_fade_secs = 60.0").unwrap();
+        assert_eq!(config.sleep_fade_secs, 60.0);
+        assert_eq!(Config::default().sleep_fade_secs, 30.0);
+
+        let config = Config::parse("sleep_fade_secs = inf").unwrap();
+        assert_eq!(config.sleep_fade_secs, sleep::MAX_FADE_SECS);
+        let config = Config::parse("sleep_fade_secs = -5.0").unwrap();
+        assert_eq!(config.sleep_fade_secs, 0.0);
+    }
+
+    #[test]
+    fn test_config_resume() {
+        let toml_string = r#"
+            resume_min_secs = 0
+            resume_dirs = ["/music/Audiobooks", "/music/Podcasts"]
+        "#;
+
+        let config: Config = toml::from_str(toml_string).unwrap();
+        assert_eq!(config.resume_min_secs, 0);
+        assert_eq!(config.resume_dirs.len(), 2);
+        assert_eq!(Config::default().resume_min_secs, 1200);
+        assert!(Config::default().resume_dirs.is_empty());
+    }
+
+    #[test]
+    fn test_config_eq_presets() {
+        let toml_string = r"
+            [eq_presets]
+            mine = [1.0, 2.0, 3.0, 0.0, 0.0, 0.0, 0.0, -3.0, -2.0, -1.0]
+        ";
+
+        let config: Config = toml::from_str(toml_string).unwrap();
+        assert_eq!(config.eq_presets.len(), 1);
+        assert_eq!(config.eq_presets["mine"][2], 3.0);
+
+        // Without the table, the built-in presets are used
+        let config: Config = toml::from_str("volume = 0.5").unwrap();
+        assert!(config.eq_presets.contains_key("flat"));
+
+        // Presets must round-trip, since a default config is written on first start
+        let toml_string = toml::to_string_pretty(&Config::default()).unwrap();
+        let config: Config = toml::from_str(&toml_string).unwrap();
+        assert_eq!(config.eq_presets, equalizer::default_presets());
+    }
+
+    #[test]
+    fn test_config_output() {
+        let config: Config = toml::from_str(r#"output = "wav:/tmp/capture.wav""#).unwrap();
+        assert_eq!(
+            config.output,
+            OutputBackend::Wav(PathBuf::from("/tmp/capture.wav"))
+        );
+        assert_eq!(Config::default().output, OutputBackend::Device);
+        assert!(toml::from_str::<Config>(r#"output = "speakers""#).is_err());
     }
 
     #[test]
@@ -0,0 +1,398 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::browser;
+use crate::radio;
+
+/// CUE timestamps count in CD frames, 75 per second.
+pub const FRAMES_PER_SECOND: u64 = 75;
+
+// Parsed sheets, keyed by path and refreshed when the file changes. The browser and the
+// queue ask for track names on every redraw.
+static SHEETS: Mutex<Option<HashMap<PathBuf, CachedSheet>>> = Mutex::new(None);
+
+type CachedSheet = (SystemTime, Arc<CueSheet>);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+/// One track of a sheet: a span of the audio file between two INDEX 01 entries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub file: PathBuf,
+    /// Offset of INDEX 01 in CD frames
+    pub start: u64,
+    /// Where the next track in the same file starts, `None` for the last one
+    pub end: Option<u64>,
+}
+
+impl CueTrack {
+    /// Start of the track in frames at `sample_rate`.
+    pub fn start_frame(&self, sample_rate: u32) -> u64 {
+        self.start * u64::from(sample_rate) / FRAMES_PER_SECOND
+    }
+
+    pub fn end_frame(&self, sample_rate: u32) -> Option<u64> {
+        self.end
+            .map(|end| end * u64::from(sample_rate) / FRAMES_PER_SECOND)
+    }
+
+    pub fn start_time(&self) -> Duration {
+        Duration::from_millis(self.start * 1000 / FRAMES_PER_SECOND)
+    }
+
+    pub fn length(&self) -> Option<Duration> {
+        self.end.map(|end| {
+            Duration::from_millis(end.saturating_sub(self.start) * 1000 / FRAMES_PER_SECOND)
+        })
+    }
+
+    /// Name shown in the browser and the queue, such as "03. Title".
+    pub fn label(&self) -> String {
+        match &self.title {
+            Some(title) => format!("{:02}. {title}", self.number),
+            None => format!("{:02}. Track {}", self.number, self.number),
+        }
+    }
+}
+
+impl CueSheet {
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Failed to read CUE sheet: {}", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        Ok(Self::parse(&decode_text(&bytes), dir))
+    }
+
+    /// Parses the sheet, resolving FILE entries relative to `dir`. Unknown commands and
+    /// tracks without an INDEX 01 are skipped.
+    pub fn parse(content: &str, dir: &Path) -> Self {
+        let mut sheet = CueSheet::default();
+        let mut file: Option<PathBuf> = None;
+        let mut track: Option<CueTrack> = None;
+
+        for line in content.lines() {
+            let args = split_args(line);
+            let Some((command, args)) = args.split_first() else {
+                continue;
+            };
+            let arg = args.first().cloned();
+
+            match command.to_ascii_uppercase().as_str() {
+                "FILE" => {
+                    sheet.push_track(track.take());
+                    file = arg.map(|name| resolve_file(dir, &name));
+                }
+                "TRACK" => {
+                    sheet.push_track(track.take());
+                    let is_audio = args
+                        .get(1)
+                        .is_none_or(|kind| kind.eq_ignore_ascii_case("AUDIO"));
+                    track = match (&file, arg.and_then(|n| n.parse().ok())) {
+                        (Some(file), Some(number)) if is_audio => Some(CueTrack {
+                            number,
+                            title: None,
+                            performer: None,
+                            file: file.clone(),
+                            start: u64::MAX,
+                            end: None,
+                        }),
+                        _ => None,
+                    };
+                }
+                "TITLE" => match &mut track {
+                    Some(track) => track.title = arg,
+                    None => sheet.title = arg,
+                },
+                "PERFORMER" => match &mut track {
+                    Some(track) => track.performer = arg,
+                    None => sheet.performer = arg,
+                },
+                "INDEX" => {
+                    if let (Some(track), Some("01"), Some(time)) = (
+                        &mut track,
+                        arg.as_deref(),
+                        args.get(1).and_then(|t| parse_time(t)),
+                    ) {
+                        track.start = time;
+                    }
+                }
+                "REM" => match (arg.as_deref(), args.get(1)) {
+                    (Some("DATE"), Some(value)) => sheet.date = Some(value.clone()),
+                    (Some("GENRE"), Some(value)) => sheet.genre = Some(value.clone()),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        sheet.push_track(track);
+
+        // Each track runs until the next one in the same file starts
+        for i in 1..sheet.tracks.len() {
+            let (before, after) = sheet.tracks.split_at_mut(i);
+            let previous = &mut before[i - 1];
+            if previous.file == after[0].file && after[0].start > previous.start {
+                previous.end = Some(after[0].start);
+            }
+        }
+
+        sheet
+    }
+
+    fn push_track(&mut self, track: Option<CueTrack>) {
+        if let Some(track) = track.filter(|t| t.start != u64::MAX) {
+            self.tracks.push(track);
+        }
+    }
+
+    pub fn track(&self, number: u32) -> Option<&CueTrack> {
+        self.tracks.iter().find(|t| t.number == number)
+    }
+
+    /// Audio files the sheet splits up.
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        let mut files: Vec<&Path> = self.tracks.iter().map(|t| t.file.as_path()).collect();
+        files.dedup();
+        files.into_iter()
+    }
+}
+
+pub fn is_cue_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"))
+}
+
+/// Path standing for track `number` of the sheet at `cue`, such as
+/// `Album.cue/track0003`. Queues and playlists store these like regular files.
+pub fn track_path(cue: &Path, number: u32) -> PathBuf {
+    cue.join(format!("track{number:04}"))
+}
+
+/// Splits a path made by `track_path` back into the sheet and the track number.
+pub fn split_track_path(path: &Path) -> Option<(&Path, u32)> {
+    let cue = path.parent().filter(|parent| is_cue_file(parent))?;
+    let number = path
+        .file_name()?
+        .to_str()?
+        .strip_prefix("track")?
+        .parse()
+        .ok()?;
+    Some((cue, number))
+}
+
+/// Loads the sheet at `path`, reusing the parsed copy while the file is unchanged.
+pub fn sheet(path: &Path) -> Option<Arc<CueSheet>> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+
+    let mut sheets = SHEETS.lock().unwrap();
+    let sheets = sheets.get_or_insert_with(HashMap::new);
+    if let Some((time, sheet)) = sheets.get(path) {
+        if *time == modified {
+            return Some(Arc::clone(sheet));
+        }
+    }
+
+    let sheet = Arc::new(CueSheet::load(path).ok()?);
+    sheets.insert(path.to_path_buf(), (modified, Arc::clone(&sheet)));
+    Some(sheet)
+}
+
+/// Looks up the sheet and track a `track_path` refers to.
+pub fn resolve(path: &Path) -> Option<(Arc<CueSheet>, CueTrack)> {
+    let (cue, number) = split_track_path(path)?;
+    let sheet = sheet(cue)?;
+    let track = sheet.track(number)?.clone();
+    Some((sheet, track))
+}
+
+/// Name to show for a track: its CUE label for virtual tracks, the URL for streams,
+/// the file name otherwise.
+pub fn display_name(path: &Path) -> String {
+    if radio::is_url(path) {
+        return path.to_string_lossy().into_owned();
+    }
+    if let Some((_, track)) = resolve(path) {
+        return track.label();
+    }
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+        .to_string()
+}
+
+// Sheets ripped on Windows are often Latin-1 rather than UTF-8
+fn decode_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+// Splits a line into whitespace separated words, keeping quoted strings together
+fn split_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            args.push(chars.by_ref().take_while(|&c| c != '"').collect());
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            args.push(word);
+        }
+    }
+    args
+}
+
+// "mm:ss:ff" to CD frames
+fn parse_time(value: &str) -> Option<u64> {
+    let mut parts = value.split(':').map(|p| p.parse::<u64>().ok());
+    let (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    Some((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames)
+}
+
+// Sheets often still name the WAV they were ripped to after the image was compressed,
+// so fall back to an audio file with the same stem
+fn resolve_file(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if path.exists() {
+        return path;
+    }
+
+    let stem = path.file_stem().map(std::ffi::OsStr::to_os_string);
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|candidate| {
+            candidate.file_stem().map(std::ffi::OsStr::to_os_string) == stem
+                && browser::is_audio_file(candidate)
+        })
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const SHEET: &str = r#"REM GENRE Jazz
+REM DATE 1959
+PERFORMER "Miles Davis"
+TITLE "Kind of Blue"
+FILE "Kind of Blue.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "So What"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Freddie Freeloader"
+    PERFORMER "Miles Davis Sextet"
+    INDEX 00 09:20:10
+    INDEX 01 09:22:30
+  TRACK 03 AUDIO
+    TITLE "Blue in Green"
+    INDEX 01 19:08:00
+"#;
+
+    #[test]
+    fn test_parse_sheet() {
+        let sheet = CueSheet::parse(SHEET, Path::new("/music"));
+
+        assert_eq!(sheet.title.as_deref(), Some("Kind of Blue"));
+        assert_eq!(sheet.performer.as_deref(), Some("Miles Davis"));
+        assert_eq!(sheet.date.as_deref(), Some("1959"));
+        assert_eq!(sheet.genre.as_deref(), Some("Jazz"));
+        assert_eq!(sheet.tracks.len(), 3);
+
+        let second = &sheet.tracks[1];
+        assert_eq!(second.number, 2);
+        assert_eq!(second.title.as_deref(), Some("Freddie Freeloader"));
+        assert_eq!(second.performer.as_deref(), Some("Miles Davis Sextet"));
+        assert_eq!(second.file, PathBuf::from("/music/Kind of Blue.flac"));
+        // INDEX 01 marks the start, the pregap stays with the previous track
+        assert_eq!(second.start, (9 * 60 + 22) * 75 + 30);
+        assert_eq!(sheet.tracks[0].end, Some(second.start));
+        assert_eq!(second.end, Some((19 * 60 + 8) * 75));
+        assert_eq!(sheet.tracks[2].end, None);
+        assert_eq!(second.start_frame(44100), 24_801_840);
+    }
+
+    #[test]
+    fn test_tracks_end_at_file_boundaries() {
+        let sheet = CueSheet::parse(
+            "FILE \"a.wav\" WAVE\nTRACK 1 AUDIO\nINDEX 01 00:00:00\n\
+             FILE \"b.wav\" WAVE\nTRACK 2 AUDIO\nINDEX 01 00:00:00\n\
+             TRACK 3 AUDIO\nINDEX 01 01:00:00\nTRACK 4 AUDIO\n",
+            Path::new("/cd"),
+        );
+
+        assert_eq!(sheet.tracks.len(), 3);
+        assert_eq!(sheet.tracks[0].end, None);
+        assert_eq!(sheet.tracks[1].end, Some(60 * 75));
+        assert_eq!(sheet.files().count(), 2);
+    }
+
+    #[test]
+    fn test_track_paths_round_trip() {
+        let path = track_path(Path::new("/music/Album.cue"), 7);
+        assert_eq!(path, PathBuf::from("/music/Album.cue/track0007"));
+        assert_eq!(
+            split_track_path(&path),
+            Some((Path::new("/music/Album.cue"), 7))
+        );
+        assert_eq!(split_track_path(Path::new("/music/Album/track0007")), None);
+        assert_eq!(
+            split_track_path(Path::new("/music/Album.cue/cover.jpg")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_latin1_sheet_and_renamed_image() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("album.flac"), b"").unwrap();
+        let cue = dir.path().join("album.cue");
+        fs::write(
+            &cue,
+            b"FILE \"album.wav\" WAVE\nTRACK 01 AUDIO\nTITLE \"Caf\xE9\"\nINDEX 01 00:00:00\n",
+        )
+        .unwrap();
+
+        let (sheet, track) = resolve(&track_path(&cue, 1)).unwrap();
+        assert_eq!(track.title.as_deref(), Some("Café"));
+        assert_eq!(track.file, dir.path().join("album.flac"));
+        assert_eq!(sheet.tracks.len(), 1);
+        assert_eq!(display_name(&track_path(&cue, 1)), "01. Café");
+        assert!(resolve(&track_path(&cue, 2)).is_none());
+    }
+}
@@ -0,0 +1,324 @@
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Highest level the limiter lets through, -1 dBFS.
+pub const CEILING: f32 = 0.891;
+
+// Release time of the limiter once a peak has passed
+pub(crate) const LIMITER_RELEASE_MS: f32 = 80.0;
+
+/// Compressor presets, from bypassed to heavy compression for quiet listening.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DynamicsPreset {
+    #[default]
+    Off,
+    /// Gentle leveling that keeps most of the dynamics
+    Light,
+    /// Brings quiet passages up and loud ones down, for low volume listening
+    Night,
+}
+
+impl DynamicsPreset {
+    pub fn as_str(&self) -> &str {
+        match self {
+            DynamicsPreset::Off => "Off",
+            DynamicsPreset::Light => "Light",
+            DynamicsPreset::Night => "Night",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "off" => Some(DynamicsPreset::Off),
+            "light" => Some(DynamicsPreset::Light),
+            "night" => Some(DynamicsPreset::Night),
+            _ => None,
+        }
+    }
+
+    fn params(self) -> Option<CompressorParams> {
+        match self {
+            DynamicsPreset::Off => None,
+            DynamicsPreset::Light => Some(CompressorParams {
+                threshold_db: -18.0,
+                ratio: 2.0,
+                attack_ms: 10.0,
+                release_ms: 200.0,
+                makeup_db: 4.0,
+            }),
+            DynamicsPreset::Night => Some(CompressorParams {
+                threshold_db: -32.0,
+                ratio: 4.0,
+                attack_ms: 5.0,
+                release_ms: 300.0,
+                makeup_db: 14.0,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CompressorParams {
+    threshold_db: f32,
+    ratio: f32,
+    attack_ms: f32,
+    release_ms: f32,
+    makeup_db: f32,
+}
+
+/// Volume and compressor preset shared with the playing source. The source compares
+/// the generation once per frame and only locks the settings when it changed.
+pub struct DynamicsControl {
+    settings: Mutex<(f32, DynamicsPreset)>,
+    generation: AtomicU64,
+}
+
+impl Default for DynamicsControl {
+    fn default() -> Self {
+        Self {
+            settings: Mutex::new((1.0, DynamicsPreset::Off)),
+            generation: AtomicU64::new(0),
+        }
+    }
+}
+
+impl DynamicsControl {
+    pub fn set_volume(&self, volume: f32) {
+        self.settings.lock().unwrap().0 = volume.max(0.0);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn set_preset(&self, preset: DynamicsPreset) {
+        self.settings.lock().unwrap().1 = preset;
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+// One-pole smoothing coefficient reaching ~63% of a step after `ms`
+pub(crate) fn smoothing(ms: f32, sample_rate: u32) -> f32 {
+    (-1.0 / (ms / 1000.0 * sample_rate as f32).max(1.0)).exp()
+}
+
+/// Source applying the volume, then the compressor and a peak limiter. The compressor
+/// reacts to the level before the volume, so a preset behaves the same at any volume,
+/// while the limiter keeps the final output under `CEILING`. With the preset off only
+/// the volume is applied.
+pub struct Dynamics<S> {
+    input: S,
+    control: Arc<DynamicsControl>,
+    generation: u64,
+    volume: f32,
+    params: Option<CompressorParams>,
+    attack: f32,
+    release: f32,
+    limiter_release: f32,
+    // Smoothed gain reduction of the compressor, in dB
+    reduction_db: f32,
+    limiter_gain: f32,
+    // The frame being played, processed as a whole so channels share one gain
+    frame: Vec<f32>,
+    frame_pos: usize,
+}
+
+impl<S> Dynamics<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, control: Arc<DynamicsControl>) -> Self {
+        Self {
+            input,
+            control,
+            // Force a lookup on the first frame
+            generation: u64::MAX,
+            volume: 1.0,
+            params: None,
+            attack: 0.0,
+            release: 0.0,
+            limiter_release: 0.0,
+            reduction_db: 0.0,
+            limiter_gain: 1.0,
+            frame: Vec::new(),
+            frame_pos: 0,
+        }
+    }
+
+    fn update_settings(&mut self) {
+        let generation = self.control.generation.load(Ordering::Acquire);
+        if generation == self.generation {
+            return;
+        }
+        self.generation = generation;
+
+        let (volume, preset) = *self.control.settings.lock().unwrap();
+        let sample_rate = self.input.sample_rate();
+        self.volume = volume;
+        self.params = preset.params();
+        if let Some(params) = self.params {
+            self.attack = smoothing(params.attack_ms, sample_rate);
+            self.release = smoothing(params.release_ms, sample_rate);
+        }
+        self.limiter_release = smoothing(LIMITER_RELEASE_MS, sample_rate);
+    }
+
+    // Pulls the next frame from the input and scales it. Returns false at the end.
+    fn process_frame(&mut self) -> bool {
+        self.update_settings();
+
+        let channels = self.input.channels().max(1) as usize;
+        self.frame.clear();
+        self.frame.extend(self.input.by_ref().take(channels));
+        self.frame_pos = 0;
+        if self.frame.is_empty() {
+            return false;
+        }
+
+        let Some(params) = self.params else {
+            for sample in &mut self.frame {
+                *sample *= self.volume;
+            }
+            return true;
+        };
+
+        let peak = self.frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+        // Gain reduction for the part of the level above the threshold
+        let level_db = 20.0 * peak.max(1e-6).log10();
+        let target_db = (level_db - params.threshold_db).max(0.0) * (1.0 - 1.0 / params.ratio);
+        let coeff = if target_db > self.reduction_db {
+            self.attack
+        } else {
+            self.release
+        };
+        self.reduction_db = target_db + coeff * (self.reduction_db - target_db);
+        let gain = self.volume * 10f32.powf((params.makeup_db - self.reduction_db) / 20.0);
+
+        // The limiter clamps at once and recovers slowly, so peaks never go over
+        let output_peak = peak * gain;
+        let needed = if output_peak > CEILING {
+            CEILING / output_peak
+        } else {
+            1.0
+        };
+        let recovered = 1.0 + self.limiter_release * (self.limiter_gain - 1.0);
+        self.limiter_gain = recovered.min(needed);
+
+        let gain = gain * self.limiter_gain;
+        for sample in &mut self.frame {
+            *sample *= gain;
+        }
+        true
+    }
+}
+
+impl<S> Iterator for Dynamics<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame_pos >= self.frame.len() && !self.process_frame() {
+            return None;
+        }
+        let sample = self.frame[self.frame_pos];
+        self.frame_pos += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Dynamics<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), rodio::source::SeekError> {
+        self.input.try_seek(pos)?;
+        // Drop what is left of the frame from before the seek
+        self.frame.clear();
+        self.frame_pos = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    // Mono 48 kHz tone at `amplitude` for `secs` seconds
+    fn tone(amplitude: f32, secs: f32) -> Vec<f32> {
+        let frames = (48_000.0 * secs) as usize;
+        (0..frames)
+            .map(|i| amplitude * (i as f32 * 2.0 * std::f32::consts::PI * 440.0 / 48_000.0).sin())
+            .collect()
+    }
+
+    fn process(samples: Vec<f32>, volume: f32, preset: DynamicsPreset) -> Vec<f32> {
+        let control = Arc::new(DynamicsControl::default());
+        control.set_volume(volume);
+        control.set_preset(preset);
+        Dynamics::new(SamplesBuffer::new(1, 48_000, samples), control).collect()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn test_off_only_applies_volume() {
+        let input = tone(0.8, 0.1);
+        let output = process(input.clone(), 0.5, DynamicsPreset::Off);
+        assert_eq!(output.len(), input.len());
+        assert!(
+            input
+                .iter()
+                .zip(&output)
+                .all(|(i, o)| (i * 0.5 - o).abs() < 1e-6)
+        );
+    }
+
+    #[test]
+    fn test_night_narrows_the_range() {
+        // 40 dB between the quiet and the loud passage
+        let quiet = peak(&process(tone(0.01, 1.0), 1.0, DynamicsPreset::Night)[24_000..]);
+        let loud = peak(&process(tone(1.0, 1.0), 1.0, DynamicsPreset::Night)[24_000..]);
+        let range_db = 20.0 * (loud / quiet).log10();
+        assert!(range_db < 20.0, "range {range_db} dB");
+        assert!(quiet > 0.01);
+    }
+
+    #[test]
+    fn test_limiter_never_clips() {
+        // Hot input with gain already applied upstream, even at full volume
+        for preset in [DynamicsPreset::Light, DynamicsPreset::Night] {
+            let output = process(tone(3.0, 0.5), 1.0, preset);
+            assert!(peak(&output) <= CEILING + 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_parse_preset() {
+        assert_eq!(DynamicsPreset::parse("Night"), Some(DynamicsPreset::Night));
+        assert_eq!(DynamicsPreset::parse("light"), Some(DynamicsPreset::Light));
+        assert_eq!(DynamicsPreset::parse("loud"), None);
+    }
+}
@@ -0,0 +1,571 @@
+use crate::channels::ChannelSettings;
+use crate::dynamics::DynamicsPreset;
+use crate::equalizer::Bands;
+use crate::logger;
+use crate::metadata::{StreamChoices, TrackMetadata};
+use crate::output::OutputBackend;
+use crate::player::{DecodeError, FadeCurve, Notice, Notify, Player, ReplayGainMode};
+use crate::resume::{ResumePoint, ResumePolicy, ResumePositions};
+use crate::visualizer::AudioTap;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How often the position is reported while playing
+const POSITION_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the UI asks of the engine. Commands are carried out in the order sent.
+#[derive(Debug)]
+pub enum Command {
+    Play(PathBuf),
+    Pause,
+    Resume,
+    Stop,
+    SeekTo(Duration),
+    SeekForward(Duration),
+    SeekBackward(Duration),
+    SetVolume(f32),
+    /// Track to continue with once the current one ends, see `Player::queue_next`
+    QueueNext(Option<PathBuf>),
+    SelectStream(u32),
+    SetAbLoop(Option<Duration>, Option<Duration>),
+    SetReplayGain(ReplayGainMode, f32),
+    SetEqualizer(Option<Bands>),
+    SetCrossfade(f32, FadeCurve),
+    SetDynamics(DynamicsPreset),
+    SetChannels(ChannelSettings),
+    SetDecodeErrorLimit(u32),
+    SetResumePolicy(ResumePolicy),
+    SetResumePositions(ResumePositions),
+    SetStreamChoices(StreamChoices),
+}
+
+/// What the engine reports back, as it happens.
+#[derive(Debug)]
+pub enum Event {
+    /// `path` started playing. `gapless` when the engine moved on to the queued track
+    /// by itself.
+    TrackStarted {
+        path: PathBuf,
+        channels: Option<u16>,
+        gapless: bool,
+    },
+    /// The current track played to its end, with nothing queued after it
+    TrackEnded,
+    /// Playback was stopped and the current track dropped
+    Stopped,
+    /// Tags of the current track, or what the station on air announces
+    Metadata(Option<Box<TrackMetadata>>),
+    Position(Duration),
+    State(PlayState),
+    AbLoop(Option<Duration>, Option<Duration>),
+    DecodeError(DecodeError),
+    ResumePositions(ResumePositions),
+    StreamChoices(StreamChoices),
+    /// Playing `path` failed; whatever played before carries on
+    PlayFailed {
+        path: PathBuf,
+        error: String,
+    },
+    /// Any other command that failed
+    Error(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlayState {
+    /// Nothing loaded, or the current track played out
+    #[default]
+    Stopped,
+    Playing,
+    Paused,
+}
+
+enum Message {
+    Command(Command),
+    Notice(Notice),
+    Shutdown,
+}
+
+/// Handle to the engine thread, which owns the player along with the output, the sink
+/// and the decoders. Dropping it stops playback.
+pub struct Engine {
+    messages: mpsc::Sender<Message>,
+    tap: Arc<AudioTap>,
+    thread: Option<JoinHandle<(StreamChoices, ResumePositions)>>,
+}
+
+impl Engine {
+    /// Opens `backend` on a new thread and starts taking commands. `emit` is called on
+    /// that thread with every event, so it should only pass them on.
+    pub fn spawn(backend: OutputBackend, emit: impl Fn(Event) + Send + 'static) -> Result<Self> {
+        let (messages, inbox) = mpsc::channel();
+        let notices = messages.clone();
+        let notify: Notify = Arc::new(move |notice| {
+            let _ = notices.send(Message::Notice(notice));
+        });
+
+        let (ready, started) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name(String::from("engine"))
+            .spawn(move || {
+                let player = match Player::new(&backend, notify) {
+                    Ok(player) => player,
+                    Err(e) => {
+                        let _ = ready.send(Err(e));
+                        return Default::default();
+                    }
+                };
+                let _ = ready.send(Ok(Arc::clone(player.tap())));
+                EngineThread::new(player, Box::new(emit)).run(&inbox)
+            })
+            .context("Failed to start the audio engine")?;
+
+        let tap = started
+            .recv()
+            .context("The audio engine quit while starting")??;
+        Ok(Self {
+            messages,
+            tap,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn send(&self, command: Command) {
+        // Only fails once the thread is gone, after `shutdown`
+        let _ = self.messages.send(Message::Command(command));
+    }
+
+    /// Samples on their way to the output, for the visualizer.
+    pub fn tap(&self) -> &AudioTap {
+        &self.tap
+    }
+
+    /// Stops playback, remembering where the current track was left, and waits for the
+    /// engine thread. Hands back the state worth saving, the first time only.
+    pub fn shutdown(&mut self) -> Option<(StreamChoices, ResumePositions)> {
+        let thread = self.thread.take()?;
+        let _ = self.messages.send(Message::Shutdown);
+        thread.join().ok()
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// What was reported last, so that only changes go out
+#[derive(Default)]
+struct Reported {
+    metadata: Option<(u64, Option<String>)>,
+    position: Option<Duration>,
+    state: Option<PlayState>,
+    ab_loop: Option<(Option<Duration>, Option<Duration>)>,
+    resume_positions: Option<ResumePositions>,
+    stream_choices: Option<StreamChoices>,
+    ended: bool,
+}
+
+struct EngineThread {
+    player: Player,
+    emit: Box<dyn Fn(Event) + Send>,
+    reported: Reported,
+}
+
+impl EngineThread {
+    fn new(player: Player, emit: Box<dyn Fn(Event) + Send>) -> Self {
+        Self {
+            player,
+            emit,
+            reported: Reported::default(),
+        }
+    }
+
+    fn run(mut self, inbox: &mpsc::Receiver<Message>) -> (StreamChoices, ResumePositions) {
+        let mut next_tick = Instant::now();
+        loop {
+            // Only playback moves the position along; otherwise there is nothing to
+            // report until a message comes in
+            let message = if self.player.is_playing() {
+                match inbox.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                match inbox.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => break,
+                }
+            };
+
+            match message {
+                Some(Message::Command(command)) => self.handle_command(command),
+                Some(Message::Notice(Notice::Ended)) => self.ended(),
+                Some(Message::Notice(notice)) => {
+                    if let Some(path) = self.player.handle_notice(notice) {
+                        self.started(path, true);
+                    }
+                }
+                Some(Message::Shutdown) => break,
+                None => {}
+            }
+
+            // Ticks keep their pace however many messages come in between
+            let tick = Instant::now() >= next_tick;
+            if tick {
+                next_tick = Instant::now() + POSITION_INTERVAL;
+            }
+            self.report(tick);
+        }
+
+        self.player.stop();
+        (
+            self.player.stream_choices().clone(),
+            self.player.resume_positions().clone(),
+        )
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        let player = &mut self.player;
+        match command {
+            Command::Play(path) => match player.play(&path) {
+                Ok(()) => self.started(path, false),
+                Err(e) => (self.emit)(Event::PlayFailed {
+                    path,
+                    error: e.to_string(),
+                }),
+            },
+            Command::Pause => player.pause(),
+            Command::Resume => player.resume(),
+            Command::Stop => {
+                player.stop();
+                (self.emit)(Event::Stopped);
+                self.reported.position = None;
+            }
+            Command::SeekTo(position) => {
+                player.seek_to(position);
+                self.reported.position = None;
+            }
+            Command::SeekForward(offset) => {
+                player.seek_to(player.position() + offset);
+                self.reported.position = None;
+            }
+            Command::SeekBackward(offset) => {
+                player.seek_to(player.position().saturating_sub(offset));
+                self.reported.position = None;
+            }
+            Command::SetVolume(volume) => player.set_volume(volume),
+            Command::QueueNext(path) => {
+                if let Err(e) = player.queue_next(path.as_deref()) {
+                    logger::log(&format!("Failed to preload next track: {e}"));
+                }
+            }
+            Command::SelectStream(id) => {
+                if let Err(e) = player.select_stream(id) {
+                    (self.emit)(Event::Error(format!("Failed to switch audio track: {e}")));
+                }
+            }
+            Command::SetAbLoop(start, end) => player.set_ab_loop(start, end),
+            Command::SetReplayGain(mode, preamp_db) => player.set_replaygain(mode, preamp_db),
+            Command::SetEqualizer(bands) => player.set_equalizer(bands),
+            Command::SetCrossfade(secs, curve) => player.set_crossfade(secs, curve),
+            Command::SetDynamics(preset) => player.set_dynamics(preset),
+            Command::SetChannels(settings) => player.set_channels(settings),
+            Command::SetDecodeErrorLimit(limit) => player.set_decode_error_limit(limit),
+            Command::SetResumePolicy(policy) => player.set_resume_policy(policy),
+            Command::SetResumePositions(positions) => player.set_resume_positions(positions),
+            Command::SetStreamChoices(choices) => player.set_stream_choices(choices),
+        }
+    }
+
+    fn started(&mut self, path: PathBuf, gapless: bool) {
+        (self.emit)(Event::TrackStarted {
+            path,
+            channels: self.player.source_channels(),
+            gapless,
+        });
+        // Whoever listens starts the new track from scratch
+        self.reported.metadata = None;
+        self.reported.position = None;
+        self.reported.ab_loop = Some((None, None));
+        self.reported.ended = false;
+    }
+
+    // The chain ran dry. The notice may trail a command that already started something
+    // else, so the player has the last word on whether the track is over.
+    fn ended(&mut self) {
+        let player = &self.player;
+        if self.reported.ended
+            || !player.is_finished()
+            || player.has_pending_transition()
+            || player.current_track().is_none()
+        {
+            return;
+        }
+        // Report first, so the track is fully described by the time it ends
+        self.report(true);
+        (self.emit)(Event::TrackEnded);
+        self.reported.ended = true;
+    }
+
+    // Sends whatever changed since the last report. The position moves on all the
+    // time, so it only goes out on ticks or right after a jump.
+    fn report(&mut self, tick: bool) {
+        let player = &self.player;
+        let emit = &self.emit;
+        let reported = &mut self.reported;
+
+        for error in player.take_decode_errors() {
+            emit(Event::DecodeError(error));
+        }
+
+        let metadata = (player.metadata_version(), player.station_title());
+        if reported.metadata.as_ref() != Some(&metadata) {
+            emit(Event::Metadata(player.current_metadata().map(Box::new)));
+            reported.metadata = Some(metadata);
+        }
+
+        if tick || reported.position.is_none() {
+            let position = player.position();
+            if reported.position != Some(position) {
+                emit(Event::Position(position));
+                reported.position = Some(position);
+            }
+        }
+
+        let ab_loop = player.ab_loop();
+        if reported.ab_loop != Some(ab_loop) {
+            emit(Event::AbLoop(ab_loop.0, ab_loop.1));
+            reported.ab_loop = Some(ab_loop);
+        }
+
+        let state = if player.current_track().is_none() || player.is_finished() {
+            PlayState::Stopped
+        } else if player.is_paused() {
+            PlayState::Paused
+        } else {
+            PlayState::Playing
+        };
+        if reported.state != Some(state) {
+            emit(Event::State(state));
+            reported.state = Some(state);
+        }
+
+        if reported.resume_positions.as_ref() != Some(player.resume_positions()) {
+            emit(Event::ResumePositions(player.resume_positions().clone()));
+            reported.resume_positions = Some(player.resume_positions().clone());
+        }
+        if reported.stream_choices.as_ref() != Some(player.stream_choices()) {
+            emit(Event::StreamChoices(player.stream_choices().clone()));
+            reported.stream_choices = Some(player.stream_choices().clone());
+        }
+    }
+}
+
+/// The engine as last reported, kept by the UI so that drawing never waits on it.
+#[derive(Default)]
+pub struct PlaybackState {
+    track: Option<PathBuf>,
+    metadata: Option<Box<TrackMetadata>>,
+    channels: Option<u16>,
+    position: Duration,
+    state: PlayState,
+    ab_loop: (Option<Duration>, Option<Duration>),
+    resume_positions: ResumePositions,
+    stream_choices: StreamChoices,
+}
+
+impl PlaybackState {
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::TrackStarted { path, channels, .. } => {
+                self.track = Some(path.clone());
+                self.metadata = None;
+                self.channels = *channels;
+                self.position = Duration::ZERO;
+                self.ab_loop = (None, None);
+            }
+            Event::Stopped => {
+                self.track = None;
+                self.metadata = None;
+                self.channels = None;
+                self.position = Duration::ZERO;
+            }
+            Event::Metadata(metadata) => self.metadata.clone_from(metadata),
+            Event::Position(position) => self.position = *position,
+            Event::State(state) => self.state = *state,
+            Event::AbLoop(start, end) => self.ab_loop = (*start, *end),
+            Event::ResumePositions(positions) => self.resume_positions.clone_from(positions),
+            Event::StreamChoices(choices) => self.stream_choices.clone_from(choices),
+            Event::TrackEnded
+            | Event::DecodeError(_)
+            | Event::PlayFailed { .. }
+            | Event::Error(_) => {}
+        }
+    }
+
+    pub fn current_track(&self) -> Option<PathBuf> {
+        self.track.clone()
+    }
+
+    /// Metadata of the current track. For streams, the song on air as the station
+    /// last announced it.
+    pub fn current_metadata(&self) -> Option<TrackMetadata> {
+        self.metadata.as_deref().cloned()
+    }
+
+    pub fn position(&self) -> Duration {
+        self.position
+    }
+
+    /// Position, and how far into the track it is from 0.0 to 1.0 when its length
+    /// is known.
+    pub fn position_and_progress(&self) -> (Duration, Option<f64>) {
+        let progress = self
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.duration_secs)
+            .filter(|duration_secs| *duration_secs > 0)
+            .map(|duration_secs| (self.position.as_secs_f64() / duration_secs as f64).min(1.0));
+        (self.position, progress)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state == PlayState::Playing
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == PlayState::Paused
+    }
+
+    /// True when nothing is loaded or the current track played out.
+    pub fn is_finished(&self) -> bool {
+        self.state == PlayState::Stopped
+    }
+
+    /// Channel count of the playing track, before it is mixed down to stereo.
+    pub fn source_channels(&self) -> Option<u16> {
+        self.channels
+    }
+
+    /// A-B loop markers of the current track.
+    pub fn ab_loop(&self) -> (Option<Duration>, Option<Duration>) {
+        self.ab_loop
+    }
+
+    pub fn resume_point(&self, path: &Path) -> Option<ResumePoint> {
+        self.resume_positions.get(path)
+    }
+
+    pub fn resume_positions(&self) -> &ResumePositions {
+        &self.resume_positions
+    }
+
+    /// Stream picked for `path` with `Command::SelectStream`, if any.
+    pub fn chosen_stream(&self, path: &Path) -> Option<u32> {
+        self.stream_choices.get(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::tests::write_wav;
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    fn spawn() -> (Engine, mpsc::Receiver<Event>) {
+        let (sender, events) = mpsc::channel();
+        let engine = Engine::spawn(OutputBackend::Null, move |event| {
+            let _ = sender.send(event);
+        })
+        .unwrap();
+        (engine, events)
+    }
+
+    // Folds events into `state` until one matches, giving up after a few seconds
+    fn wait_for(
+        events: &mpsc::Receiver<Event>,
+        state: &mut PlaybackState,
+        matches: impl Fn(&Event) -> bool,
+    ) -> Event {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let event = events.recv_timeout(timeout).expect("event never came");
+            state.apply(&event);
+            if matches(&event) {
+                return event;
+            }
+        }
+    }
+
+    #[test]
+    fn test_reports_tracks_from_start_to_end() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = temp_dir.path().join("first.wav");
+        let second = temp_dir.path().join("second.wav");
+        write_wav(&first, 1, 8000, 8000, 1000);
+        write_wav(&second, 1, 8000, 2400, 1000);
+
+        let (engine, events) = spawn();
+        let mut state = PlaybackState::default();
+        engine.send(Command::Play(first.clone()));
+        let event = wait_for(&events, &mut state, |event| {
+            matches!(event, Event::TrackStarted { .. })
+        });
+        assert!(matches!(event, Event::TrackStarted { gapless: false, .. }));
+        assert_eq!(state.current_track(), Some(first.clone()));
+        assert_eq!(state.source_channels(), Some(1));
+
+        wait_for(&events, &mut state, |event| {
+            matches!(event, Event::Metadata(Some(_)))
+        });
+        assert!(state.is_playing());
+
+        // The queued track follows without a gap, then the engine runs out
+        engine.send(Command::QueueNext(Some(second.clone())));
+        let event = wait_for(&events, &mut state, |event| {
+            matches!(event, Event::TrackStarted { .. })
+        });
+        assert!(matches!(event, Event::TrackStarted { gapless: true, .. }));
+        assert_eq!(state.current_track(), Some(second.clone()));
+
+        wait_for(&events, &mut state, |event| {
+            matches!(event, Event::TrackEnded)
+        });
+        assert_eq!(state.current_track(), Some(second));
+        assert!(state.is_finished());
+    }
+
+    #[test]
+    fn test_reports_commands_that_failed() {
+        let temp_dir = TempDir::new().unwrap();
+        let track = temp_dir.path().join("track.wav");
+        write_wav(&track, 1, 8000, 16000, 1000);
+        let missing = temp_dir.path().join("missing.wav");
+
+        let (engine, events) = spawn();
+        let mut state = PlaybackState::default();
+        engine.send(Command::Play(track.clone()));
+        engine.send(Command::Pause);
+        engine.send(Command::Play(missing.clone()));
+        let event = wait_for(&events, &mut state, |event| {
+            matches!(event, Event::PlayFailed { .. })
+        });
+        assert!(matches!(event, Event::PlayFailed { path, .. } if path == missing));
+
+        // What played before is left alone
+        assert_eq!(state.current_track(), Some(track));
+        assert!(state.is_paused());
+
+        engine.send(Command::Stop);
+        wait_for(&events, &mut state, |event| matches!(event, Event::Stopped));
+        assert_eq!(state.current_track(), None);
+    }
+}
@@ -0,0 +1,471 @@
+use anyhow::{Context, Result};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const BAND_COUNT: usize = 10;
+
+/// Center frequency of each band in Hz, one octave apart.
+pub const BAND_FREQUENCIES: [f32; BAND_COUNT] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+/// Largest boost or cut accepted for a band, in dB.
+pub const MAX_GAIN_DB: f32 = 12.0;
+
+/// Gain of every band in dB, lowest frequency first.
+pub type Bands = [f32; BAND_COUNT];
+
+// Q of a one octave wide peaking filter
+const BAND_Q: f32 = std::f32::consts::SQRT_2;
+
+/// Presets written to a fresh configuration file.
+pub fn default_presets() -> BTreeMap<String, Bands> {
+    BTreeMap::from([
+        ("flat".to_string(), [0.0; BAND_COUNT]),
+        (
+            "bass".to_string(),
+            [6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        ),
+        (
+            "treble".to_string(),
+            [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 4.0, 5.0, 6.0],
+        ),
+        (
+            "vocal".to_string(),
+            [-2.0, -2.0, -1.0, 0.0, 2.0, 4.0, 4.0, 2.0, 0.0, -1.0],
+        ),
+        (
+            "loudness".to_string(),
+            [5.0, 4.0, 2.0, 0.0, -1.0, -1.0, 0.0, 2.0, 4.0, 5.0],
+        ),
+    ])
+}
+
+/// Short label for a band, e.g. "125" or "2k".
+pub fn band_label(band: usize) -> String {
+    let freq = BAND_FREQUENCIES[band];
+    if freq >= 1000.0 {
+        format!("{}k", freq / 1000.0)
+    } else {
+        format!("{freq}")
+    }
+}
+
+/// Equalizer settings, restored on the next start.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EqState {
+    pub enabled: bool,
+    /// Preset the bands were last loaded from
+    pub preset: String,
+    pub bands: Bands,
+}
+
+impl Default for EqState {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            preset: "flat".to_string(),
+            bands: [0.0; BAND_COUNT],
+        }
+    }
+}
+
+impl EqState {
+    /// Loads the bands of `name` and enables the equalizer. Returns false when there is
+    /// no such preset.
+    pub fn apply_preset(&mut self, name: &str, presets: &BTreeMap<String, Bands>) -> bool {
+        let Some(bands) = presets.get(name) else {
+            return false;
+        };
+        self.preset = name.to_string();
+        self.bands = clamp_bands(*bands);
+        self.enabled = true;
+        true
+    }
+
+    pub fn adjust(&mut self, band: usize, delta_db: f32) {
+        if let Some(gain) = self.bands.get_mut(band) {
+            *gain = (*gain + delta_db).clamp(-MAX_GAIN_DB, MAX_GAIN_DB);
+        }
+    }
+
+    /// Whether the bands were changed since the preset was loaded.
+    pub fn is_modified(&self, presets: &BTreeMap<String, Bands>) -> bool {
+        presets.get(&self.preset) != Some(&self.bands)
+    }
+
+    /// Bands to hand to the player, or None while the equalizer is bypassed.
+    pub fn active_bands(&self) -> Option<Bands> {
+        self.enabled.then_some(self.bands)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let state_path = Self::state_path();
+
+        if let Some(parent) = state_path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create equalizer directory {}", parent.display())
+            })?;
+        }
+
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(&state_path, content).with_context(|| {
+            format!("Failed to write equalizer file at {}", state_path.display())
+        })?;
+        Ok(())
+    }
+
+    pub fn load() -> Result<Self> {
+        let state_path = Self::state_path();
+
+        if state_path.exists() {
+            let content = fs::read_to_string(&state_path)?;
+            Self::parse(&content)
+        } else {
+            Ok(EqState::default())
+        }
+    }
+
+    // The file may have been edited by hand, so gains are kept in range
+    fn parse(content: &str) -> Result<Self> {
+        let mut state: EqState = serde_json::from_str(content)?;
+        state.bands = clamp_bands(state.bands);
+        Ok(state)
+    }
+
+    fn state_path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("impulse")
+            .join("equalizer.json")
+    }
+}
+
+// Gains that are not a number at all fall back to flat
+fn clamp_bands(bands: Bands) -> Bands {
+    bands.map(|gain| {
+        if gain.is_finite() {
+            gain.clamp(-MAX_GAIN_DB, MAX_GAIN_DB)
+        } else {
+            0.0
+        }
+    })
+}
+
+/// Band settings shared with the playing source. The source compares the generation
+/// once per frame and only locks the bands when it changed.
+#[derive(Default)]
+pub struct EqControl {
+    bands: Mutex<Option<Bands>>,
+    generation: AtomicU64,
+}
+
+impl EqControl {
+    pub fn set(&self, bands: Option<Bands>) {
+        *self.bands.lock().unwrap() = bands;
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+// Normalized peaking filter coefficients (RBJ audio EQ cookbook)
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn peaking(freq: f32, gain_db: f32, sample_rate: u32) -> Self {
+        let a = 10f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * BAND_Q);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha / a;
+
+        Self {
+            b0: (1.0 + alpha * a) / a0,
+            b1: (-2.0 * cos_w0) / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: (-2.0 * cos_w0) / a0,
+            a2: (1.0 - alpha / a) / a0,
+        }
+    }
+
+    // Magnitude of the filter's response at `freq`
+    fn magnitude(&self, freq: f32, sample_rate: u32) -> f32 {
+        let w = 2.0 * std::f32::consts::PI * freq / sample_rate as f32;
+        let (sin1, cos1) = w.sin_cos();
+        let (sin2, cos2) = (2.0 * w).sin_cos();
+        let num_re = self.b0 + self.b1 * cos1 + self.b2 * cos2;
+        let num_im = -(self.b1 * sin1 + self.b2 * sin2);
+        let den_re = 1.0 + self.a1 * cos1 + self.a2 * cos2;
+        let den_im = -(self.a1 * sin1 + self.a2 * sin2);
+        num_re.hypot(num_im) / den_re.hypot(den_im).max(f32::EPSILON)
+    }
+}
+
+// Input scaling that keeps the largest combined boost of `filters` at unity gain.
+// Neighbouring bands overlap, so the response is probed between the centers as well.
+fn headroom(filters: &[Biquad], sample_rate: u32) -> f32 {
+    let nyquist = sample_rate as f32 / 2.0;
+    let probes = BAND_FREQUENCIES
+        .iter()
+        .flat_map(|freq| [*freq, freq * std::f32::consts::SQRT_2])
+        .filter(|freq| *freq < nyquist);
+    let peak = probes
+        .map(|freq| {
+            filters
+                .iter()
+                .map(|filter| filter.magnitude(freq, sample_rate))
+                .product::<f32>()
+        })
+        .fold(1.0f32, f32::max);
+    1.0 / peak
+}
+
+// Transposed direct form II delay line, one per band and channel
+#[derive(Clone, Copy, Default)]
+struct FilterState {
+    z1: f32,
+    z2: f32,
+}
+
+impl FilterState {
+    fn process(&mut self, filter: &Biquad, input: f32) -> f32 {
+        let output = filter.b0 * input + self.z1;
+        self.z1 = filter.b1 * input - filter.a1 * output + self.z2;
+        self.z2 = filter.b2 * input - filter.a2 * output;
+        output
+    }
+}
+
+/// Source applying the shared equalizer bands to `input`. Passes samples through
+/// untouched while the equalizer is bypassed.
+pub struct Equalizer<S> {
+    input: S,
+    control: Arc<EqControl>,
+    generation: u64,
+    // Only bands with a non-zero gain below Nyquist are kept
+    filters: Vec<Biquad>,
+    // `filters.len()` states per channel
+    states: Vec<FilterState>,
+    // Scales the input down by the largest boost so boosted bands do not clip
+    headroom: f32,
+    channel: usize,
+}
+
+impl<S> Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, control: Arc<EqControl>) -> Self {
+        Self {
+            input,
+            control,
+            // Force a lookup on the first frame
+            generation: u64::MAX,
+            filters: Vec::new(),
+            states: Vec::new(),
+            headroom: 1.0,
+            channel: 0,
+        }
+    }
+
+    fn update_filters(&mut self) {
+        let generation = self.control.generation.load(Ordering::Acquire);
+        if generation == self.generation {
+            return;
+        }
+        self.generation = generation;
+
+        let bands = *self.control.bands.lock().unwrap();
+        let sample_rate = self.input.sample_rate();
+        let nyquist = sample_rate as f32 / 2.0;
+
+        let filters: Vec<Biquad> = bands
+            .iter()
+            .flatten()
+            .zip(BAND_FREQUENCIES)
+            .filter(|(gain, freq)| gain.abs() > f32::EPSILON && *freq < nyquist * 0.9)
+            .map(|(gain, freq)| Biquad::peaking(freq, *gain, sample_rate))
+            .collect();
+
+        self.headroom = headroom(&filters, sample_rate);
+
+        // Keep the filter memory when only the gains moved, so live edits do not click
+        let states_len = filters.len() * self.input.channels().max(1) as usize;
+        if self.states.len() != states_len {
+            self.states = vec![FilterState::default(); states_len];
+        }
+        self.filters = filters;
+    }
+}
+
+impl<S> Iterator for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.update_filters();
+        }
+
+        let sample = self.input.next()?;
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.input.channels().max(1) as usize;
+
+        if self.filters.is_empty() {
+            return Some(sample);
+        }
+
+        let offset = channel * self.filters.len();
+        let states = &mut self.states[offset..offset + self.filters.len()];
+        let mut value = sample * self.headroom;
+        for (filter, state) in self.filters.iter().zip(states) {
+            value = state.process(filter, value);
+        }
+        Some(value)
+    }
+}
+
+impl<S> Source for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), rodio::source::SeekError> {
+        self.input.try_seek(pos)?;
+        // Ringing from before the seek point would leak into the new position
+        self.states.fill(FilterState::default());
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn sine(freq: f32, amplitude: f32, sample_rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                (2.0 * std::f32::consts::PI * freq * t).sin() * amplitude
+            })
+            .collect()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn test_bypassed_equalizer_passes_samples_through() {
+        let input = sine(1000.0, 0.25, 44100, 4410);
+        let control = Arc::new(EqControl::default());
+        let eq = Equalizer::new(SamplesBuffer::new(1, 44100, input.clone()), control);
+        assert_eq!(eq.collect::<Vec<f32>>(), input);
+    }
+
+    #[test]
+    fn test_band_cut_attenuates_its_frequency_only() {
+        let control = Arc::new(EqControl::default());
+        let mut bands = [0.0; BAND_COUNT];
+        bands[5] = -12.0;
+        control.set(Some(bands));
+
+        let cut: Vec<f32> = Equalizer::new(
+            SamplesBuffer::new(1, 44100, sine(1000.0, 0.25, 44100, 44100)),
+            Arc::clone(&control),
+        )
+        .collect();
+        // Skip the filter settling time
+        assert!(peak(&cut[22050..]) < 0.25 / 3.0);
+
+        let untouched: Vec<f32> = Equalizer::new(
+            SamplesBuffer::new(1, 44100, sine(31.0, 0.25, 44100, 44100)),
+            control,
+        )
+        .collect();
+        assert!(peak(&untouched[22050..]) > 0.22);
+    }
+
+    #[test]
+    fn test_boost_does_not_clip() {
+        let control = Arc::new(EqControl::default());
+        control.set(Some([MAX_GAIN_DB; BAND_COUNT]));
+        let loud = sine(1000.0, 0.95, 44100, 44100);
+
+        let out: Vec<f32> = Equalizer::new(SamplesBuffer::new(1, 44100, loud), control).collect();
+        assert!(peak(&out[22050..]) < 1.0);
+    }
+
+    #[test]
+    fn test_loaded_bands_are_clamped() {
+        let state = EqState::parse(
+            r#"{"enabled": true, "preset": "flat", "bands": [-40, 40, 0, 0, 0, 0, 0, 0, 0, 0]}"#,
+        )
+        .unwrap();
+        assert_eq!(state.bands[0], -MAX_GAIN_DB);
+        assert_eq!(state.bands[1], MAX_GAIN_DB);
+
+        // The JSON state has no NaN, but a TOML preset can spell one out
+        let mut bands = [0.0; BAND_COUNT];
+        bands[0] = f32::NAN;
+        bands[1] = f32::INFINITY;
+        assert_eq!(clamp_bands(bands)[..2], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_apply_preset_and_modified() {
+        let presets = default_presets();
+        let mut state = EqState::default();
+        assert!(!state.enabled);
+
+        assert!(state.apply_preset("bass", &presets));
+        assert!(state.enabled);
+        assert_eq!(state.preset, "bass");
+        assert!(!state.is_modified(&presets));
+
+        state.adjust(0, 100.0);
+        assert_eq!(state.bands[0], MAX_GAIN_DB);
+        assert!(state.is_modified(&presets));
+
+        assert!(!state.apply_preset("missing", &presets));
+        assert_eq!(state.preset, "bass");
+    }
+
+    #[test]
+    fn test_band_labels() {
+        assert_eq!(band_label(0), "31");
+        assert_eq!(band_label(5), "1k");
+        assert_eq!(band_label(9), "16k");
+    }
+}
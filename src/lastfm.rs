@@ -1,5 +1,6 @@
 use crate::config::LastfmConfig;
 use crate::metadata::TrackMetadata;
+use crate::radio;
 use anyhow::{Context, Result};
 use rustfm_scrobble::{Scrobble, Scrobbler};
 use std::path::Path;
@@ -63,7 +64,8 @@ impl LastfmScrobbler {
     }
 
     pub fn now_playing(&self, path: &Path, metadata: &TrackMetadata) -> Result<()> {
-        if !self.enabled {
+        // Streams have no track of their own to scrobble
+        if !self.enabled || radio::is_url(path) {
             return Ok(());
         }
 
@@ -90,7 +92,8 @@ impl LastfmScrobbler {
     }
 
     pub fn scrobble(&self, path: &Path, metadata: &TrackMetadata) -> Result<()> {
-        if !self.enabled {
+        // Streams have no track of their own to scrobble
+        if !self.enabled || radio::is_url(path) {
             return Ok(());
         }
 
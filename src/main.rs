@@ -1,13 +1,25 @@
 mod browser;
+mod channels;
+mod chapters;
 mod config;
+mod cue;
+mod dynamics;
+mod engine;
+mod equalizer;
 mod lastfm;
 mod lastfm_auth;
 mod logger;
 mod metadata;
+mod output;
 mod player;
 mod playlist;
 mod queue;
+mod radio;
+mod resume;
+mod sleep;
 mod ui;
+mod visualizer;
+mod waveform;
 
 use anyhow::Result;
 use clap::Parser;
@@ -29,6 +41,14 @@ struct Args {
     /// Load songs from text file(s) with "artist - song" format (one per line). Can be specified multiple times.
     #[arg(short, long, value_name = "FILE")]
     load_playlist: Vec<PathBuf>,
+
+    /// Audio output: device, null, wav:<path> or fifo:<path>. Overrides the config file.
+    #[arg(short, long, value_name = "OUTPUT")]
+    output: Option<output::OutputBackend>,
+
+    /// Audio files, M3U/PLS playlists or http(s) stream URLs to queue.
+    #[arg(value_name = "FILE_OR_URL")]
+    tracks: Vec<String>,
 }
 
 fn main() -> Result<()> {
@@ -42,6 +62,10 @@ fn main() -> Result<()> {
         }
     };
 
+    if let Some(output) = args.output {
+        config.output = output;
+    }
+
     // Initialize logger
     if let Err(e) = logger::init_logger(&config.log_file) {
         eprintln!("Warning: Failed to initialize logger: {}", e);
@@ -55,7 +79,7 @@ fn main() -> Result<()> {
     }
 
     // Load songs from file(s) if provided
-    let initial_queue = if !args.load_playlist.is_empty() {
+    let mut initial_queue = if !args.load_playlist.is_empty() {
         let mut all_tracks = Vec::new();
         for playlist_file in &args.load_playlist {
             logger::log(&format!("Loading playlist: {}", playlist_file.display()));
@@ -94,6 +118,7 @@ fn main() -> Result<()> {
     } else {
         Vec::new()
     };
+    initial_queue.extend(expand_arguments(&args.tracks));
 
     // Setup terminal
     enable_raw_mode()?;
@@ -116,10 +141,7 @@ fn main() -> Result<()> {
             disable_raw_mode()?;
             execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
             eprintln!("\nError initializing audio player: {}", e);
-            eprintln!("Make sure you have audio devices configured on your system.");
-            eprintln!("\nFor headless systems or systems without audio:");
-            eprintln!("  - Install a virtual audio device like 'pulseaudio' or 'alsa-utils'");
-            eprintln!("  - Configure a dummy audio output");
+            eprintln!("Check the `output` setting, or run with `--output null`.");
             std::process::exit(1);
         }
     };
@@ -142,6 +164,45 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Turns command line arguments into tracks: URLs and audio files are queued as is,
+/// playlists are replaced by their entries.
+fn expand_arguments(arguments: &[String]) -> Vec<PathBuf> {
+    let mut tracks = Vec::new();
+    for argument in arguments {
+        let path = PathBuf::from(argument);
+        if radio::is_url(&path) {
+            tracks.push(path);
+            continue;
+        }
+
+        let is_playlist = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                ["m3u", "m3u8", "pls"]
+                    .iter()
+                    .any(|known| ext.eq_ignore_ascii_case(known))
+            });
+        if is_playlist {
+            match playlist::Playlist::load(&path) {
+                Ok(playlist) => tracks.extend(playlist.tracks),
+                Err(e) => {
+                    let msg = format!("Error loading playlist {}: {:#}", path.display(), e);
+                    eprintln!("{}", msg);
+                    logger::log(&msg);
+                }
+            }
+        } else if path.is_file() {
+            tracks.push(path);
+        } else {
+            let msg = format!("Warning: {} does not exist", path.display());
+            eprintln!("{}", msg);
+            logger::log(&msg);
+        }
+    }
+    tracks
+}
+
 fn load_songs_from_file(playlist_file: &Path, music_dir: &Path) -> Result<Vec<PathBuf>> {
     let content = fs::read_to_string(playlist_file)?;
     let mut found_tracks = Vec::new();
@@ -326,6 +387,34 @@ mod tests {
         music_dir
     }
 
+    #[test]
+    fn test_expand_arguments() {
+        let temp_dir = TempDir::new().unwrap();
+        let track = temp_dir.path().join("song.mp3");
+        fs::write(&track, "dummy audio").unwrap();
+        let pls = temp_dir.path().join("radio.pls");
+        fs::write(&pls, "[playlist]\nFile1=https://radio.example/live\n").unwrap();
+
+        let arguments = vec![
+            "http://radio.example/stream".to_string(),
+            track.to_string_lossy().to_string(),
+            pls.to_string_lossy().to_string(),
+            temp_dir
+                .path()
+                .join("missing.mp3")
+                .to_string_lossy()
+                .to_string(),
+        ];
+        assert_eq!(
+            expand_arguments(&arguments),
+            vec![
+                PathBuf::from("http://radio.example/stream"),
+                track,
+                PathBuf::from("https://radio.example/live"),
+            ]
+        );
+    }
+
     #[test]
     fn test_load_songs_from_file_single_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -1,7 +1,13 @@
-use anyhow::Result;
-use std::fs::File;
-use std::path::Path;
-use symphonia::core::formats::FormatOptions;
+use crate::chapters::{self, Chapter};
+use crate::cue::{self, CueSheet, CueTrack};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use symphonia::core::codecs::{CODEC_TYPE_NULL, CODEC_TYPE_OPUS, CodecType};
+use symphonia::core::formats::{FormatOptions, FormatReader, Track};
 use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
 use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
 use symphonia::core::probe::Hint;
@@ -17,12 +23,202 @@ pub struct TrackMetadata {
     pub track_number: Option<String>,
     pub disc_number: Option<String>,
     pub duration_secs: Option<u64>,
-    pub cover_art: Option<Vec<u8>>,
+    /// Encoded image, shared so copies of the metadata stay cheap
+    pub cover_art: Option<Arc<[u8]>>,
+    pub replaygain: ReplayGain,
+    /// Every audio stream in the container, in container order
+    pub streams: Vec<AudioStream>,
+    /// Stream played when none was picked for the file
+    pub default_stream: Option<u32>,
+    /// Chapter markers, in playback order
+    pub chapters: Vec<Chapter>,
+}
+
+/// An audio stream of a container, such as one language of a multi-language MKA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioStream {
+    pub id: u32,
+    pub codec: String,
+    pub language: Option<String>,
+    pub channels: Option<u16>,
+}
+
+impl AudioStream {
+    fn from_track(track: &Track) -> Self {
+        Self {
+            id: track.id,
+            codec: codec_name(track.codec_params.codec),
+            language: track.language.clone().filter(|l| l != "und"),
+            channels: track.codec_params.channels.map(|c| c.count() as u16),
+        }
+    }
+
+    /// Short description such as "aac, eng, 6 ch".
+    pub fn describe(&self) -> String {
+        let mut parts = vec![self.codec.clone()];
+        parts.extend(self.language.clone());
+        parts.extend(self.channels.map(|c| format!("{c} ch")));
+        parts.join(", ")
+    }
+}
+
+fn codec_name(codec: CodecType) -> String {
+    if codec == CODEC_TYPE_OPUS {
+        return String::from("opus");
+    }
+    symphonia::default::get_codecs()
+        .get_codec(codec)
+        .map_or_else(|| String::from("unknown"), |d| d.short_name.to_string())
+}
+
+fn is_audio(track: &Track) -> bool {
+    track.codec_params.codec != CODEC_TYPE_NULL
+}
+
+/// Track to play from `format`: `preferred` when the container has such an audio track,
+/// otherwise the default track if it is audio, otherwise the first audio track. Video
+/// files often list their picture first.
+pub fn select_audio_track(format: &dyn FormatReader, preferred: Option<u32>) -> Option<&Track> {
+    let tracks = format.tracks();
+    preferred
+        .and_then(|id| tracks.iter().find(|t| t.id == id && is_audio(t)))
+        .or_else(|| format.default_track().filter(|t| is_audio(t)))
+        .or_else(|| tracks.iter().find(|t| is_audio(t)))
+}
+
+/// Audio stream picked for each file, kept across sessions.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamChoices {
+    choices: BTreeMap<PathBuf, u32>,
+}
+
+impl StreamChoices {
+    pub fn get(&self, path: &Path) -> Option<u32> {
+        self.choices.get(path).copied()
+    }
+
+    pub fn set(&mut self, path: &Path, stream: u32) {
+        self.choices.insert(path.to_path_buf(), stream);
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create data directory {}", parent.display()))?;
+        }
+
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write stream choices at {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if path.exists() {
+            let content = fs::read_to_string(&path)?;
+            Ok(serde_json::from_str(&content)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    fn path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("impulse")
+            .join("streams.json")
+    }
+}
+
+/// Replay gain values read from a track's tags. Gains are in dB, peaks are linear.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    /// Picks up REPLAYGAIN_* values from Vorbis comments, ID3 TXXX frames and MP4
+    /// freeform atoms. The latter two are matched by name when symphonia does not map
+    /// them to a standard key (lowercase TXXX descriptions, iTunes freeform atoms).
+    pub fn read_tags(&mut self, tags: &[Tag]) {
+        for tag in tags {
+            let field = match tag.std_key {
+                Some(StandardTagKey::ReplayGainTrackGain) => &mut self.track_gain,
+                Some(StandardTagKey::ReplayGainTrackPeak) => &mut self.track_peak,
+                Some(StandardTagKey::ReplayGainAlbumGain) => &mut self.album_gain,
+                Some(StandardTagKey::ReplayGainAlbumPeak) => &mut self.album_peak,
+                _ => {
+                    let name = tag.key.rsplit(':').next().unwrap_or_default();
+                    match name.to_ascii_uppercase().as_str() {
+                        "REPLAYGAIN_TRACK_GAIN" => &mut self.track_gain,
+                        "REPLAYGAIN_TRACK_PEAK" => &mut self.track_peak,
+                        "REPLAYGAIN_ALBUM_GAIN" => &mut self.album_gain,
+                        "REPLAYGAIN_ALBUM_PEAK" => &mut self.album_peak,
+                        _ => continue,
+                    }
+                }
+            };
+
+            if let Some(value) = Self::parse_value(&tag.value.to_string()) {
+                *field = Some(value);
+            }
+        }
+    }
+
+    // Accepts values such as "-6.54 dB", "+1.20dB" or "0.988547"
+    fn parse_value(value: &str) -> Option<f32> {
+        let value = value.trim();
+        let number = value
+            .strip_suffix("dB")
+            .or_else(|| value.strip_suffix("db"))
+            .or_else(|| value.strip_suffix("DB"))
+            .unwrap_or(value)
+            .trim();
+        number.parse::<f32>().ok().filter(|v| v.is_finite())
+    }
+
+    /// Gain in dB to apply, preferring album or track values and falling back to the other.
+    pub fn gain_db(&self, prefer_album: bool) -> Option<f32> {
+        if prefer_album {
+            self.album_gain.or(self.track_gain)
+        } else {
+            self.track_gain.or(self.album_gain)
+        }
+    }
+
+    /// Linear factor to scale samples by, including `preamp_db`. The factor is capped so
+    /// that the tagged peak never exceeds full scale. Untagged tracks play unchanged.
+    pub fn factor(&self, prefer_album: bool, preamp_db: f32) -> f32 {
+        let Some(gain_db) = self.gain_db(prefer_album) else {
+            return 1.0;
+        };
+
+        let factor = 10f32.powf((gain_db + preamp_db) / 20.0);
+        let peak = if prefer_album && self.album_gain.is_some() {
+            self.album_peak.or(self.track_peak)
+        } else {
+            self.track_peak.or(self.album_peak)
+        };
+
+        match peak {
+            Some(peak) if peak > 0.0 => factor.min(1.0 / peak),
+            _ => factor,
+        }
+    }
 }
 
 impl TrackMetadata {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
+        if let Some((sheet, track)) = cue::resolve(path) {
+            return Self::from_cue_track(&sheet, &track);
+        }
+
         let file = File::open(path)?;
         let mss = MediaSourceStream::new(Box::new(file), MediaSourceStreamOptions::default());
 
@@ -41,10 +237,20 @@ impl TrackMetadata {
             },
         )?;
 
-        let mut metadata = TrackMetadata::default();
+        let mut metadata = TrackMetadata {
+            streams: probed
+                .format
+                .tracks()
+                .iter()
+                .filter(|t| is_audio(t))
+                .map(AudioStream::from_track)
+                .collect(),
+            ..TrackMetadata::default()
+        };
 
         // Get duration if available
-        if let Some(track) = probed.format.default_track() {
+        if let Some(track) = select_audio_track(&*probed.format, None) {
+            metadata.default_stream = Some(track.id);
             if let Some(time_base) = track.codec_params.time_base {
                 if let Some(n_frames) = track.codec_params.n_frames {
                     let duration_secs = time_base.calc_time(n_frames).seconds;
@@ -70,9 +276,38 @@ impl TrackMetadata {
 
         // If no embedded cover art, look for external image files
         if metadata.cover_art.is_none() {
-            metadata.cover_art = Self::find_external_cover_art(path);
+            metadata.cover_art = Self::find_external_cover_art(path).map(Arc::from);
         }
 
+        metadata.chapters = chapters::read(path).unwrap_or_default();
+
+        Ok(metadata)
+    }
+
+    // Tags of the image file, overridden by what the sheet says about the track
+    fn from_cue_track(sheet: &CueSheet, track: &CueTrack) -> Result<Self> {
+        let mut metadata = Self::from_file(&track.file)?;
+        let album_artist = sheet.performer.clone().or(metadata.album_artist.take());
+
+        metadata.title = track.title.clone().or(metadata.title.take());
+        metadata.artist = track
+            .performer
+            .clone()
+            .or_else(|| album_artist.clone())
+            .or(metadata.artist.take());
+        metadata.album = sheet.title.clone().or(metadata.album.take());
+        metadata.album_artist = album_artist;
+        metadata.year = sheet.date.clone().or(metadata.year.take());
+        metadata.genre = sheet.genre.clone().or(metadata.genre.take());
+        metadata.track_number = Some(track.number.to_string());
+        metadata.duration_secs = match track.length() {
+            Some(length) => Some(length.as_secs()),
+            None => metadata
+                .duration_secs
+                .map(|total| total.saturating_sub(track.start_time().as_secs())),
+        };
+        // The sheet already splits the image, its chapters would not line up
+        metadata.chapters.clear();
         Ok(metadata)
     }
 
@@ -96,12 +331,14 @@ impl TrackMetadata {
                 }
             }
         }
+
+        self.replaygain.read_tags(tags);
     }
 
     fn read_visuals(&mut self, visuals: &[symphonia::core::meta::Visual]) {
         // Get the first visual (usually the cover art)
         if let Some(visual) = visuals.first() {
-            self.cover_art = Some(visual.data.to_vec());
+            self.cover_art = Some(Arc::from(&visual.data[..]));
         }
     }
 
@@ -167,6 +404,19 @@ impl TrackMetadata {
         None
     }
 
+    /// True when both tracks carry the same album tag (and album artist, when both have one).
+    pub fn is_same_album(&self, other: &TrackMetadata) -> bool {
+        let same_album = matches!(
+            (&self.album, &other.album),
+            (Some(a), Some(b)) if !a.trim().is_empty() && a == b
+        );
+        let same_artist = match (&self.album_artist, &other.album_artist) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        same_album && same_artist
+    }
+
     pub fn format_duration(&self) -> String {
         match self.duration_secs {
             Some(secs) => {
@@ -184,3 +434,124 @@ impl TrackMetadata {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn album_track(album: &str, album_artist: Option<&str>) -> TrackMetadata {
+        TrackMetadata {
+            album: Some(album.to_string()),
+            album_artist: album_artist.map(str::to_string),
+            ..TrackMetadata::default()
+        }
+    }
+
+    fn tag(std_key: Option<StandardTagKey>, key: &str, value: &str) -> Tag {
+        Tag::new(std_key, key, symphonia::core::meta::Value::from(value))
+    }
+
+    #[test]
+    fn test_replaygain_from_standard_keys() {
+        let mut replaygain = ReplayGain::default();
+        replaygain.read_tags(&[
+            tag(
+                Some(StandardTagKey::ReplayGainTrackGain),
+                "REPLAYGAIN_TRACK_GAIN",
+                "-6.54 dB",
+            ),
+            tag(
+                Some(StandardTagKey::ReplayGainTrackPeak),
+                "REPLAYGAIN_TRACK_PEAK",
+                "0.988547",
+            ),
+            tag(
+                Some(StandardTagKey::ReplayGainAlbumGain),
+                "REPLAYGAIN_ALBUM_GAIN",
+                "+1.20dB",
+            ),
+        ]);
+
+        assert_eq!(replaygain.track_gain, Some(-6.54));
+        assert_eq!(replaygain.track_peak, Some(0.988_547));
+        assert_eq!(replaygain.album_gain, Some(1.2));
+        assert_eq!(replaygain.album_peak, None);
+    }
+
+    #[test]
+    fn test_replaygain_from_unmapped_keys() {
+        let mut replaygain = ReplayGain::default();
+        replaygain.read_tags(&[
+            tag(None, "TXXX:replaygain_track_gain", "-3.00 dB"),
+            tag(None, "com.apple.iTunes:replaygain_album_gain", "-4.50 dB"),
+            tag(None, "TXXX:SOMETHING_ELSE", "1.0"),
+        ]);
+
+        assert_eq!(replaygain.track_gain, Some(-3.0));
+        assert_eq!(replaygain.album_gain, Some(-4.5));
+    }
+
+    #[test]
+    fn test_replaygain_factor() {
+        let replaygain = ReplayGain {
+            track_gain: Some(-6.0),
+            track_peak: Some(0.5),
+            album_gain: Some(6.0),
+            album_peak: Some(0.9),
+        };
+
+        assert!((replaygain.factor(false, 0.0) - 0.501).abs() < 1e-3);
+        // +6 dB would push the 0.9 peak over full scale, so it gets capped
+        assert!((replaygain.factor(true, 0.0) - 1.0 / 0.9).abs() < 1e-6);
+        assert_eq!(ReplayGain::default().factor(true, 6.0), 1.0);
+    }
+
+    #[test]
+    fn test_describe_audio_stream() {
+        let stream = AudioStream {
+            id: 2,
+            codec: String::from("aac"),
+            language: Some(String::from("eng")),
+            channels: Some(6),
+        };
+        assert_eq!(stream.describe(), "aac, eng, 6 ch");
+        assert_eq!(
+            AudioStream {
+                language: None,
+                channels: None,
+                ..stream
+            }
+            .describe(),
+            "aac"
+        );
+    }
+
+    #[test]
+    fn test_stream_choices_round_trip() {
+        let mut choices = StreamChoices::default();
+        choices.set(Path::new("/video/film.mkv"), 3);
+        choices.set(Path::new("/video/film.mkv"), 2);
+
+        let json = serde_json::to_string(&choices).unwrap();
+        let restored: StreamChoices = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get(Path::new("/video/film.mkv")), Some(2));
+        assert_eq!(restored.get(Path::new("/video/other.mkv")), None);
+    }
+
+    #[test]
+    fn test_is_same_album() {
+        let a = album_track("Blue", Some("Joni Mitchell"));
+        let b = album_track("Blue", Some("Joni Mitchell"));
+        assert!(a.is_same_album(&b));
+        assert!(a.is_same_album(&album_track("Blue", None)));
+        assert!(!a.is_same_album(&album_track("Blue", Some("Weezer"))));
+        assert!(!a.is_same_album(&album_track("Court and Spark", None)));
+    }
+
+    #[test]
+    fn test_is_same_album_requires_album_tag() {
+        let untagged = TrackMetadata::default();
+        assert!(!untagged.is_same_album(&TrackMetadata::default()));
+        assert!(!album_track("", None).is_same_album(&album_track("", None)));
+    }
+}
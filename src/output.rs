@@ -0,0 +1,469 @@
+use crate::logger;
+use anyhow::{Context, Result, anyhow, bail};
+use rodio::Sink;
+use rodio::cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rodio::cpal::{self, FromSample, Sample, SampleFormat, SizedSample};
+use rodio::dynamic_mixer::{self, DynamicMixer, DynamicMixerController};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Sample rate written by the file and pipe backends.
+pub const OUTPUT_SAMPLE_RATE: u32 = 48000;
+/// Channel count written by the file and pipe backends.
+pub const OUTPUT_CHANNELS: u16 = 2;
+
+// Samples are handed to the writer in 10 ms chunks
+const CHUNK_FRAMES: usize = OUTPUT_SAMPLE_RATE as usize / 100;
+
+/// Where the decoded audio goes, written as `device`, `null`, `wav:<path>` or
+/// `fifo:<path>` in the config and on the command line.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum OutputBackend {
+    /// The default sound card
+    #[default]
+    Device,
+    /// Discards the audio, while still playing it back in real time
+    Null,
+    /// Records the audio to a 16-bit WAV file
+    Wav(PathBuf),
+    /// Writes raw 16-bit little-endian stereo PCM to a named pipe, e.g. a Snapcast fifo
+    Fifo(PathBuf),
+}
+
+impl FromStr for OutputBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.split_once(':') {
+            Some(("wav", path)) if !path.is_empty() => Ok(OutputBackend::Wav(path.into())),
+            Some(("fifo", path)) if !path.is_empty() => Ok(OutputBackend::Fifo(path.into())),
+            _ => match value {
+                "device" => Ok(OutputBackend::Device),
+                "null" => Ok(OutputBackend::Null),
+                _ => bail!(
+                    "Unknown output '{value}', expected device, null, wav:<path> or fifo:<path>"
+                ),
+            },
+        }
+    }
+}
+
+impl fmt::Display for OutputBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputBackend::Device => write!(f, "device"),
+            OutputBackend::Null => write!(f, "null"),
+            OutputBackend::Wav(path) => write!(f, "wav:{}", path.display()),
+            OutputBackend::Fifo(path) => write!(f, "fifo:{}", path.display()),
+        }
+    }
+}
+
+impl TryFrom<String> for OutputBackend {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<OutputBackend> for String {
+    fn from(backend: OutputBackend) -> Self {
+        backend.to_string()
+    }
+}
+
+/// An opened output. Sinks created from it are mixed as f32 and only converted to the
+/// backend's sample format at the very end.
+pub struct Output {
+    controller: Arc<DynamicMixerController<f32>>,
+    // Pulls from the mixer for the sound card; the other backends use a writer thread
+    _stream: Option<cpal::Stream>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Output {
+    pub fn open(backend: &OutputBackend) -> Result<Self> {
+        let writer = match backend {
+            OutputBackend::Device => {
+                let (controller, stream) = open_device()?;
+                return Ok(Self {
+                    controller,
+                    _stream: Some(stream),
+                    stop: Arc::new(AtomicBool::new(false)),
+                });
+            }
+            OutputBackend::Null => Writer::Null,
+            OutputBackend::Wav(path) => {
+                Writer::Wav(WavFile::create(path).with_context(|| {
+                    format!("Failed to create WAV output at {}", path.display())
+                })?)
+            }
+            OutputBackend::Fifo(path) => {
+                if !path.exists() {
+                    bail!(
+                        "Output pipe {} does not exist (create it with mkfifo)",
+                        path.display()
+                    );
+                }
+                Writer::Fifo {
+                    path: path.clone(),
+                    file: None,
+                }
+            }
+        };
+
+        let (controller, mixer) = dynamic_mixer::mixer(OUTPUT_CHANNELS, OUTPUT_SAMPLE_RATE);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        thread::Builder::new()
+            .name("impulse-output".to_string())
+            .spawn(move || run_writer(mixer, writer, &thread_stop))
+            .context("Failed to start the output thread")?;
+
+        Ok(Self {
+            controller,
+            _stream: None,
+            stop,
+        })
+    }
+
+    pub fn new_sink(&self) -> Result<Sink> {
+        let (sink, queue) = Sink::new_idle();
+        self.controller.add(queue);
+        Ok(sink)
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        // The writer thread is not joined: it may be blocked opening a pipe nobody reads
+        self.stop.store(true, Ordering::Release);
+    }
+}
+
+// Opens the default sound card, falling back to the other output devices
+fn open_device() -> Result<(Arc<DynamicMixerController<f32>>, cpal::Stream)> {
+    let host = cpal::default_host();
+    let devices = host
+        .default_output_device()
+        .into_iter()
+        .chain(host.output_devices().into_iter().flatten());
+
+    let mut error = anyhow!("No audio output device found");
+    for device in devices {
+        match open_stream(&device) {
+            Ok(opened) => return Ok(opened),
+            Err(e) => error = e,
+        }
+    }
+    Err(error.context("Failed to create audio output stream"))
+}
+
+fn open_stream(device: &cpal::Device) -> Result<(Arc<DynamicMixerController<f32>>, cpal::Stream)> {
+    let config = device.default_output_config()?;
+    let (controller, mixer) = dynamic_mixer::mixer(config.channels(), config.sample_rate().0);
+    let stream_config = config.config();
+
+    // Integer formats up to 16 bits get dithered; wider ones are below f32 precision
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(device, &stream_config, mixer, None),
+        SampleFormat::F64 => build_stream::<f64>(device, &stream_config, mixer, None),
+        SampleFormat::I32 => build_stream::<i32>(device, &stream_config, mixer, None),
+        SampleFormat::U32 => build_stream::<u32>(device, &stream_config, mixer, None),
+        SampleFormat::I16 => build_stream::<i16>(device, &stream_config, mixer, Some(16)),
+        SampleFormat::U16 => build_stream::<u16>(device, &stream_config, mixer, Some(16)),
+        SampleFormat::I8 => build_stream::<i8>(device, &stream_config, mixer, Some(8)),
+        SampleFormat::U8 => build_stream::<u8>(device, &stream_config, mixer, Some(8)),
+        format => bail!("Unsupported output sample format {format}"),
+    }?;
+    stream.play()?;
+    Ok((controller, stream))
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut mixer: DynamicMixer<f32>,
+    dither_bits: Option<u32>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let mut dither = dither_bits.map(Dither::new);
+    let stream = device.build_output_stream::<T, _, _>(
+        config,
+        move |data, _| {
+            for out in data.iter_mut() {
+                let sample = match (mixer.next(), dither.as_mut()) {
+                    (Some(sample), Some(dither)) => dither.quantize(sample),
+                    (Some(sample), None) => sample.clamp(-1.0, 1.0),
+                    (None, _) => 0.0,
+                };
+                *out = T::from_sample(sample);
+            }
+        },
+        |e| logger::log(&format!("Audio output stream error: {e}")),
+        None,
+    )?;
+    Ok(stream)
+}
+
+// Pulls the mixed audio in real time and hands it to the writer
+fn run_writer(mut mixer: DynamicMixer<f32>, mut writer: Writer, stop: &AtomicBool) {
+    let chunk_len = CHUNK_FRAMES * OUTPUT_CHANNELS as usize;
+    let mut chunk = Vec::with_capacity(chunk_len);
+    let mut dither = Dither::new(16);
+    // When the current stretch of audio started, and how many frames it has had since
+    let mut clock: Option<(Instant, u64)> = None;
+    // Only the first of a run of failed writes is logged
+    let mut failing = false;
+
+    while !stop.load(Ordering::Acquire) {
+        chunk.clear();
+        chunk.extend(
+            mixer
+                .by_ref()
+                .take(chunk_len)
+                .map(|sample| i16::from_sample(dither.quantize(sample))),
+        );
+
+        if chunk.is_empty() {
+            // Nothing is playing; the clock restarts with the next sink
+            clock = None;
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+
+        match writer.write(&chunk) {
+            Ok(()) => failing = false,
+            Err(e) if !failing => {
+                logger::log(&format!("Failed to write audio output: {e}"));
+                failing = true;
+            }
+            Err(_) => {}
+        }
+
+        let (start, frames) = clock.get_or_insert_with(|| (Instant::now(), 0));
+        *frames += (chunk.len() / OUTPUT_CHANNELS as usize) as u64;
+        let due = *start + Duration::from_secs_f64(*frames as f64 / f64::from(OUTPUT_SAMPLE_RATE));
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// TPDF dither for quantizing float samples to integers of a given bit depth.
+pub struct Dither {
+    // Steps per unit, matching the float to integer conversion of the output
+    scale: f32,
+    rng: u32,
+}
+
+impl Dither {
+    pub fn new(bits: u32) -> Self {
+        Self {
+            scale: 2f32.powi(bits as i32 - 1),
+            rng: 0x9E37_79B9,
+        }
+    }
+
+    /// Adds triangular noise spanning one step either way, then rounds `sample` to the
+    /// nearest step. The result is still a float, exactly convertible to the integer.
+    pub fn quantize(&mut self, sample: f32) -> f32 {
+        let noise = self.uniform() - self.uniform();
+        let stepped = (sample * self.scale + noise)
+            .round()
+            .clamp(-self.scale, self.scale - 1.0);
+        stepped / self.scale
+    }
+
+    // Uniform in 0.0..1.0 from a xorshift generator, cheap enough for the audio callback
+    fn uniform(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+enum Writer {
+    Null,
+    Wav(WavFile),
+    Fifo { path: PathBuf, file: Option<File> },
+}
+
+impl Writer {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        match self {
+            Writer::Null => Ok(()),
+            Writer::Wav(wav) => wav.write(samples),
+            Writer::Fifo { path, file } => {
+                // Opening blocks until a reader shows up
+                let pipe = match file {
+                    Some(pipe) => pipe,
+                    None => file.insert(OpenOptions::new().write(true).open(&*path)?),
+                };
+                let result = pipe.write_all(&pcm_bytes(samples));
+                if result.is_err() {
+                    // The reader went away; reopen on the next chunk
+                    *file = None;
+                }
+                result
+            }
+        }
+    }
+}
+
+fn pcm_bytes(samples: &[i16]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+// 16-bit PCM WAV file whose header is kept up to date after every write, so the
+// recording stays readable however the player exits
+struct WavFile {
+    file: File,
+    data_len: u32,
+}
+
+impl WavFile {
+    const HEADER_LEN: u32 = 44;
+
+    fn create(path: &std::path::Path) -> io::Result<Self> {
+        let mut wav = Self {
+            file: File::create(path)?,
+            data_len: 0,
+        };
+        let block_align = OUTPUT_CHANNELS * 2;
+
+        let mut header = Vec::with_capacity(Self::HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(Self::HEADER_LEN - 8).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&OUTPUT_CHANNELS.to_le_bytes());
+        header.extend_from_slice(&OUTPUT_SAMPLE_RATE.to_le_bytes());
+        header.extend_from_slice(&(OUTPUT_SAMPLE_RATE * u32::from(block_align)).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        wav.file.write_all(&header)?;
+        Ok(wav)
+    }
+
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let bytes = pcm_bytes(samples);
+        self.file.write_all(&bytes)?;
+        self.data_len = self.data_len.saturating_add(bytes.len() as u32);
+
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(Self::HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_output_backend() {
+        assert_eq!(
+            "device".parse::<OutputBackend>().unwrap(),
+            OutputBackend::Device
+        );
+        assert_eq!(
+            "null".parse::<OutputBackend>().unwrap(),
+            OutputBackend::Null
+        );
+        assert_eq!(
+            "wav:/tmp/out.wav".parse::<OutputBackend>().unwrap(),
+            OutputBackend::Wav(PathBuf::from("/tmp/out.wav"))
+        );
+        assert_eq!(
+            "fifo:/tmp/snapfifo".parse::<OutputBackend>().unwrap(),
+            OutputBackend::Fifo(PathBuf::from("/tmp/snapfifo"))
+        );
+        assert!("wav:".parse::<OutputBackend>().is_err());
+        assert!("speakers".parse::<OutputBackend>().is_err());
+
+        let backend = OutputBackend::Fifo(PathBuf::from("/tmp/snapfifo"));
+        assert_eq!(
+            backend.to_string().parse::<OutputBackend>().unwrap(),
+            backend
+        );
+    }
+
+    #[test]
+    fn test_wav_output_records_in_real_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("capture.wav");
+        let output = Output::open(&OutputBackend::Wav(path.clone())).unwrap();
+
+        let sink = output.new_sink().unwrap();
+        let started = Instant::now();
+        // 100 ms of stereo audio at the output rate
+        sink.append(SamplesBuffer::new(2, 48000, vec![0.5f32; 9600]));
+        sink.sleep_until_end();
+        assert!(started.elapsed() >= Duration::from_millis(80));
+        drop(sink);
+
+        // Let the writer flush the last chunk
+        thread::sleep(Duration::from_millis(50));
+        let bytes = std::fs::read(&path).unwrap();
+        let data_len = u32::from_le_bytes(bytes[40..44].try_into().unwrap());
+        assert_eq!(data_len as usize, bytes.len() - 44);
+        assert!(data_len >= 9600 * 2);
+        let first = i16::from_le_bytes([bytes[44], bytes[45]]);
+        assert!((16383..=16385).contains(&first));
+    }
+
+    #[test]
+    fn test_dither_stays_within_one_step() {
+        let mut dither = Dither::new(16);
+        let sample = 1000.25 / 32768.0;
+        let quantized: Vec<f32> = (0..10000).map(|_| dither.quantize(sample)).collect();
+
+        for value in &quantized {
+            let steps = value * 32768.0;
+            assert_eq!(steps.fract(), 0.0);
+            assert!((999.0..=1002.0).contains(&steps));
+        }
+        // The noise averages out, keeping the detail below one step
+        let mean = quantized.iter().map(|v| v * 32768.0).sum::<f32>() / 10000.0;
+        assert!((mean - 1000.25).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_dither_clamps_to_the_integer_range() {
+        let mut dither = Dither::new(16);
+        for _ in 0..100 {
+            assert_eq!(i16::from_sample(dither.quantize(2.0)), i16::MAX);
+            assert_eq!(i16::from_sample(dither.quantize(-2.0)), i16::MIN);
+        }
+    }
+
+    #[test]
+    fn test_fifo_output_requires_existing_pipe() {
+        let temp_dir = TempDir::new().unwrap();
+        let missing = OutputBackend::Fifo(temp_dir.path().join("missing"));
+        assert!(Output::open(&missing).is_err());
+    }
+}
@@ -1,81 +1,641 @@
+use crate::channels::{ChannelControl, ChannelMixer, ChannelSettings};
+use crate::cue::{self, CueTrack};
+use crate::dynamics::{Dynamics, DynamicsControl, DynamicsPreset};
+use crate::equalizer::{Bands, EqControl, Equalizer};
 use crate::logger;
-use crate::metadata::TrackMetadata;
+use crate::metadata::{self, ReplayGain, StreamChoices, TrackMetadata};
+use crate::output::{Output, OutputBackend};
+use crate::radio::{self, HttpStream, StationInfo, StreamGate};
+use crate::resume::{ResumePolicy, ResumePositions};
+use crate::visualizer::{AudioTap, Tap};
 use anyhow::{Context, Result};
-use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+use rodio::{Sink, Source};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak, mpsc};
+use std::thread;
+use std::time::Duration;
 use symphonia::core::audio::SampleBuffer;
 use symphonia::core::codecs::{CodecRegistry, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
 use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+use symphonia::core::io::{MediaSource, MediaSourceStream, MediaSourceStreamOptions};
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
 use symphonia_adapter_libopus::OpusDecoder;
 
-// MUTEX LOCKING ORDER:
-// To prevent deadlocks, when multiple mutexes need to be locked, they should be acquired
-// in the following order:
-// 1. paused_elapsed
-// 2. playback_start_time
-// 3. sink
-// 4. current_track
-// 5. current_metadata
+/// Decode errors a track may have before it is cut short, unless configured otherwise.
+pub const DEFAULT_DECODE_ERROR_LIMIT: u32 = 10;
+/// Longest crossfade accepted from the config or the `:crossfade` command.
+pub const MAX_CROSSFADE_SECS: f32 = 30.0;
+/// Largest replay gain preamp, in dB either way, accepted from the config or `:rg`.
+pub const MAX_PREAMP_DB: f32 = 15.0;
 
-#[derive(Clone)]
+/// Shape of the volume ramps used when crossfading between tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum FadeCurve {
+    Linear,
+    #[default]
+    EqualPower,
+    SCurve,
+}
+
+impl FadeCurve {
+    /// Gains for the outgoing and incoming track at `t` (0.0 to 1.0) through the fade.
+    pub fn gains(self, t: f32) -> (f32, f32) {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => (1.0 - t, t),
+            FadeCurve::EqualPower => {
+                let angle = t * std::f32::consts::FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+            FadeCurve::SCurve => {
+                let incoming = t * t * (3.0 - 2.0 * t);
+                (1.0 - incoming, incoming)
+            }
+        }
+    }
+}
+
+/// Which replay gain values are applied while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+    /// Album gain for in-order playback, track gain in random mode
+    Auto,
+}
+
+impl ReplayGainMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ReplayGainMode::Off => "Off",
+            ReplayGainMode::Track => "Track",
+            ReplayGainMode::Album => "Album",
+            ReplayGainMode::Auto => "Auto",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "off" => Some(ReplayGainMode::Off),
+            "track" => Some(ReplayGainMode::Track),
+            "album" => Some(ReplayGainMode::Album),
+            "auto" => Some(ReplayGainMode::Auto),
+            _ => None,
+        }
+    }
+
+    /// Resolves `Auto` according to whether the queue is played in random order.
+    pub fn resolve(self, random: bool) -> Self {
+        match self {
+            ReplayGainMode::Auto if random => ReplayGainMode::Track,
+            ReplayGainMode::Auto => ReplayGainMode::Album,
+            mode => mode,
+        }
+    }
+}
+
+// ReplayGain settings shared with every decoded source. Sources compare the generation
+// once per packet and only lock the settings when it changed.
+#[derive(Default)]
+struct ReplayGainControl {
+    settings: Mutex<(ReplayGainMode, f32)>,
+    generation: AtomicU64,
+}
+
+/// A packet that failed to decode, reported from the audio thread.
+#[derive(Debug, Clone)]
+pub struct DecodeError {
+    pub path: PathBuf,
+    pub position: Duration,
+    pub message: String,
+    /// Errors in this track so far, this one included
+    pub count: u32,
+    /// Set when the track was cut short, after too many errors or an unreadable file
+    pub gave_up: bool,
+}
+
+// Decode errors waiting for the UI, and how many a track may have before it is skipped
+struct DecodeErrors {
+    pending: Mutex<Vec<DecodeError>>,
+    limit: AtomicU32,
+}
+
+impl Default for DecodeErrors {
+    fn default() -> Self {
+        Self {
+            pending: Mutex::new(Vec::new()),
+            limit: AtomicU32::new(DEFAULT_DECODE_ERROR_LIMIT),
+        }
+    }
+}
+
+/// Something that happened away from the thread owning the player, for it to pass to
+/// `Player::handle_notice`.
+#[derive(Debug)]
+pub enum Notice {
+    /// The chain moved on to the queued track, see `complete_transition`
+    Advanced,
+    /// The chain ran out of audio
+    Ended,
+    /// Tags of `path`, read for the current track or for the queued one
+    Metadata {
+        path: PathBuf,
+        metadata: Box<TrackMetadata>,
+        queued: bool,
+    },
+}
+
+/// Delivers notices to the thread owning the player. Called from the audio and
+/// metadata threads, so it must not block.
+pub type Notify = Arc<dyn Fn(Notice) + Send + Sync>;
+
+/// Decodes tracks and feeds them to the output. Everything here belongs to a single
+/// thread (see `engine`); the audio and metadata threads report back through `Notify`.
 pub struct Player {
-    sink: Arc<Mutex<Option<Sink>>>,
-    _stream: Rc<OutputStream>,
-    stream_handle: Arc<OutputStreamHandle>,
-    current_track: Arc<Mutex<Option<PathBuf>>>,
-    current_metadata: Arc<Mutex<Option<TrackMetadata>>>,
-    playback_start_time: Arc<Mutex<Option<SystemTime>>>,
-    paused_elapsed: Arc<Mutex<Duration>>,
+    sink: Option<Sink>,
+    output: Output,
+    chain: Option<Arc<ChainShared>>,
+    current_track: Option<PathBuf>,
+    current_metadata: Option<TrackMetadata>,
+    // Bumped whenever `current_metadata` is replaced
+    metadata_version: u64,
+    current_spec: Option<(u16, u32)>,
+    crossfade: (Duration, FadeCurve),
+    replaygain: Arc<ReplayGainControl>,
+    equalizer: Arc<EqControl>,
+    channels: Arc<ChannelControl>,
+    tap: Arc<AudioTap>,
+    dynamics: Arc<DynamicsControl>,
+    streams: StreamChoices,
+    errors: Arc<DecodeErrors>,
+    stations: Stations,
+    resume_policy: ResumePolicy,
+    resume_positions: ResumePositions,
+    metadata_jobs: mpsc::Sender<MetadataJob>,
+    notify: Notify,
+    // Bumped whenever another track becomes current, so tags nobody waits for are
+    // not read
+    generation: Arc<AtomicU64>,
 }
 
+// Tag reading handed to the metadata thread
+type MetadataJob = Box<dyn FnOnce() + Send>;
+
+// What the streams being decoded announce, by URL, for as long as they are open
+type Stations = Vec<(PathBuf, Weak<StationInfo>)>;
+
 impl Player {
-    pub fn new() -> Result<Self> {
-        let (stream, stream_handle) =
-            OutputStream::try_default().context("Failed to create audio output stream")?;
+    pub fn new(backend: &OutputBackend, notify: Notify) -> Result<Self> {
+        let output = Output::open(backend)?;
+
+        // Tags and cover art can take a while to read (large embedded images, network
+        // mounts), so they are read on their own thread, in the order asked for. The
+        // thread ends along with the player.
+        let (metadata_jobs, jobs) = mpsc::channel::<MetadataJob>();
+        thread::spawn(move || {
+            for job in jobs {
+                job();
+            }
+        });
 
         Ok(Self {
-            sink: Arc::new(Mutex::new(None)),
-            _stream: Rc::new(stream),
-            stream_handle: Arc::new(stream_handle),
-            current_track: Arc::new(Mutex::new(None)),
-            current_metadata: Arc::new(Mutex::new(None)),
-            playback_start_time: Arc::new(Mutex::new(None)),
-            paused_elapsed: Arc::new(Mutex::new(Duration::from_secs(0))),
+            sink: None,
+            output,
+            chain: None,
+            current_track: None,
+            current_metadata: None,
+            metadata_version: 0,
+            current_spec: None,
+            crossfade: (Duration::ZERO, FadeCurve::default()),
+            replaygain: Arc::new(ReplayGainControl::default()),
+            equalizer: Arc::new(EqControl::default()),
+            channels: Arc::new(ChannelControl::default()),
+            tap: Arc::new(AudioTap::default()),
+            dynamics: Arc::new(DynamicsControl::default()),
+            streams: StreamChoices::default(),
+            errors: Arc::new(DecodeErrors::default()),
+            stations: Vec::new(),
+            resume_policy: ResumePolicy::default(),
+            resume_positions: ResumePositions::default(),
+            metadata_jobs,
+            notify,
+            generation: Arc::new(AtomicU64::new(0)),
         })
     }
 
-    pub fn play(&self, path: &Path) -> Result<()> {
+    /// Plays `path`, from where it was left if it has a resume point. Audio starts right
+    /// away; `current_metadata` stays empty until the tags have been read, unless they
+    /// were already read while the track waited for gapless playback.
+    pub fn play(&mut self, path: &Path) -> Result<()> {
+        self.remember_position();
+
         // Use custom Symphonia decoder for all formats to ensure consistent seek support
-        let symphonia_source = Self::decode_symphonia(path)
-            .with_context(|| format!("Failed to decode audio file: {}", path.display()))?;
-        let source: Box<dyn Source<Item = i16> + Send> = Box::new(symphonia_source);
+        let symphonia_source = self.decode_resumed(path)?;
+        let spec = (symphonia_source.channels(), symphonia_source.sample_rate());
+
+        let metadata = self.preloaded_metadata(path);
+        let loaded = metadata.is_some();
+
+        // Skipping while something is audible fades over instead of cutting
+        if let Some(symphonia_source) = self.cut_in(path, symphonia_source, spec, metadata.as_ref())
+        {
+            self.start(path, symphonia_source, metadata, false)?;
+        } else {
+            self.set_current(path, metadata, spec);
+        }
+
+        if !loaded {
+            self.load_current_metadata(path);
+        }
+        Ok(())
+    }
+
+    fn set_current(&mut self, path: &Path, metadata: Option<TrackMetadata>, spec: (u16, u32)) {
+        if self.current_track.as_deref() != Some(path) {
+            self.generation.fetch_add(1, Ordering::Relaxed);
+        }
+        self.current_track = Some(path.to_path_buf());
+        self.set_metadata(metadata);
+        self.current_spec = Some(spec);
+    }
+
+    fn set_metadata(&mut self, metadata: Option<TrackMetadata>) {
+        self.current_metadata = metadata;
+        self.metadata_version += 1;
+    }
 
-        let sink = Sink::try_new(&self.stream_handle).context("Failed to create audio sink")?;
+    // Tags read for `path` while it was queued for gapless playback, if any
+    fn preloaded_metadata(&self, path: &Path) -> Option<TrackMetadata> {
+        let next = self.chain.as_ref()?.next.lock().unwrap();
+        next.as_ref()
+            .filter(|queued| queued.path == path)
+            .and_then(|queued| queued.metadata.clone())
+    }
+
+    // Reads the tags of the current track on the metadata thread, unless another track
+    // became current before it got to them
+    fn load_current_metadata(&self, path: &Path) {
+        let path = path.to_path_buf();
+        let generation = Arc::clone(&self.generation);
+        let expected = generation.load(Ordering::Relaxed);
+        let notify = Arc::clone(&self.notify);
+        self.read_metadata(move || {
+            if generation.load(Ordering::Relaxed) != expected {
+                return;
+            }
+            let metadata = read_tags(&path);
+            notify(Notice::Metadata {
+                path,
+                metadata,
+                queued: false,
+            });
+        });
+    }
 
+    // Reads the tags of the track queued in `chain`, unless it was dropped before the
+    // metadata thread got to it
+    fn load_next_metadata(&self, chain: &Arc<ChainShared>, path: &Path) {
+        let chain = Arc::clone(chain);
+        let path = path.to_path_buf();
+        let notify = Arc::clone(&self.notify);
+        self.read_metadata(move || {
+            let queued = chain
+                .next
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|queued| queued.path == path);
+            if !queued {
+                return;
+            }
+            let metadata = read_tags(&path);
+            notify(Notice::Metadata {
+                path,
+                metadata,
+                queued: true,
+            });
+        });
+    }
+
+    fn read_metadata(&self, job: impl FnOnce() + Send + 'static) {
+        // Only fails once the thread is gone, and then there is nobody left to tell
+        let _ = self.metadata_jobs.send(Box::new(job));
+    }
+
+    /// Takes in what the audio and metadata threads reported. Returns the track the
+    /// chain moved on to by itself, if it did.
+    pub fn handle_notice(&mut self, notice: Notice) -> Option<PathBuf> {
+        match notice {
+            Notice::Advanced => return self.complete_transition(),
+            Notice::Ended => {}
+            Notice::Metadata {
+                path,
+                metadata,
+                queued: false,
+            } => {
+                if self.current_track.as_ref() == Some(&path) {
+                    self.set_metadata(Some(*metadata));
+                }
+            }
+            Notice::Metadata {
+                path,
+                metadata,
+                queued: true,
+            } => self.attach_next_metadata(&path, *metadata),
+        }
+        None
+    }
+
+    // Hands the tags of the queued track to the chain, and settles whether to crossfade
+    // into it now that its album is known
+    fn attach_next_metadata(&mut self, path: &Path, metadata: TrackMetadata) {
+        // The chain may have moved on before the tags were read
+        if self.current_track.as_deref() == Some(path) && self.current_metadata.is_none() {
+            self.set_metadata(Some(metadata));
+            return;
+        }
+
+        let Some(chain) = self.chain.as_ref() else {
+            return;
+        };
+        let fade = self.fade_for(Some(&metadata));
+        let mut next = chain.next.lock().unwrap();
+        if let Some(queued) = next.as_mut().filter(|queued| queued.path == path) {
+            queued.metadata = Some(metadata);
+            queued.fade = fade;
+            chain.pending.store(true, Ordering::Release);
+        }
+    }
+
+    // Replaces whatever plays with `source`, cutting over right away
+    fn start(
+        &mut self,
+        path: &Path,
+        source: SymphoniaSource,
+        metadata: Option<TrackMetadata>,
+        paused: bool,
+    ) -> Result<()> {
+        let spec = (source.channels(), source.sample_rate());
+
+        // The chain lets the next queued track be appended to this same sink later on,
+        // so track changes stay sample-contiguous
+        let shared = Arc::new(ChainShared::with_notify(Arc::clone(&self.notify)));
+        let chain = TrackChain::new(source, Arc::clone(&shared));
+        let mixer = ChannelMixer::new(chain, Arc::clone(&self.channels));
+        // The visualizer sees the track before the equalizer and volume
+        let tap = Tap::new(mixer, Arc::clone(&self.tap));
+        let equalizer = Equalizer::new(tap, Arc::clone(&self.equalizer));
+        let source: Box<dyn Source<Item = f32> + Send> =
+            Box::new(Dynamics::new(equalizer, Arc::clone(&self.dynamics)));
+
+        let sink = self.output.new_sink()?;
+        if paused {
+            sink.pause();
+        }
         sink.append(source);
 
-        // Read metadata from the track
-        let metadata = TrackMetadata::from_file(path).ok();
+        self.sink = Some(sink);
+        self.chain = Some(shared);
+        self.set_current(path, metadata, spec);
 
-        *self.sink.lock().unwrap() = Some(sink);
-        *self.current_track.lock().unwrap() = Some(path.to_path_buf());
-        *self.current_metadata.lock().unwrap() = metadata;
-        *self.playback_start_time.lock().unwrap() = Some(SystemTime::now());
-        *self.paused_elapsed.lock().unwrap() = Duration::from_secs(0);
+        Ok(())
+    }
 
+    /// Plays audio stream `id` of the current file from the same position, and
+    /// remembers the choice for the next time the file plays. Drops any track
+    /// queued for gapless playback.
+    pub fn select_stream(&mut self, id: u32) -> Result<()> {
+        let Some(path) = self.current_track.clone() else {
+            return Ok(());
+        };
+        self.streams.set(&path, id);
+
+        let position = self.position();
+        let mut source = self.decode(&path)?;
+        if let Err(e) = source.try_seek(position) {
+            logger::log(&format!("Failed to seek after switching audio stream: {e}"));
+        }
+        let metadata = self.current_metadata.clone();
+        self.start(&path, source, metadata, self.is_paused())
+    }
+
+    pub fn set_stream_choices(&mut self, choices: StreamChoices) {
+        self.streams = choices;
+    }
+
+    /// Streams picked with `select_stream`, by file.
+    pub fn stream_choices(&self) -> &StreamChoices {
+        &self.streams
+    }
+
+    /// Decodes `path` ahead of time and hands it to the playing chain, so it starts on the
+    /// very next sample once the current track runs out. Passing `None` drops any track
+    /// queued earlier.
+    ///
+    /// Tracks whose channel count or sample rate differ from the current one are not
+    /// queued, since the output cannot change format mid-stream; they play after the
+    /// usual end-of-track detection instead.
+    pub fn queue_next(&mut self, path: Option<&Path>) -> Result<()> {
+        let Some(chain) = self.chain.clone() else {
+            return Ok(());
+        };
+
+        // Streams are only opened when they start, so they do not buffer stale audio
+        let Some(path) = path.filter(|path| !radio::is_url(path)) else {
+            *chain.next.lock().unwrap() = None;
+            chain.pending.store(true, Ordering::Release);
+            return Ok(());
+        };
+
+        let symphonia_source = self.decode_resumed(path)?;
+        let spec = (symphonia_source.channels(), symphonia_source.sample_rate());
+
+        if self.current_spec != Some(spec) {
+            logger::log(&format!(
+                "Not queuing {} for gapless playback: output format differs",
+                path.display()
+            ));
+            *chain.next.lock().unwrap() = None;
+            chain.pending.store(true, Ordering::Release);
+            return Ok(());
+        }
+
+        // Crossfades until the tags tell it continues the same album
+        let fade = self.fade_for(None);
+
+        *chain.next.lock().unwrap() = Some(QueuedTrack {
+            path: path.to_path_buf(),
+            metadata: None,
+            spec,
+            fade,
+            source: Some(symphonia_source),
+        });
+        chain.pending.store(true, Ordering::Release);
+        self.load_next_metadata(&chain, path);
         Ok(())
     }
 
-    fn decode_symphonia(path: &Path) -> Result<impl Source<Item = i16> + Send + use<>> {
-        // Create a media source stream from the file with seek support
+    /// Sets the replay gain mode (already resolved, see `ReplayGainMode::resolve`) and
+    /// preamp in dB. Takes effect on the playing track within one packet.
+    pub fn set_replaygain(&self, mode: ReplayGainMode, preamp_db: f32) {
+        *self.replaygain.settings.lock().unwrap() = (mode, preamp_db);
+        self.replaygain.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Sets the equalizer band gains in dB, or bypasses it with `None`. Applies to the
+    /// playing track right away.
+    pub fn set_equalizer(&self, bands: Option<Bands>) {
+        self.equalizer.set(bands);
+    }
+
+    pub fn set_crossfade(&mut self, secs: f32, curve: FadeCurve) {
+        // NaN turns the crossfade off, and anything out of range would panic below
+        let secs = if secs.is_nan() {
+            0.0
+        } else {
+            secs.clamp(0.0, MAX_CROSSFADE_SECS)
+        };
+        self.crossfade = (Duration::from_secs_f32(secs), curve);
+    }
+
+    // Crossfade to use before `next`, see `fade_between`
+    fn fade_for(&self, next: Option<&TrackMetadata>) -> Option<Fade> {
+        fade_between(self.crossfade, self.current_metadata.as_ref(), next)
+    }
+
+    // Hands the new track to the playing chain so it fades in over the current one.
+    // Gives the source back when a hard cut is needed instead.
+    fn cut_in(
+        &self,
+        path: &Path,
+        source: SymphoniaSource,
+        spec: (u16, u32),
+        metadata: Option<&TrackMetadata>,
+    ) -> Option<SymphoniaSource> {
+        if !self.is_playing() || self.current_spec != Some(spec) {
+            return Some(source);
+        }
+        let Some(fade) = self.fade_for(metadata) else {
+            return Some(source);
+        };
+        let Some(chain) = self.chain.as_ref() else {
+            return Some(source);
+        };
+
+        *chain.next.lock().unwrap() = None;
+        *chain.cut_in.lock().unwrap() = Some(QueuedTrack {
+            path: path.to_path_buf(),
+            metadata: None,
+            spec,
+            fade: Some(fade),
+            source: Some(source),
+        });
+        chain.pending.store(true, Ordering::Release);
+        None
+    }
+
+    /// Returns true once the playing chain has moved on to the queued track.
+    /// `current_track` and `current_metadata` still describe the previous track
+    /// until `complete_transition` is called.
+    pub fn has_pending_transition(&self) -> bool {
+        self.chain
+            .as_ref()
+            .is_some_and(|chain| chain.started.lock().unwrap().is_some())
+    }
+
+    /// Promotes the queued track that the chain has started playing to the current track.
+    pub fn complete_transition(&mut self) -> Option<PathBuf> {
+        let started = self.chain.as_ref()?.started.lock().unwrap().take()?;
+
+        // The previous track played out, so it starts over next time
+        if let Some(previous) = &self.current_track {
+            self.resume_positions.remove(previous);
+        }
+        let loaded = started.metadata.is_some();
+        self.set_current(&started.path, started.metadata, started.spec);
+
+        // The chain may have moved on before the tags were read
+        if !loaded {
+            self.load_current_metadata(&started.path);
+        }
+        Some(started.path)
+    }
+
+    // Decodes `path` positioned at its resume point, if it has one
+    fn decode_resumed(&mut self, path: &Path) -> Result<SymphoniaSource> {
+        let mut source = self.decode(path)?;
+        if let Some(point) = self.resume_positions.get(path) {
+            if let Err(e) = source.try_seek(point.position()) {
+                logger::log(&format!("Failed to resume {}: {e}", path.display()));
+            }
+        }
+        Ok(source)
+    }
+
+    fn decode(&mut self, path: &Path) -> Result<SymphoniaSource> {
+        let stream = self.streams.get(path);
+        let mut source = match cue::resolve(path) {
+            Some((_, track)) => Self::decode_cue_track(&track, stream),
+            None if radio::is_url(path) => self.decode_url(path),
+            None => Self::decode_symphonia(path, stream),
+        }
+        .with_context(|| format!("Failed to decode audio file: {}", path.display()))?;
+        source.attach_replaygain(Arc::clone(&self.replaygain));
+        source.attach_errors(Arc::clone(&self.errors), path);
+        Ok(source)
+    }
+
+    /// Decodes `path` the way playback does, CUE tracks included, but without
+    /// replay gain or any of the later stages. For analysis off the audio thread.
+    pub fn decode_for_analysis(path: &Path) -> Result<impl Source<Item = f32> + Send> {
+        match cue::resolve(path) {
+            Some((_, track)) => Self::decode_cue_track(&track, None),
+            None => Self::decode_symphonia(path, None),
+        }
+        .with_context(|| format!("Failed to decode audio file: {}", path.display()))
+    }
+
+    // Opens the image file a CUE track lives in, limited to the track's span
+    fn decode_cue_track(track: &CueTrack, stream: Option<u32>) -> Result<SymphoniaSource> {
+        let mut source = Self::decode_symphonia(&track.file, stream)?;
+        let start = track.start_frame(source.sample_rate);
+        let end = track.end_frame(source.sample_rate);
+        source
+            .set_span(start, end)
+            .map_err(|e| anyhow::anyhow!("Failed to seek to the start of the track: {e}"))?;
+        Ok(source)
+    }
+
+    // Connects to an http(s) stream and keeps track of what the station announces
+    fn decode_url(&mut self, url: &Path) -> Result<SymphoniaSource> {
+        let stream = HttpStream::open(&url.to_string_lossy())?;
+
+        let mut hint = Hint::new();
+        if let Some(extension) = stream.extension() {
+            hint.with_extension(extension);
+        }
+
+        self.stations.retain(|(_, info)| info.strong_count() > 0);
+        self.stations
+            .push((url.to_path_buf(), Arc::downgrade(&stream.info())));
+
+        let gate = stream.gate();
+        let mut source = Self::decode_media(Box::new(stream), &hint, None)?;
+        source.gate = Some(gate);
+        Ok(source)
+    }
+
+    // Decodes audio stream `stream` of the file, or the one `select_audio_track` prefers
+    fn decode_symphonia(path: &Path, stream: Option<u32>) -> Result<SymphoniaSource> {
         let file = std::fs::File::open(path)?;
-        let mss = MediaSourceStream::new(Box::new(file), MediaSourceStreamOptions::default());
 
         // Create a hint to help the format registry
         let mut hint = Hint::new();
@@ -83,10 +643,20 @@ impl Player {
             hint.with_extension(extension);
         }
 
+        Self::decode_media(Box::new(file), &hint, stream)
+    }
+
+    fn decode_media(
+        media: Box<dyn MediaSource>,
+        hint: &Hint,
+        stream: Option<u32>,
+    ) -> Result<SymphoniaSource> {
+        let mss = MediaSourceStream::new(media, MediaSourceStreamOptions::default());
+
         // Probe the media source
-        let probed = symphonia::default::get_probe()
+        let mut probed = symphonia::default::get_probe()
             .format(
-                &hint,
+                hint,
                 mss,
                 &FormatOptions {
                     enable_gapless: true,
@@ -96,10 +666,19 @@ impl Player {
             )
             .context("Failed to probe audio file")?;
 
-        let format = probed.format;
-        let track = format
-            .default_track()
-            .context("No default track found in audio file")?;
+        // ReplayGain tags may sit in the container or in a leading ID3 tag
+        let mut replaygain = ReplayGain::default();
+        if let Some(metadata_rev) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+            replaygain.read_tags(metadata_rev.tags());
+        }
+
+        let mut format = probed.format;
+        if let Some(metadata_rev) = format.metadata().current() {
+            replaygain.read_tags(metadata_rev.tags());
+        }
+
+        let track = metadata::select_audio_track(&*format, stream)
+            .context("No audio track found in file")?;
 
         let track_id = track.id;
         let codec_params = track.codec_params.clone();
@@ -115,169 +694,525 @@ impl Player {
             })
             .context("Failed to create decoder")?;
 
-        Ok(SymphoniaSource::new(decoder, format, track_id))
+        let mut source = SymphoniaSource::new(decoder, format, track_id);
+        source.replaygain = replaygain;
+        Ok(source)
     }
 
     pub fn pause(&self) {
-        // Check if already paused
-        if self.is_paused() {
-            return;
-        }
-
-        // Get elapsed time before we pause
-        let elapsed = self.get_elapsed_duration();
-
-        // Now pause the sink and store elapsed time
-        if let Some(sink) = self.sink.lock().unwrap().as_ref() {
-            *self.paused_elapsed.lock().unwrap() = elapsed;
+        if let Some(sink) = &self.sink {
             sink.pause();
         }
     }
 
     pub fn resume(&self) {
-        if let Some(sink) = self.sink.lock().unwrap().as_ref() {
+        if let Some(sink) = &self.sink {
             if sink.is_paused() {
-                // Resume from the stored position
-                *self.playback_start_time.lock().unwrap() = Some(SystemTime::now());
                 sink.play();
             }
         }
     }
 
-    pub fn stop(&self) {
-        if let Some(sink) = self.sink.lock().unwrap().as_ref() {
+    pub fn stop(&mut self) {
+        self.remember_position();
+        if let Some(sink) = self.sink.take() {
             sink.stop();
         }
-        *self.sink.lock().unwrap() = None;
-        *self.current_track.lock().unwrap() = None;
-        *self.current_metadata.lock().unwrap() = None;
-        *self.playback_start_time.lock().unwrap() = None;
-        *self.paused_elapsed.lock().unwrap() = Duration::from_secs(0);
+        self.chain = None;
+        self.current_track = None;
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.set_metadata(None);
+        self.current_spec = None;
+        self.tap.clear();
     }
 
     pub fn is_playing(&self) -> bool {
         self.sink
-            .lock()
-            .unwrap()
             .as_ref()
             .is_some_and(|s| !s.is_paused() && !s.empty())
+            && !self.has_ended()
     }
 
     pub fn is_paused(&self) -> bool {
-        self.sink
-            .lock()
-            .unwrap()
-            .as_ref()
-            .is_some_and(rodio::Sink::is_paused)
+        self.sink.as_ref().is_some_and(rodio::Sink::is_paused)
     }
 
+    /// True once the current track played out, as soon as the chain ran dry rather
+    /// than once the output drained.
     pub fn is_finished(&self) -> bool {
-        self.sink
-            .lock()
-            .unwrap()
+        self.sink.as_ref().is_none_or(rodio::Sink::empty) || self.has_ended()
+    }
+
+    fn has_ended(&self) -> bool {
+        self.chain
             .as_ref()
-            .is_none_or(rodio::Sink::empty)
+            .is_some_and(|chain| chain.ended.load(Ordering::Acquire))
     }
 
-    pub fn current_track(&self) -> Option<PathBuf> {
-        self.current_track.lock().unwrap().clone()
+    pub fn current_track(&self) -> Option<&Path> {
+        self.current_track.as_deref()
     }
 
+    /// Metadata of the current track. For streams, the song on air as the station
+    /// last announced it.
     pub fn current_metadata(&self) -> Option<TrackMetadata> {
-        self.current_metadata.lock().unwrap().clone()
+        let metadata = self.current_metadata.clone();
+        let Some(station) = self.current_track().and_then(|track| self.station(track)) else {
+            return metadata;
+        };
+        let mut metadata = metadata.unwrap_or_default();
+        station.apply(&mut metadata);
+        Some(metadata)
+    }
+
+    /// Changes whenever the tags of the current track are replaced. Does not follow
+    /// what stations announce, see `station_title`.
+    pub fn metadata_version(&self) -> u64 {
+        self.metadata_version
+    }
+
+    /// The song on air, when the current track is a stream.
+    pub fn station_title(&self) -> Option<String> {
+        self.station(self.current_track()?)?.title()
+    }
+
+    // What the open stream of `url` announces, the latest one if it is open twice
+    fn station(&self, url: &Path) -> Option<Arc<StationInfo>> {
+        self.stations
+            .iter()
+            .rev()
+            .filter(|(path, _)| path == url)
+            .find_map(|(_, info)| info.upgrade())
     }
 
-    fn get_elapsed_duration(&self) -> Duration {
-        // Lock all needed mutexes upfront in a consistent order to avoid deadlocks
-        let paused_elapsed = *self.paused_elapsed.lock().unwrap();
-        let playback_start_time = *self.playback_start_time.lock().unwrap();
-        let is_paused = self
-            .sink
-            .lock()
-            .unwrap()
+    /// Position within the current track, as published by the decoder from the
+    /// timestamp of the packet being played. Unaffected by UI stalls, pauses and seeks.
+    pub fn position(&self) -> Duration {
+        // Never runs past the end, since it comes from what was actually decoded
+        self.chain
             .as_ref()
-            .map(|s| s.is_paused())
-            .unwrap_or(false);
-
-        let elapsed = if is_paused {
-            // When paused, return the stored elapsed time
-            paused_elapsed
-        } else if let Some(start_time) = playback_start_time {
-            // When playing, add the time since resume to the paused elapsed time
-            let current_elapsed = SystemTime::now()
-                .duration_since(start_time)
-                .unwrap_or(Duration::from_secs(0));
-            paused_elapsed + current_elapsed
-        } else {
-            Duration::from_secs(0)
+            .map_or(Duration::ZERO, |chain| chain.position())
+    }
+
+    /// Channel count of the playing track, before it is mixed down to stereo.
+    pub fn source_channels(&self) -> Option<u16> {
+        self.current_spec.map(|(channels, _)| channels)
+    }
+
+    /// Samples on their way to the output, for the visualizer.
+    pub fn tap(&self) -> &Arc<AudioTap> {
+        &self.tap
+    }
+
+    /// A-B loop markers of the current track. Playback jumps back to A whenever it
+    /// reaches B, as long as both are set.
+    pub fn ab_loop(&self) -> (Option<Duration>, Option<Duration>) {
+        self.chain
+            .as_ref()
+            .map_or((None, None), |chain| chain.ab_loop())
+    }
+
+    /// Sets or clears the A-B loop markers. They are dropped when the track changes.
+    pub fn set_ab_loop(&self, start: Option<Duration>, end: Option<Duration>) {
+        if let Some(chain) = &self.chain {
+            chain.set_ab_loop(start, end);
+        }
+    }
+
+    /// Sets the volume of this and every following track. It is applied right before
+    /// the compressor rather than by the sink.
+    pub fn set_volume(&self, volume: f32) {
+        self.dynamics.set_volume(volume);
+    }
+
+    /// Selects the compressor preset. Applies to the playing track right away.
+    pub fn set_dynamics(&self, preset: DynamicsPreset) {
+        self.dynamics.set_preset(preset);
+    }
+
+    /// Sets the balance and mono downmix. Applies to the playing track right away.
+    pub fn set_channels(&self, settings: ChannelSettings) {
+        self.channels.set(settings);
+    }
+
+    /// Sets how many decode errors a track may have before playback moves on.
+    pub fn set_decode_error_limit(&self, limit: u32) {
+        self.errors.limit.store(limit.max(1), Ordering::Relaxed);
+    }
+
+    /// Sets which tracks get a resume point when playback leaves them.
+    pub fn set_resume_policy(&mut self, policy: ResumePolicy) {
+        self.resume_policy = policy;
+    }
+
+    pub fn set_resume_positions(&mut self, positions: ResumePositions) {
+        self.resume_positions = positions;
+    }
+
+    pub fn resume_positions(&self) -> &ResumePositions {
+        &self.resume_positions
+    }
+
+    /// Records the position of the current track if the resume policy covers it. Done
+    /// whenever playback leaves a track; call it before saving the positions too.
+    pub fn remember_position(&mut self) {
+        let Some(track) = self.current_track.clone() else {
+            return;
         };
+        if self.is_finished() {
+            self.resume_positions.remove(&track);
+            return;
+        }
 
-        // Clamp elapsed time to the track's actual duration to prevent it from exceeding
-        // the max length when the queue ends
-        if let Some(metadata) = self.current_metadata() {
-            if let Some(duration_secs) = metadata.duration_secs {
-                let max_duration = Duration::from_secs(duration_secs);
-                elapsed.min(max_duration)
-            } else {
-                elapsed
-            }
-        } else {
-            elapsed
+        let duration = self
+            .current_metadata
+            .as_ref()
+            .and_then(|metadata| metadata.duration_secs)
+            .map(Duration::from_secs);
+        if self.resume_policy.applies(&track, duration) {
+            self.resume_positions
+                .update(&track, self.position(), duration);
         }
     }
 
-    pub fn get_position_and_progress(&self) -> (Duration, Option<f64>) {
-        let position = self.get_elapsed_duration();
-        let progress = if let Some(metadata) = self.current_metadata() {
-            if let Some(duration_secs) = metadata.duration_secs {
-                let position_secs = position.as_secs();
-                if duration_secs > 0 {
-                    Some((position_secs as f64 / duration_secs as f64).min(1.0))
-                } else {
-                    None
-                }
-            } else {
-                None
+    /// Decode errors met since the last call, oldest first.
+    pub fn take_decode_errors(&self) -> Vec<DecodeError> {
+        std::mem::take(&mut *self.errors.pending.lock().unwrap())
+    }
+
+    pub fn seek_to(&self, position: Duration) {
+        // The source publishes the new position itself once the seek went through
+        if let Some(sink) = &self.sink {
+            if let Err(e) = sink.try_seek(position) {
+                logger::log(&format!("Failed to seek: {e}"));
             }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Fade {
+    duration: Duration,
+    curve: FadeCurve,
+}
+
+// Tags of `path`. A file whose tags cannot be read still counts as loaded, just
+// without any, so the UI stops waiting for them.
+fn read_tags(path: &Path) -> Box<TrackMetadata> {
+    let metadata = TrackMetadata::from_file(path).unwrap_or_else(|e| {
+        if !radio::is_url(path) {
+            logger::log(&format!("Failed to read tags of {}: {e:#}", path.display()));
+        }
+        TrackMetadata::default()
+    });
+    Box::new(metadata)
+}
+
+// Crossfade between tracks described by `current` and `next`, or None for a plain
+// gapless change. Consecutive tracks from the same album are never crossfaded so
+// gapless albums stay intact.
+fn fade_between(
+    (duration, curve): (Duration, FadeCurve),
+    current: Option<&TrackMetadata>,
+    next: Option<&TrackMetadata>,
+) -> Option<Fade> {
+    if duration.is_zero() {
+        return None;
+    }
+    if let (Some(current), Some(next)) = (current, next) {
+        if current.is_same_album(next) {
+            return None;
+        }
+    }
+    Some(Fade { duration, curve })
+}
+
+// A track decoded ahead of time, waiting for the current one to finish
+struct QueuedTrack {
+    path: PathBuf,
+    metadata: Option<TrackMetadata>,
+    spec: (u16, u32),
+    fade: Option<Fade>,
+    // Taken by the chain when it switches over; the rest is left for the player
+    source: Option<SymphoniaSource>,
+}
+
+// State shared between the player and the chain running on the audio thread
+#[derive(Default)]
+struct ChainShared {
+    // Track to continue with once the current one ends (or starts fading out)
+    next: Mutex<Option<QueuedTrack>>,
+    // Track to fade in right away, replacing the current one
+    cut_in: Mutex<Option<QueuedTrack>>,
+    // Set whenever `next` or `cut_in` changed, so the audio thread can check without locking
+    pending: AtomicBool,
+    started: Mutex<Option<QueuedTrack>>,
+    // Position of the track the chain considers current, in microseconds
+    position: Arc<AtomicU64>,
+    // Where the previous track stopped, reported until `started` is taken
+    previous_position: AtomicU64,
+    // A-B loop markers of the current track in microseconds, `NO_MARKER` when unset
+    loop_start: AtomicU64,
+    loop_end: AtomicU64,
+    // Set once the chain ran out of audio
+    ended: AtomicBool,
+    // Tells the player about track changes; None when nobody listens
+    notify: Option<Notify>,
+}
+
+const NO_MARKER: u64 = u64::MAX;
+
+impl ChainShared {
+    fn new() -> Self {
+        Self {
+            loop_start: AtomicU64::new(NO_MARKER),
+            loop_end: AtomicU64::new(NO_MARKER),
+            ..Self::default()
+        }
+    }
+
+    fn with_notify(notify: Notify) -> Self {
+        Self {
+            notify: Some(notify),
+            ..Self::new()
+        }
+    }
+
+    // Only called once per track, so it does not hold up the audio thread
+    fn announce(&self, notice: Notice) {
+        if let Some(notify) = &self.notify {
+            notify(notice);
+        }
+    }
+
+    fn ab_loop(&self) -> (Option<Duration>, Option<Duration>) {
+        let marker = |value: &AtomicU64| {
+            let micros = value.load(Ordering::Acquire);
+            (micros != NO_MARKER).then(|| Duration::from_micros(micros))
+        };
+        (marker(&self.loop_start), marker(&self.loop_end))
+    }
+
+    fn set_ab_loop(&self, start: Option<Duration>, end: Option<Duration>) {
+        let micros = |marker: Option<Duration>| {
+            marker.map_or(NO_MARKER, |d| {
+                d.as_micros().min(u128::from(NO_MARKER - 1)) as u64
+            })
+        };
+        // End first, so the audio thread never sees a new end with the old start
+        self.loop_end.store(NO_MARKER, Ordering::Release);
+        self.loop_start.store(micros(start), Ordering::Release);
+        self.loop_end.store(micros(end), Ordering::Release);
+    }
+
+    fn position(&self) -> Duration {
+        // Keep reporting the old track until the player has caught up with the change
+        let micros = if self.started.lock().unwrap().is_some() {
+            self.previous_position.load(Ordering::Acquire)
         } else {
-            None
+            self.position.load(Ordering::Acquire)
         };
-        (position, progress)
+        Duration::from_micros(micros)
     }
+}
 
-    pub fn set_volume(&self, volume: f32) {
-        if let Some(sink) = self.sink.lock().unwrap().as_ref() {
-            sink.set_volume(volume);
+struct Crossfade {
+    incoming: SymphoniaSource,
+    curve: FadeCurve,
+    pos: usize,
+    len: usize,
+}
+
+// Source that plays the current track, then continues straight into the queued one,
+// crossfading the two when the queued track asks for it
+struct TrackChain {
+    current: SymphoniaSource,
+    shared: Arc<ChainShared>,
+    crossfade: Option<Crossfade>,
+    // Fade length (in samples) wanted before the queued track, valid once `next_checked`
+    next_fade: Option<usize>,
+    next_checked: bool,
+}
+
+impl TrackChain {
+    fn new(mut current: SymphoniaSource, shared: Arc<ChainShared>) -> Self {
+        current.publish_position(Some(Arc::clone(&shared.position)));
+        Self {
+            current,
+            shared,
+            crossfade: None,
+            next_fade: None,
+            next_checked: false,
+        }
+    }
+
+    fn fade_samples(&self, fade: Fade) -> usize {
+        let frames = fade.duration.as_secs_f64() * f64::from(self.current.sample_rate);
+        frames as usize * self.current.channels as usize
+    }
+
+    // Moves position reporting over to `incoming`, remembering where the current track was
+    fn hand_over_position(&mut self, incoming: &mut SymphoniaSource) {
+        self.shared
+            .previous_position
+            .store(self.current.played_micros(), Ordering::Release);
+        // Loop markers belong to the track that is ending
+        self.shared.set_ab_loop(None, None);
+        self.current.publish_position(None);
+        incoming.publish_position(Some(Arc::clone(&self.shared.position)));
+    }
+
+    fn start_crossfade(&mut self, mut queued: QueuedTrack, len: usize, announce: bool) {
+        let (Some(mut incoming), Some(fade)) = (queued.source.take(), queued.fade) else {
+            return;
+        };
+        self.hand_over_position(&mut incoming);
+        self.crossfade = Some(Crossfade {
+            incoming,
+            curve: fade.curve,
+            pos: 0,
+            len: len.max(1),
+        });
+        self.next_checked = false;
+        if announce {
+            *self.shared.started.lock().unwrap() = Some(queued);
+            self.shared.announce(Notice::Advanced);
         }
     }
 
-    pub fn seek_forward(&self, seconds: u64) {
-        let current = self.get_elapsed_duration();
-        let target_position = current + Duration::from_secs(seconds);
-        if let Some(sink) = self.sink.lock().unwrap().as_ref() {
-            if let Err(e) = sink.try_seek(target_position) {
-                logger::log(&format!("Failed to seek forward: {e}"));
-            } else {
-                // Update tracking after successful seek
-                *self.playback_start_time.lock().unwrap() = Some(SystemTime::now());
-                *self.paused_elapsed.lock().unwrap() = target_position;
+    // Seeks back to A once playback reaches B. Only called on frame boundaries.
+    fn check_loop(&mut self) {
+        let end = self.shared.loop_end.load(Ordering::Acquire);
+        if end == NO_MARKER || self.current.played_micros() < end {
+            return;
+        }
+        let start = self.shared.loop_start.load(Ordering::Acquire);
+        if start < end {
+            if let Err(e) = self.current.try_seek(Duration::from_micros(start)) {
+                logger::log(&format!("Failed to loop back to A: {e}"));
+                self.shared.set_ab_loop(None, None);
             }
+            self.next_checked = false;
         }
     }
 
-    pub fn seek_backward(&self, seconds: u64) {
-        let current = self.get_elapsed_duration();
-        let target_position = current.saturating_sub(Duration::from_secs(seconds));
-        if let Some(sink) = self.sink.lock().unwrap().as_ref() {
-            if let Err(e) = sink.try_seek(target_position) {
-                logger::log(&format!("Failed to seek backward: {e}"));
-            } else {
-                // Update tracking after successful seek
-                *self.playback_start_time.lock().unwrap() = Some(SystemTime::now());
-                *self.paused_elapsed.lock().unwrap() = target_position;
+    // Starts a pending fade when it is due. Only called on frame boundaries.
+    fn check_fades(&mut self) {
+        if self.shared.pending.swap(false, Ordering::Acquire) {
+            // The queued track may have changed too
+            self.next_checked = false;
+            let cut_in = self.shared.cut_in.lock().unwrap().take();
+            if let Some(queued) = cut_in {
+                let len = queued.fade.map_or(0, |fade| self.fade_samples(fade));
+                self.start_crossfade(queued, len, false);
+                return;
+            }
+        }
+
+        if !self.next_checked {
+            let next = self.shared.next.lock().unwrap();
+            self.next_fade = next
+                .as_ref()
+                .and_then(|queued| queued.fade)
+                .map(|fade| self.fade_samples(fade));
+            self.next_checked = true;
+        }
+
+        if let Some(len) = self.next_fade {
+            let remaining = self
+                .current
+                .remaining_frames()
+                .map(|frames| frames as usize * self.current.channels as usize);
+            if remaining.is_some_and(|remaining| remaining <= len) {
+                let queued = self.shared.next.lock().unwrap().take();
+                if let Some(queued) = queued {
+                    self.start_crossfade(queued, remaining.unwrap_or(len), true);
+                }
             }
         }
     }
+
+    fn next_crossfaded(&mut self) -> Option<f32> {
+        let fade = self.crossfade.as_mut()?;
+        let (out_gain, in_gain) = fade.curve.gains(fade.pos as f32 / fade.len as f32);
+        fade.pos += 1;
+
+        let incoming = fade.incoming.next();
+        let outgoing = self.current.next();
+
+        if outgoing.is_none() || fade.pos >= fade.len {
+            let fade = self.crossfade.take()?;
+            self.current = fade.incoming;
+        }
+
+        Some(outgoing.unwrap_or(0.0) * out_gain + incoming.unwrap_or(0.0) * in_gain)
+    }
+}
+
+impl Iterator for TrackChain {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.crossfade.is_some() {
+            return self.next_crossfaded();
+        }
+
+        if self.current.at_frame_boundary() {
+            self.check_loop();
+            self.check_fades();
+            if self.crossfade.is_some() {
+                return self.next_crossfaded();
+            }
+        }
+
+        loop {
+            if let Some(sample) = self.current.next() {
+                return Some(sample);
+            }
+
+            // Only reached once per track, so locking here does not hurt the audio thread
+            let next = self.shared.next.lock().unwrap().take();
+            let Some((queued, mut incoming)) =
+                next.and_then(|mut queued| queued.source.take().map(|source| (queued, source)))
+            else {
+                self.shared.ended.store(true, Ordering::Release);
+                self.shared.announce(Notice::Ended);
+                return None;
+            };
+            self.hand_over_position(&mut incoming);
+            self.current = incoming;
+            self.next_checked = false;
+            *self.shared.started.lock().unwrap() = Some(queued);
+            self.shared.announce(Notice::Advanced);
+        }
+    }
+}
+
+impl Source for TrackChain {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.current.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.current.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: std::time::Duration) -> Result<(), rodio::source::SeekError> {
+        // Seeking finishes any fade in progress; the incoming track is the one being seeked
+        if let Some(fade) = self.crossfade.take() {
+            self.current = fade.incoming;
+        }
+        self.next_checked = false;
+        self.current.try_seek(pos)
+    }
 }
 
 // Custom source that wraps Symphonia decoder for use with rodio
@@ -285,10 +1220,29 @@ struct SymphoniaSource {
     decoder: Box<dyn symphonia::core::codecs::Decoder>,
     format: Box<dyn symphonia::core::formats::FormatReader>,
     track_id: u32,
-    sample_buf: Option<SampleBuffer<i16>>,
+    sample_buf: Option<SampleBuffer<f32>>,
     sample_pos: usize,
     channels: u16,
     sample_rate: u32,
+    total_frames: Option<u64>,
+    // Span of the file that makes up the track, in frames, for CUE tracks
+    start_frame: u64,
+    end_frame: Option<u64>,
+    packet_ts: u64,
+    // Frame to resume output at after an accurate seek, dropping what comes before it
+    seek_target: Option<u64>,
+    // Where the position of the packet being played is published, in microseconds
+    position: Option<Arc<AtomicU64>>,
+    replaygain: ReplayGain,
+    replaygain_control: Option<Arc<ReplayGainControl>>,
+    replaygain_generation: u64,
+    gain: f32,
+    // Where decode errors are reported, with the path they are reported under
+    errors: Option<(Arc<DecodeErrors>, PathBuf)>,
+    error_count: u32,
+    // Set for network streams, which play silence while their buffer refills
+    gate: Option<StreamGate>,
+    silence_left: usize,
 }
 
 impl SymphoniaSource {
@@ -300,6 +1254,7 @@ impl SymphoniaSource {
         let codec_params = &decoder.codec_params();
         let channels = codec_params.channels.map(|c| c.count() as u16).unwrap_or(2);
         let sample_rate = codec_params.sample_rate.unwrap_or(48000);
+        let total_frames = codec_params.n_frames;
 
         Self {
             decoder,
@@ -309,19 +1264,154 @@ impl SymphoniaSource {
             sample_pos: 0,
             channels,
             sample_rate,
+            total_frames,
+            start_frame: 0,
+            end_frame: None,
+            packet_ts: 0,
+            seek_target: None,
+            position: None,
+            replaygain: ReplayGain::default(),
+            replaygain_control: None,
+            replaygain_generation: 0,
+            gain: 1.0,
+            errors: None,
+            error_count: 0,
+            gate: None,
+            silence_left: 0,
         }
     }
 
+    fn attach_replaygain(&mut self, control: Arc<ReplayGainControl>) {
+        self.replaygain_control = Some(control);
+        // Force a lookup on the first packet
+        self.replaygain_generation = u64::MAX;
+    }
+
+    fn attach_errors(&mut self, errors: Arc<DecodeErrors>, path: &Path) {
+        self.errors = Some((errors, path.to_path_buf()));
+    }
+
+    // Counts an error at file frame `frame` and reports it. Returns false when the
+    // track should stop here, because of `fatal` or once it had too many.
+    fn report_error(&mut self, frame: u64, error: &SymphoniaError, fatal: bool) -> bool {
+        self.error_count += 1;
+        let limit = self
+            .errors
+            .as_ref()
+            .map_or(DEFAULT_DECODE_ERROR_LIMIT, |(errors, _)| {
+                errors.limit.load(Ordering::Relaxed)
+            });
+        let gave_up = fatal || self.error_count >= limit;
+
+        if let Some((errors, path)) = &self.errors {
+            let played = frame.saturating_sub(self.start_frame);
+            errors.pending.lock().unwrap().push(DecodeError {
+                path: path.clone(),
+                position: Duration::from_micros(
+                    played * 1_000_000 / u64::from(self.sample_rate.max(1)),
+                ),
+                message: error.to_string(),
+                count: self.error_count,
+                gave_up,
+            });
+        }
+        !gave_up
+    }
+
+    fn update_gain(&mut self) {
+        let Some(control) = &self.replaygain_control else {
+            return;
+        };
+        let generation = control.generation.load(Ordering::Acquire);
+        if generation == self.replaygain_generation {
+            return;
+        }
+        self.replaygain_generation = generation;
+
+        let (mode, preamp_db) = *control.settings.lock().unwrap();
+        self.gain = match mode {
+            ReplayGainMode::Off => 1.0,
+            ReplayGainMode::Track => self.replaygain.factor(false, preamp_db),
+            ReplayGainMode::Album | ReplayGainMode::Auto => self.replaygain.factor(true, preamp_db),
+        };
+    }
+
+    // Restricts playback to `start..end` of the file. Positions and seeks are relative to
+    // `start` from then on.
+    fn set_span(&mut self, start: u64, end: Option<u64>) -> Result<(), rodio::source::SeekError> {
+        self.start_frame = start;
+        self.end_frame = end;
+        self.total_frames = end
+            .or(self.total_frames)
+            .map(|end| end.saturating_sub(start));
+        self.try_seek(Duration::ZERO)
+    }
+
+    fn publish_position(&mut self, position: Option<Arc<AtomicU64>>) {
+        self.position = position;
+        self.store_position();
+    }
+
+    fn store_position(&self) {
+        if let Some(position) = &self.position {
+            position.store(self.played_micros(), Ordering::Release);
+        }
+    }
+
+    fn played_micros(&self) -> u64 {
+        self.played_frames() * 1_000_000 / u64::from(self.sample_rate.max(1))
+    }
+
+    fn played_frames(&self) -> u64 {
+        self.file_frame().saturating_sub(self.start_frame)
+    }
+
+    // Frame of the file the next sample belongs to
+    fn file_frame(&self) -> u64 {
+        self.packet_ts + (self.sample_pos / self.channels.max(1) as usize) as u64
+    }
+
+    fn at_frame_boundary(&self) -> bool {
+        self.sample_pos % self.channels.max(1) as usize == 0
+    }
+
+    // Frames left until the end of the track, if the container reports its length
+    fn remaining_frames(&self) -> Option<u64> {
+        let played = self.played_frames();
+        self.total_frames.map(|total| total.saturating_sub(played))
+    }
+
     fn decode_next_packet(&mut self) -> Option<()> {
         loop {
             let packet = match self.format.next_packet() {
                 Ok(packet) => packet,
-                Err(_) => return None,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    // The last packet has played out entirely
+                    self.store_position();
+                    return None;
+                }
+                Err(SymphoniaError::ResetRequired) => {
+                    // A new logical stream starts (chained Ogg), which ends this track
+                    self.store_position();
+                    return None;
+                }
+                Err(e) => {
+                    // The demuxer resyncs after damaged data, but read failures stay
+                    let fatal = matches!(e, SymphoniaError::IoError(_));
+                    if self.report_error(self.file_frame(), &e, fatal) {
+                        continue;
+                    }
+                    self.store_position();
+                    return None;
+                }
             };
 
             if packet.track_id() != self.track_id {
                 continue;
             }
+            let packet_ts = packet.ts();
 
             match self.decoder.decode(&packet) {
                 Ok(decoded) => {
@@ -331,31 +1421,68 @@ impl SymphoniaSource {
                         self.sample_buf = Some(SampleBuffer::new(duration, spec));
                     }
 
-                    if let Some(buf) = &mut self.sample_buf {
-                        buf.copy_interleaved_ref(decoded);
-                        self.sample_pos = 0;
-                        return Some(());
+                    let Some(buf) = &mut self.sample_buf else {
+                        continue;
+                    };
+                    buf.copy_interleaved_ref(decoded);
+                    let frames = (buf.len() / self.channels.max(1) as usize) as u64;
+                    self.sample_pos = 0;
+                    self.packet_ts = packet_ts;
+
+                    if let Some(target) = self.seek_target {
+                        if packet_ts + frames <= target {
+                            continue;
+                        }
+                        let skip = target.saturating_sub(packet_ts) as usize;
+                        self.sample_pos = skip * self.channels.max(1) as usize;
+                        self.seek_target = None;
+                    }
+
+                    self.update_gain();
+                    self.store_position();
+                    return Some(());
+                }
+                Err(e) => {
+                    // Skip the damaged packet, unless the track had too many already
+                    if self.report_error(packet_ts, &e, false) {
+                        continue;
                     }
+                    self.store_position();
+                    return None;
                 }
-                Err(_) => continue,
             }
         }
     }
 }
 
 impl Iterator for SymphoniaSource {
-    type Item = i16;
+    type Item = f32;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
+            if self.silence_left > 0 {
+                self.silence_left -= 1;
+                return Some(0.0);
+            }
             if let Some(buf) = &self.sample_buf {
                 if self.sample_pos < buf.len() {
+                    if self.end_frame.is_some_and(|end| self.file_frame() >= end) {
+                        // The rest of the file belongs to the next CUE track
+                        self.store_position();
+                        return None;
+                    }
                     let sample = buf.samples()[self.sample_pos];
                     self.sample_pos += 1;
-                    return Some(sample);
+                    // No clamping: the float path has headroom until the output stage
+                    return Some(sample * self.gain);
                 }
             }
 
+            // Waiting on the network would hold up the audio thread, so fill in 20 ms
+            if self.gate.as_ref().is_some_and(|gate| !gate.is_ready()) {
+                self.silence_left = (self.sample_rate / 50) as usize * usize::from(self.channels);
+                continue;
+            }
             self.decode_next_packet()?;
         }
     }
@@ -383,10 +1510,12 @@ impl Source for SymphoniaSource {
         use symphonia::core::formats::SeekTo;
 
         let sample_rate = self.sample_rate as u64;
-        let target_ts =
-            pos.as_secs() * sample_rate + (pos.subsec_nanos() as u64 * sample_rate / 1_000_000_000);
+        let target_ts = self.start_frame
+            + pos.as_secs() * sample_rate
+            + (pos.subsec_nanos() as u64 * sample_rate / 1_000_000_000);
 
-        self.format
+        let seeked = self
+            .format
             .seek(
                 SeekMode::Accurate,
                 SeekTo::TimeStamp {
@@ -402,7 +1531,455 @@ impl Source for SymphoniaSource {
         self.decoder.reset();
         self.sample_buf = None;
         self.sample_pos = 0;
+        // The reader lands on a packet at or before the target; decoding drops the rest
+        self.packet_ts = seeked.required_ts;
+        self.seek_target = Some(seeked.required_ts);
+        self.store_position();
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // Decoded value of the 16-bit sample `value`
+    fn level(value: i16) -> f32 {
+        f32::from(value) / 32768.0
+    }
+
+    /// Writes a 16-bit PCM WAV file holding `frames` frames of a constant sample value.
+    pub(crate) fn write_wav(path: &Path, channels: u16, sample_rate: u32, frames: u32, value: i16) {
+        write_wav_with(path, channels, sample_rate, frames, |_| value);
+    }
+
+    /// Like `write_wav`, with the sample value of each frame given by `value`.
+    pub(crate) fn write_wav_with(
+        path: &Path,
+        channels: u16,
+        sample_rate: u32,
+        frames: u32,
+        value: impl Fn(u32) -> i16,
+    ) {
+        let data_len = frames * u32::from(channels) * 2;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * u32::from(channels) * 2).to_le_bytes());
+        bytes.extend_from_slice(&(channels * 2).to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for frame in 0..frames {
+            for _ in 0..channels {
+                bytes.extend_from_slice(&value(frame).to_le_bytes());
+            }
+        }
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    fn queued(path: &Path, fade: Option<Fade>) -> QueuedTrack {
+        let source = Player::decode_symphonia(path, None).unwrap();
+        QueuedTrack {
+            path: path.to_path_buf(),
+            metadata: None,
+            spec: (source.channels(), source.sample_rate()),
+            fade,
+            source: Some(source),
+        }
+    }
+
+    #[test]
+    fn test_chain_plays_queued_track_without_gap() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = temp_dir.path().join("first.wav");
+        let second = temp_dir.path().join("second.wav");
+        write_wav(&first, 2, 8000, 4000, 1000);
+        write_wav(&second, 2, 8000, 4000, -1000);
+
+        let shared = Arc::new(ChainShared::new());
+        let mut chain = TrackChain::new(
+            Player::decode_symphonia(&first, None).unwrap(),
+            Arc::clone(&shared),
+        );
+        *shared.next.lock().unwrap() = Some(queued(&second, None));
+        shared.pending.store(true, Ordering::Release);
+
+        let samples: Vec<f32> = chain.by_ref().collect();
+        assert_eq!(samples.len(), 16000);
+        assert!(samples[..8000].iter().all(|s| *s == level(1000)));
+        assert!(samples[8000..].iter().all(|s| *s == level(-1000)));
+        assert_eq!(
+            shared
+                .started
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|t| t.path.clone()),
+            Some(second)
+        );
+    }
+
+    #[test]
+    fn test_chain_crossfades_into_queued_track() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = temp_dir.path().join("first.wav");
+        let second = temp_dir.path().join("second.wav");
+        write_wav(&first, 1, 8000, 8000, 1000);
+        write_wav(&second, 1, 8000, 8000, -1000);
+
+        let shared = Arc::new(ChainShared::new());
+        let mut chain = TrackChain::new(
+            Player::decode_symphonia(&first, None).unwrap(),
+            Arc::clone(&shared),
+        );
+        let fade = Fade {
+            duration: Duration::from_millis(250),
+            curve: FadeCurve::Linear,
+        };
+        *shared.next.lock().unwrap() = Some(queued(&second, Some(fade)));
+        shared.pending.store(true, Ordering::Release);
+
+        let samples: Vec<f32> = chain.by_ref().collect();
+        // The 2000-sample overlap shortens the total by as much
+        assert_eq!(samples.len(), 14000);
+        assert_eq!(samples[0], level(1000));
+        assert!(samples[6000..8000].windows(2).all(|w| w[1] <= w[0]));
+        assert!(samples[7000].abs() < level(2));
+        assert_eq!(*samples.last().unwrap(), level(-1000));
+    }
+
+    #[test]
+    fn test_chain_publishes_position_and_seeks_accurately() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = temp_dir.path().join("first.wav");
+        let second = temp_dir.path().join("second.wav");
+        write_wav(&first, 1, 8000, 8000, 1000);
+        write_wav(&second, 1, 8000, 8000, -1000);
+
+        let shared = Arc::new(ChainShared::new());
+        let mut chain = TrackChain::new(
+            Player::decode_symphonia(&first, None).unwrap(),
+            Arc::clone(&shared),
+        );
+        assert_eq!(shared.position(), Duration::ZERO);
+
+        // Positions are published per packet, so they trail the samples pulled so far
+        chain.by_ref().take(4000).for_each(drop);
+        assert!(shared.position() <= Duration::from_millis(500));
+        assert!(shared.position() > Duration::ZERO);
+
+        chain.try_seek(Duration::from_millis(750)).unwrap();
+        assert_eq!(shared.position(), Duration::from_millis(750));
+        *shared.next.lock().unwrap() = Some(queued(&second, None));
+        assert_eq!(
+            chain.by_ref().take_while(|s| *s == level(1000)).count(),
+            2000
+        );
+
+        // Until the player takes the transition, the old track's end is reported
+        assert_eq!(shared.position(), Duration::from_secs(1));
+        shared.started.lock().unwrap().take();
+        assert!(shared.position() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_chain_loops_between_markers() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("track.wav");
+        write_wav(&path, 1, 8000, 8000, 1000);
+
+        let shared = Arc::new(ChainShared::new());
+        let mut chain = TrackChain::new(
+            Player::decode_symphonia(&path, None).unwrap(),
+            Arc::clone(&shared),
+        );
+        shared.set_ab_loop(
+            Some(Duration::from_millis(250)),
+            Some(Duration::from_millis(500)),
+        );
+
+        // 4000 samples reach B, then playback continues from A
+        chain.by_ref().take(5000).for_each(drop);
+        assert_eq!(chain.current.played_micros(), 375_000);
+
+        // The loop keeps going well past the length of the file
+        assert_eq!(chain.by_ref().take(20_000).count(), 20_000);
+        assert!(chain.current.played_micros() < 500_000);
+
+        shared.set_ab_loop(None, None);
+        let played = chain.current.played_frames();
+        assert_eq!(chain.by_ref().count() as u64, 8000 - played);
+    }
+
+    #[test]
+    fn test_lists_streams_and_ignores_unknown_choice() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("single.wav");
+        write_wav(&path, 2, 8000, 800, 1000);
+
+        let metadata = TrackMetadata::from_file(&path).unwrap();
+        assert_eq!(metadata.streams.len(), 1);
+        assert_eq!(metadata.streams[0].channels, Some(2));
+        assert_eq!(metadata.default_stream, Some(metadata.streams[0].id));
+
+        // A remembered stream the file no longer has falls back to the default one
+        let source = Player::decode_symphonia(&path, Some(99)).unwrap();
+        assert_eq!(source.track_id, metadata.streams[0].id);
+        assert_eq!(source.count(), 1600);
+    }
+
+    #[test]
+    fn test_decode_errors_are_reported_until_the_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("track.wav");
+        write_wav(&path, 1, 8000, 8000, 1000);
+
+        let errors = Arc::new(DecodeErrors::default());
+        errors.limit.store(3, Ordering::Relaxed);
+        let mut source = Player::decode_symphonia(&path, None).unwrap();
+        source.attach_errors(Arc::clone(&errors), &path);
+
+        let error = SymphoniaError::DecodeError("invalid frame");
+        assert!(source.report_error(4000, &error, false));
+        assert!(source.report_error(4800, &error, false));
+        assert!(!source.report_error(5600, &error, false));
+
+        let pending = std::mem::take(&mut *errors.pending.lock().unwrap());
+        assert_eq!(pending.len(), 3);
+        assert_eq!(pending[0].path, path);
+        assert_eq!(pending[0].position, Duration::from_millis(500));
+        assert!(!pending[1].gave_up);
+        assert!(pending[2].gave_up);
+        assert_eq!(pending[2].count, 3);
+
+        // A file that cannot be read any further is given up on right away
+        let mut source = Player::decode_symphonia(&path, None).unwrap();
+        source.attach_errors(Arc::clone(&errors), &path);
+        let error = SymphoniaError::IoError(std::io::Error::other("device gone"));
+        assert!(!source.report_error(0, &error, true));
+        assert!(errors.pending.lock().unwrap()[0].gave_up);
+    }
+
+    #[test]
+    fn test_cue_tracks_play_their_span_of_the_image() {
+        let temp_dir = TempDir::new().unwrap();
+        write_wav(&temp_dir.path().join("album.wav"), 1, 8000, 8000, 1000);
+        let cue = temp_dir.path().join("album.cue");
+        std::fs::write(
+            &cue,
+            "FILE \"album.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\n\
+             TRACK 02 AUDIO\nINDEX 01 00:00:30\n",
+        )
+        .unwrap();
+
+        let first = cue::resolve(&cue::track_path(&cue, 1)).unwrap().1;
+        assert_eq!(
+            Player::decode_cue_track(&first, None).unwrap().count(),
+            3200
+        );
+
+        // The second track starts 0.4 s in and runs to the end of the file
+        let second = cue::resolve(&cue::track_path(&cue, 2)).unwrap().1;
+        let source = Player::decode_cue_track(&second, None).unwrap();
+        assert_eq!(source.remaining_frames(), Some(4800));
+
+        let shared = Arc::new(ChainShared::new());
+        let mut chain = TrackChain::new(source, Arc::clone(&shared));
+        assert_eq!(shared.position(), Duration::ZERO);
+        chain.try_seek(Duration::from_millis(100)).unwrap();
+        assert_eq!(shared.position(), Duration::from_millis(100));
+        assert_eq!(chain.by_ref().count(), 4000);
+        assert_eq!(shared.position(), Duration::from_millis(600));
+    }
+
+    // Player on the null output, along with the notices it sends its owner
+    fn null_player() -> (Player, mpsc::Receiver<Notice>) {
+        let (sender, notices) = mpsc::channel();
+        let notify: Notify = Arc::new(move |notice| {
+            let _ = sender.send(notice);
+        });
+        (Player::new(&OutputBackend::Null, notify).unwrap(), notices)
+    }
+
+    #[test]
+    fn test_null_output_plays_in_real_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let track = temp_dir.path().join("track.wav");
+        write_wav(&track, 2, 44100, 13230, 1000);
+
+        let (mut player, _notices) = null_player();
+        player.play(&track).unwrap();
+        assert!(player.is_playing());
+
+        std::thread::sleep(Duration::from_millis(150));
+        let position = player.position();
+        assert!(position > Duration::ZERO && position < Duration::from_millis(300));
+
+        for _ in 0..100 {
+            if player.is_finished() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(player.is_finished());
+        assert_eq!(player.position(), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_plays_http_stream_with_live_title() {
+        let temp_dir = TempDir::new().unwrap();
+        let track = temp_dir.path().join("track.wav");
+        write_wav(&track, 2, 44100, 44100, 1000);
+        let audio = std::fs::read(&track).unwrap();
+        let url = PathBuf::from(crate::radio::tests::serve(vec![
+            crate::radio::tests::icy_response(&audio, 16000, "Artist - Live Song"),
+        ]));
+
+        let (mut player, _notices) = null_player();
+        player.play(&url).unwrap();
+        assert_eq!(player.current_track(), Some(url.as_path()));
+
+        let mut title = None;
+        for _ in 0..100 {
+            title = player
+                .current_metadata()
+                .and_then(|metadata| metadata.title);
+            if title.is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(title.as_deref(), Some("Live Song"));
+        let metadata = player.current_metadata().unwrap();
+        assert_eq!(metadata.artist.as_deref(), Some("Artist"));
+        assert_eq!(metadata.album.as_deref(), Some("Test FM"));
+        player.stop();
+    }
+
+    // Handles notices until the tags of the current track have been read
+    fn wait_for_metadata(player: &mut Player, notices: &mpsc::Receiver<Notice>) -> TrackMetadata {
+        for _ in 0..100 {
+            while let Ok(notice) = notices.try_recv() {
+                player.handle_notice(notice);
+            }
+            if let Some(metadata) = player.current_metadata() {
+                return metadata;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("metadata never arrived");
+    }
+
+    #[test]
+    fn test_metadata_is_read_in_the_background() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = temp_dir.path().join("first.wav");
+        let second = temp_dir.path().join("second.wav");
+        write_wav(&first, 1, 8000, 8000 * 3, 1000);
+        write_wav(&second, 1, 8000, 8000 * 5, 1000);
+
+        let (mut player, notices) = null_player();
+        player.play(&first).unwrap();
+        assert_eq!(player.current_track(), Some(first.as_path()));
+        assert_eq!(
+            wait_for_metadata(&mut player, &notices).duration_secs,
+            Some(3)
+        );
+
+        // Tags read while the next track was queued are there as soon as it plays
+        player.queue_next(Some(&second)).unwrap();
+        for _ in 0..100 {
+            while let Ok(notice) = notices.try_recv() {
+                player.handle_notice(notice);
+            }
+            if player.preloaded_metadata(&second).is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        player.play(&second).unwrap();
+        let metadata = player.current_metadata().unwrap();
+        assert_eq!(metadata.duration_secs, Some(5));
+        player.stop();
+    }
+
+    #[test]
+    fn test_resumes_where_playback_was_left() {
+        let temp_dir = TempDir::new().unwrap();
+        let track = temp_dir.path().join("mix.wav");
+        write_wav(&track, 1, 8000, 8000 * 60, 1000);
+
+        let (mut player, notices) = null_player();
+        player.set_resume_policy(ResumePolicy {
+            min_duration: None,
+            dirs: vec![temp_dir.path().to_path_buf()],
+        });
+        let mut positions = ResumePositions::default();
+        positions.update(
+            &track,
+            Duration::from_secs(20),
+            Some(Duration::from_secs(60)),
+        );
+        player.set_resume_positions(positions);
+
+        player.play(&track).unwrap();
+        wait_for_metadata(&mut player, &notices);
+        let position = player.position();
+        assert!(
+            position >= Duration::from_secs(20) && position < Duration::from_secs(21),
+            "{position:?}"
+        );
+
+        // Leaving the track records how far it got
+        player.seek_to(Duration::from_secs(30));
+        std::thread::sleep(Duration::from_millis(100));
+        player.stop();
+        let point = player.resume_positions().get(&track).unwrap();
+        assert!(point.position() >= Duration::from_secs(30));
+        assert_eq!(point.percent(), Some(50));
+
+        // Tracks outside the policy are left alone
+        player.set_resume_policy(ResumePolicy::default());
+        player.play(&track).unwrap();
+        player.seek_to(Duration::from_secs(45));
+        std::thread::sleep(Duration::from_millis(100));
+        player.stop();
+        assert_eq!(player.resume_positions().get(&track), Some(point));
+    }
+
+    #[test]
+    fn test_unreadable_tags_still_count_as_loaded() {
+        let metadata = read_tags(Path::new("/nonexistent/track.flac"));
+        assert!(metadata.title.is_none());
+        assert!(metadata.cover_art.is_none());
+    }
+
+    #[test]
+    fn test_fade_curves_start_and_end() {
+        for curve in [FadeCurve::Linear, FadeCurve::EqualPower, FadeCurve::SCurve] {
+            let (out_gain, in_gain) = curve.gains(0.0);
+            assert!((out_gain - 1.0).abs() < 1e-6);
+            assert!(in_gain.abs() < 1e-6);
+
+            let (out_gain, in_gain) = curve.gains(1.0);
+            assert!(out_gain.abs() < 1e-6);
+            assert!((in_gain - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_equal_power_fade_keeps_power() {
+        for step in 0..=10 {
+            let (out_gain, in_gain) = FadeCurve::EqualPower.gains(step as f32 / 10.0);
+            assert!((out_gain * out_gain + in_gain * in_gain - 1.0).abs() < 1e-5);
+        }
+    }
+}
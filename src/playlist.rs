@@ -33,13 +33,14 @@ impl Playlist {
             .unwrap_or("Unnamed")
             .to_string();
 
-        let mut tracks = Vec::new();
-        for line in content.lines() {
-            let line = line.trim();
-            if !line.is_empty() && !line.starts_with('#') {
-                tracks.push(PathBuf::from(line));
-            }
-        }
+        let is_pls = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pls"));
+        let tracks = if is_pls {
+            parse_pls(&content)
+        } else {
+            parse_m3u(&content)
+        };
 
         Ok(Self {
             name,
@@ -115,7 +116,7 @@ impl PlaylistManager {
                 let path = entry.path();
                 if path.is_file() {
                     if let Some(ext) = path.extension() {
-                        if ext == "m3u" || ext == "m3u8" {
+                        if ext == "m3u" || ext == "m3u8" || ext == "pls" {
                             match Playlist::load(&path) {
                                 Ok(playlist) => {
                                     logger::log(&format!(
@@ -216,6 +217,30 @@ impl PlaylistManager {
     }
 }
 
+fn parse_m3u(content: &str) -> Vec<PathBuf> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect()
+}
+
+// PLS entries look like `File1=http://...`; titles and lengths are ignored
+fn parse_pls(content: &str) -> Vec<PathBuf> {
+    content
+        .lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .filter(|(key, _)| {
+            key.get(..4)
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case("file"))
+                && key[4..].chars().all(|c| c.is_ascii_digit())
+        })
+        .map(|(_, value)| PathBuf::from(value.trim()))
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect()
+}
+
 fn playlist_filename(name: &str) -> String {
     let trimmed = name.trim();
     let mut base = if trimmed.is_empty() {
@@ -327,6 +352,43 @@ mod tests {
         let _ = fs::remove_file(&temp_file);
     }
 
+    #[test]
+    fn test_playlist_keeps_cue_tracks() {
+        let temp_file = std::env::temp_dir().join("test_playlist_cue.m3u");
+        let _ = fs::remove_file(&temp_file);
+
+        let track = crate::cue::track_path(Path::new("/music/Album.cue"), 3);
+        let mut playlist = Playlist::new("Test".to_string(), temp_file.clone());
+        playlist.add_track(track.clone());
+        playlist.save().unwrap();
+
+        let loaded = Playlist::load(&temp_file).unwrap();
+        assert_eq!(loaded.tracks, vec![track]);
+
+        let _ = fs::remove_file(&temp_file);
+    }
+
+    #[test]
+    fn test_playlist_load_pls() {
+        let temp_file = std::env::temp_dir().join("test_playlist_radio.pls");
+        let _ = fs::remove_file(&temp_file);
+
+        let content = "[playlist]\nNumberOfEntries=2\nFile1=http://radio.example/stream\nTitle1=Radio\nLength1=-1\nFile2=/music/track1.mp3\nVersion=2\n";
+        fs::write(&temp_file, content).unwrap();
+
+        let loaded = Playlist::load(&temp_file).unwrap();
+        assert_eq!(loaded.name, "test_playlist_radio");
+        assert_eq!(
+            loaded.tracks,
+            vec![
+                PathBuf::from("http://radio.example/stream"),
+                PathBuf::from("/music/track1.mp3")
+            ]
+        );
+
+        let _ = fs::remove_file(&temp_file);
+    }
+
     #[test]
     fn test_playlist_load_ignores_comments() {
         let temp_dir = std::env::temp_dir();
@@ -1,10 +1,13 @@
 use anyhow::{Context, Result};
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::fs;
 use std::path::PathBuf;
 
+// Queue edits that can be undone, oldest dropped first
+const HISTORY_LIMIT: usize = 100;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum RepeatMode {
     #[default]
@@ -31,6 +34,15 @@ impl RepeatMode {
     }
 }
 
+// The queue on one side of an edit, with the row selected in the UI at the time
+#[derive(Debug, Clone)]
+struct Snapshot {
+    edit: &'static str,
+    tracks: Vec<PathBuf>,
+    current_index: Option<usize>,
+    selected: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Queue {
     tracks: Vec<PathBuf>,
@@ -40,6 +52,15 @@ pub struct Queue {
     repeat_mode: RepeatMode,
     #[serde(skip)]
     played_indices: HashSet<usize>,
+    #[serde(skip)]
+    upcoming_random: Option<usize>,
+    // End of the tracks `insert_next` put after the current one, where the next ones go
+    #[serde(skip)]
+    inserted_end: Option<usize>,
+    #[serde(skip)]
+    undo_stack: VecDeque<Snapshot>,
+    #[serde(skip)]
+    redo_stack: Vec<Snapshot>,
 }
 
 impl Queue {
@@ -50,6 +71,10 @@ impl Queue {
             random_mode: false,
             repeat_mode: RepeatMode::Off,
             played_indices: HashSet::new(),
+            upcoming_random: None,
+            inserted_end: None,
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
         }
     }
 
@@ -67,9 +92,43 @@ impl Queue {
         }
     }
 
+    /// Inserts `tracks` to play right after the current one, in random mode too. Tracks
+    /// inserted this way one after the other keep their order, until playback moved past
+    /// them or the queue was edited otherwise. Returns the row the first one landed on.
+    pub fn insert_next(&mut self, tracks: Vec<PathBuf>) -> usize {
+        let Some(current) = self.current_index else {
+            let index = self.tracks.len();
+            self.add_multiple(tracks);
+            return index;
+        };
+        let index = self
+            .inserted_end
+            .filter(|&end| end > current && end <= self.tracks.len())
+            .unwrap_or(current + 1);
+        let count = tracks.len();
+        self.tracks.splice(index..index, tracks);
+        self.inserted_end = Some(index + count);
+        self.upcoming_random = None;
+        self.played_indices = self
+            .played_indices
+            .iter()
+            .map(|&row| shift_for_insert(row, index, count))
+            .collect();
+        index
+    }
+
+    // The inserted track due after the current one, if playback is still in that range
+    fn inserted_next(&self) -> Option<usize> {
+        let next = self.current_index? + 1;
+        self.inserted_end.filter(|&end| next < end)?;
+        Some(next)
+    }
+
     pub fn remove(&mut self, index: usize) {
         if index < self.tracks.len() {
             self.tracks.remove(index);
+            self.upcoming_random = None;
+            self.inserted_end = None;
 
             // Update current_index if needed
             if let Some(current) = self.current_index {
@@ -91,6 +150,8 @@ impl Queue {
     pub fn clear(&mut self) {
         self.tracks.clear();
         self.current_index = None;
+        self.upcoming_random = None;
+        self.inserted_end = None;
     }
 
     pub fn current(&self) -> Option<&PathBuf> {
@@ -107,23 +168,26 @@ impl Queue {
             if let Some(current) = self.current_index {
                 self.played_indices.insert(current);
             }
+            if let Some(next_idx) = self.inserted_next() {
+                self.current_index = Some(next_idx);
+                return self.current();
+            }
+            // Tracks inserted to play next may be left behind anywhere
+            self.inserted_end = None;
 
             // If all tracks have been played, reset
             if self.played_indices.len() >= self.tracks.len() {
                 self.played_indices.clear();
             }
 
-            // Find unplayed tracks
-            let unplayed: Vec<usize> = (0..self.tracks.len())
-                .filter(|i| !self.played_indices.contains(i))
-                .collect();
-
-            if !unplayed.is_empty() {
-                let mut rng = rand::thread_rng();
-                if let Some(&next_idx) = unplayed.choose(&mut rng) {
-                    self.current_index = Some(next_idx);
-                    return self.current();
-                }
+            // Use the pick reserved by peek_next, if any, so gapless preloading stays correct
+            let reserved = self
+                .upcoming_random
+                .take()
+                .filter(|i| *i < self.tracks.len() && !self.played_indices.contains(i));
+            if let Some(next_idx) = reserved.or_else(|| self.pick_unplayed()) {
+                self.current_index = Some(next_idx);
+                return self.current();
             }
             None
         } else {
@@ -138,10 +202,65 @@ impl Queue {
         }
     }
 
+    /// Returns the track that will play after the current one finishes, taking the
+    /// repeat mode into account. In random mode the pick is reserved, so the following
+    /// `next()` lands on the same track.
+    pub fn peek_next(&mut self) -> Option<&PathBuf> {
+        let current = self.current_index?;
+
+        if self.repeat_mode == RepeatMode::Track {
+            return self.current();
+        }
+
+        if self.random_mode {
+            if let Some(next_idx) = self.inserted_next() {
+                return self.tracks.get(next_idx);
+            }
+            if self.upcoming_random.is_none() {
+                // Mirror next(): the current track counts as played
+                let mut played = self.played_indices.clone();
+                played.insert(current);
+                if played.len() >= self.tracks.len() {
+                    played.clear();
+                }
+                let unplayed: Vec<usize> = (0..self.tracks.len())
+                    .filter(|i| !played.contains(i))
+                    .collect();
+                self.upcoming_random = unplayed.choose(&mut rand::thread_rng()).copied();
+            }
+            return self.upcoming_random.and_then(|idx| self.tracks.get(idx));
+        }
+
+        if current + 1 < self.tracks.len() {
+            self.tracks.get(current + 1)
+        } else if self.repeat_mode == RepeatMode::Queue {
+            self.tracks.first()
+        } else {
+            None
+        }
+    }
+
+    /// Whether `peek_next` has a track to offer, without reserving a random pick.
+    /// Random mode always goes on, starting over once every track played.
+    pub fn has_next(&self) -> bool {
+        let Some(current) = self.current_index else {
+            return false;
+        };
+        self.random_mode || self.repeat_mode != RepeatMode::Off || current + 1 < self.tracks.len()
+    }
+
+    fn pick_unplayed(&self) -> Option<usize> {
+        let unplayed: Vec<usize> = (0..self.tracks.len())
+            .filter(|i| !self.played_indices.contains(i))
+            .collect();
+        unplayed.choose(&mut rand::thread_rng()).copied()
+    }
+
     pub fn prev(&mut self) -> Option<&PathBuf> {
         if let Some(current) = self.current_index {
             if current > 0 {
                 self.current_index = Some(current - 1);
+                self.inserted_end = None;
                 return self.current();
             }
         }
@@ -151,6 +270,8 @@ impl Queue {
     pub fn jump_to(&mut self, index: usize) -> Option<&PathBuf> {
         if index < self.tracks.len() {
             self.current_index = Some(index);
+            self.upcoming_random = None;
+            self.inserted_end = None;
             self.current()
         } else {
             None
@@ -175,6 +296,7 @@ impl Queue {
 
     pub fn toggle_random(&mut self) {
         self.random_mode = !self.random_mode;
+        self.upcoming_random = None;
         if self.random_mode {
             self.played_indices.clear();
             // Disable track repeat when enabling random mode
@@ -190,6 +312,7 @@ impl Queue {
 
     pub fn cycle_repeat_mode(&mut self) {
         self.repeat_mode = self.repeat_mode.next();
+        self.upcoming_random = None;
         // Disable random mode when enabling track repeat
         if self.repeat_mode == RepeatMode::Track && self.random_mode {
             self.random_mode = false;
@@ -204,6 +327,8 @@ impl Queue {
     pub fn restart(&mut self) -> Option<&PathBuf> {
         if !self.tracks.is_empty() {
             self.current_index = Some(0);
+            self.upcoming_random = None;
+            self.inserted_end = None;
             if self.random_mode {
                 self.played_indices.clear();
             }
@@ -219,6 +344,8 @@ impl Queue {
         }
 
         self.tracks.swap(index, index - 1);
+        self.upcoming_random = None;
+        self.inserted_end = None;
         if let Some(current) = self.current_index {
             if current == index {
                 self.current_index = Some(index - 1);
@@ -234,6 +361,8 @@ impl Queue {
         }
 
         self.tracks.swap(index, index + 1);
+        self.upcoming_random = None;
+        self.inserted_end = None;
         if let Some(current) = self.current_index {
             if current == index {
                 self.current_index = Some(index + 1);
@@ -243,6 +372,111 @@ impl Queue {
         }
     }
 
+    /// Removes the tracks at `indices`, given in ascending order.
+    pub fn remove_many(&mut self, indices: &[usize]) {
+        for &index in indices.iter().rev() {
+            self.remove(index);
+        }
+    }
+
+    /// Moves the tracks at `indices`, given in ascending order, one row up together.
+    /// Nothing moves when the first one is already at the top.
+    pub fn move_block_up(&mut self, indices: &[usize]) {
+        if indices.first().is_none_or(|&first| first == 0) {
+            return;
+        }
+        for &index in indices {
+            self.move_up(index);
+        }
+    }
+
+    /// Moves the tracks at `indices`, given in ascending order, one row down together.
+    /// Nothing moves when the last one is already at the bottom.
+    pub fn move_block_down(&mut self, indices: &[usize]) {
+        if indices
+            .last()
+            .is_none_or(|&last| last + 1 >= self.tracks.len())
+        {
+            return;
+        }
+        for &index in indices.iter().rev() {
+            self.move_down(index);
+        }
+    }
+
+    /// Shuffles the tracks at `indices` among those same rows, the current track
+    /// included; the other rows stay where they are.
+    pub fn shuffle_tracks(&mut self, indices: &[usize]) {
+        let indices: Vec<usize> = indices
+            .iter()
+            .copied()
+            .filter(|&index| index < self.tracks.len())
+            .collect();
+        let mut order = indices.clone();
+        order.shuffle(&mut rand::thread_rng());
+
+        let tracks = self.tracks.clone();
+        let mut current_index = self.current_index;
+        for (&row, &from) in indices.iter().zip(&order) {
+            self.tracks[row] = tracks[from].clone();
+            if self.current_index == Some(from) {
+                current_index = Some(row);
+            }
+        }
+        self.current_index = current_index;
+        self.upcoming_random = None;
+        self.inserted_end = None;
+    }
+
+    /// Records the queue as it is before `edit`, so that `undo` can bring it back.
+    /// `selected` is the row selected at the time, handed back on undo.
+    pub fn checkpoint(&mut self, edit: &'static str, selected: usize) {
+        let snapshot = self.snapshot(edit, selected);
+        self.push_undo(snapshot);
+        self.redo_stack.clear();
+    }
+
+    /// Reverts the last edit, current track included. Takes the row selected now and
+    /// returns the edit along with the row to select, or `None` with nothing to undo.
+    pub fn undo(&mut self, selected: usize) -> Option<(&'static str, usize)> {
+        let snapshot = self.undo_stack.pop_back()?;
+        self.redo_stack.push(self.snapshot(snapshot.edit, selected));
+        Some(self.restore(snapshot))
+    }
+
+    /// Applies again the last edit undone, like `undo`.
+    pub fn redo(&mut self, selected: usize) -> Option<(&'static str, usize)> {
+        let snapshot = self.redo_stack.pop()?;
+        let current = self.snapshot(snapshot.edit, selected);
+        self.push_undo(current);
+        Some(self.restore(snapshot))
+    }
+
+    fn snapshot(&self, edit: &'static str, selected: usize) -> Snapshot {
+        Snapshot {
+            edit,
+            tracks: self.tracks.clone(),
+            current_index: self.current_index,
+            selected,
+        }
+    }
+
+    fn push_undo(&mut self, snapshot: Snapshot) {
+        if self.undo_stack.len() == HISTORY_LIMIT {
+            self.undo_stack.pop_front();
+        }
+        self.undo_stack.push_back(snapshot);
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> (&'static str, usize) {
+        self.tracks = snapshot.tracks;
+        self.current_index = snapshot.current_index;
+        self.upcoming_random = None;
+        self.inserted_end = None;
+        let selected = snapshot.selected.min(self.tracks.len().saturating_sub(1));
+        (snapshot.edit, selected)
+    }
+
     pub fn save(&self) -> Result<()> {
         let queue_path = Self::queue_path();
 
@@ -278,6 +512,11 @@ impl Queue {
     }
 }
 
+/// Where a row ends up once `count` tracks were inserted at `index`.
+pub fn shift_for_insert(row: usize, index: usize, count: usize) -> usize {
+    if row >= index { row + count } else { row }
+}
+
 impl Default for Queue {
     fn default() -> Self {
         Self::new()
@@ -620,4 +859,245 @@ mod tests {
         assert_eq!(queue.next(), Some(&tracks[1]));
         assert_eq!(queue.next(), Some(&tracks[2]));
     }
+
+    #[test]
+    fn test_queue_serializes_cue_tracks() {
+        let mut queue = Queue::new();
+        let tracks = vec![
+            crate::cue::track_path(std::path::Path::new("/music/Album.cue"), 1),
+            crate::cue::track_path(std::path::Path::new("/music/Album.cue"), 2),
+        ];
+        queue.add_multiple(tracks.clone());
+        queue.next();
+
+        let restored: Queue =
+            serde_json::from_str(&serde_json::to_string(&queue).unwrap()).unwrap();
+        assert_eq!(restored.tracks(), &tracks[..]);
+        assert_eq!(restored.current(), Some(&tracks[1]));
+    }
+
+    #[test]
+    fn test_peek_next_sequential() {
+        let mut queue = Queue::new();
+        let tracks = vec![
+            PathBuf::from("/music/track1.mp3"),
+            PathBuf::from("/music/track2.mp3"),
+        ];
+        queue.add_multiple(tracks.clone());
+
+        assert_eq!(queue.peek_next(), Some(&tracks[1]));
+        assert_eq!(queue.current_index(), Some(0));
+        assert!(queue.has_next());
+        queue.next();
+        assert_eq!(queue.peek_next(), None);
+        assert!(!queue.has_next());
+
+        // Random mode goes on whatever row it is on
+        queue.toggle_random();
+        assert!(queue.has_next());
+    }
+
+    #[test]
+    fn test_peek_next_respects_repeat_modes() {
+        let mut queue = Queue::new();
+        let tracks = vec![
+            PathBuf::from("/music/track1.mp3"),
+            PathBuf::from("/music/track2.mp3"),
+        ];
+        queue.add_multiple(tracks.clone());
+        queue.jump_to(1);
+
+        queue.cycle_repeat_mode(); // Queue
+        assert_eq!(queue.peek_next(), Some(&tracks[0]));
+        assert!(queue.has_next());
+
+        queue.cycle_repeat_mode(); // Track
+        assert_eq!(queue.peek_next(), Some(&tracks[1]));
+    }
+
+    #[test]
+    fn test_peek_next_random_matches_next() {
+        let mut queue = Queue::new();
+        let tracks: Vec<PathBuf> = (1..=10)
+            .map(|i| PathBuf::from(format!("/music/track{i}.mp3")))
+            .collect();
+        queue.add_multiple(tracks);
+        queue.toggle_random();
+
+        for _ in 0..20 {
+            let peeked = queue.peek_next().cloned();
+            assert_eq!(queue.next().cloned(), peeked);
+        }
+    }
+
+    #[test]
+    fn test_undo_redo_restores_tracks_current_and_selection() {
+        let mut queue = Queue::new();
+        let tracks: Vec<PathBuf> = (1..=4)
+            .map(|i| PathBuf::from(format!("/music/track{i}.mp3")))
+            .collect();
+        queue.add_multiple(tracks.clone());
+        queue.jump_to(2);
+
+        queue.checkpoint("remove track", 1);
+        queue.remove(1);
+        queue.checkpoint("clear queue", 0);
+        queue.clear();
+        assert!(queue.is_empty());
+
+        assert_eq!(queue.undo(0), Some(("clear queue", 0)));
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.current(), Some(&tracks[2]));
+        assert_eq!(queue.undo(0), Some(("remove track", 1)));
+        assert_eq!(queue.tracks(), &tracks[..]);
+        assert_eq!(queue.current_index(), Some(2));
+        assert_eq!(queue.undo(1), None);
+
+        // Redo comes back to the selection the undo was made from
+        assert_eq!(queue.redo(1), Some(("remove track", 0)));
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.current(), Some(&tracks[2]));
+
+        // A new edit forgets what was undone
+        queue.checkpoint("move track", 0);
+        queue.move_down(0);
+        assert_eq!(queue.redo(0), None);
+        assert_eq!(queue.undo(1), Some(("move track", 0)));
+        assert_eq!(queue.tracks()[0], tracks[0]);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut queue = Queue::new();
+        for i in 0..HISTORY_LIMIT + 5 {
+            queue.checkpoint("add to queue", 0);
+            queue.add(PathBuf::from(format!("/music/track{i}.mp3")));
+        }
+
+        let mut undone = 0;
+        while queue.undo(0).is_some() {
+            undone += 1;
+        }
+        assert_eq!(undone, HISTORY_LIMIT);
+        assert_eq!(queue.len(), 5);
+    }
+
+    #[test]
+    fn test_block_moves_keep_rows_together() {
+        let mut queue = Queue::new();
+        let tracks: Vec<PathBuf> = (0..6)
+            .map(|i| PathBuf::from(format!("/music/track{i}.mp3")))
+            .collect();
+        queue.add_multiple(tracks.clone());
+        queue.jump_to(3);
+
+        queue.move_block_up(&[2, 3, 5]);
+        let order = [0, 2, 3, 1, 5, 4].map(|i| tracks[i].clone());
+        assert_eq!(queue.tracks(), &order[..]);
+        assert_eq!(queue.current(), Some(&tracks[3]));
+
+        // The block stops at the edges
+        queue.move_block_up(&[0, 1]);
+        assert_eq!(queue.tracks(), &order[..]);
+        queue.move_block_down(&[1, 2, 4]);
+        let order = [0, 1, 2, 3, 4, 5].map(|i| tracks[i].clone());
+        assert_eq!(queue.tracks(), &order[..]);
+        assert_eq!(queue.current_index(), Some(3));
+        queue.move_block_down(&[4, 5]);
+        assert_eq!(queue.tracks(), &order[..]);
+    }
+
+    #[test]
+    fn test_remove_many_and_shuffle_selection() {
+        let mut queue = Queue::new();
+        let tracks: Vec<PathBuf> = (0..8)
+            .map(|i| PathBuf::from(format!("/music/track{i}.mp3")))
+            .collect();
+        queue.add_multiple(tracks.clone());
+        queue.jump_to(4);
+
+        queue.remove_many(&[1, 2, 6]);
+        let order = [0, 3, 4, 5, 7].map(|i| tracks[i].clone());
+        assert_eq!(queue.tracks(), &order[..]);
+        assert_eq!(queue.current(), Some(&tracks[4]));
+
+        // Only the chosen rows move, and the current track is followed
+        queue.shuffle_tracks(&[1, 2, 3]);
+        assert_eq!(queue.tracks()[0], tracks[0]);
+        assert_eq!(queue.tracks()[4], tracks[7]);
+        let mut middle = queue.tracks()[1..4].to_vec();
+        middle.sort();
+        assert_eq!(middle, [3, 4, 5].map(|i| tracks[i].clone()));
+        assert_eq!(queue.current(), Some(&tracks[4]));
+    }
+
+    #[test]
+    fn test_insert_next_keeps_insertion_order() {
+        let track = |name: &str| PathBuf::from(format!("/music/{name}.mp3"));
+        let mut queue = Queue::new();
+        queue.add_multiple(vec![track("a"), track("b"), track("c")]);
+
+        queue.insert_next(vec![track("x")]);
+        queue.insert_next(vec![track("y"), track("z")]);
+        let names = ["a", "x", "y", "z", "b", "c"].map(track);
+        assert_eq!(queue.tracks(), &names[..]);
+        assert_eq!(queue.current(), Some(&track("a")));
+
+        // Still after the earlier requests once the first of them plays
+        assert_eq!(queue.next(), Some(&track("x")));
+        queue.insert_next(vec![track("w")]);
+        assert_eq!(queue.tracks()[4], track("w"));
+
+        // Right after the current track again once playback moved past them
+        queue.jump_to(5);
+        queue.insert_next(vec![track("v")]);
+        assert_eq!(queue.tracks()[6], track("v"));
+        assert_eq!(queue.current(), Some(&track("b")));
+    }
+
+    #[test]
+    fn test_insert_next_into_empty_queue() {
+        let mut queue = Queue::new();
+        assert_eq!(
+            queue.insert_next(vec![PathBuf::from("/music/track1.mp3")]),
+            0
+        );
+        assert_eq!(queue.current(), Some(&PathBuf::from("/music/track1.mp3")));
+    }
+
+    #[test]
+    fn test_insert_next_rows_follow_their_tracks() {
+        let track = |name: &str| PathBuf::from(format!("/music/{name}.mp3"));
+        let mut queue = Queue::new();
+        queue.add_multiple(vec![track("a"), track("b"), track("c")]);
+        let marked = [0, 2].map(|row| queue.tracks()[row].clone());
+
+        let index = queue.insert_next(vec![track("x"), track("y")]);
+        assert_eq!(index, 1);
+        let rows = [0, 2].map(|row| shift_for_insert(row, index, 2));
+        assert_eq!(rows, [0, 4]);
+        queue.remove_many(&rows);
+        assert!(marked.iter().all(|track| !queue.tracks().contains(track)));
+        assert_eq!(queue.tracks(), &["x", "y", "b"].map(track)[..]);
+    }
+
+    #[test]
+    fn test_insert_next_plays_next_in_random_mode() {
+        let track = |name: &str| PathBuf::from(format!("/music/{name}.mp3"));
+        let mut queue = Queue::new();
+        queue.add_multiple((0..20).map(|i| track(&i.to_string())).collect());
+        queue.toggle_random();
+        queue.next();
+        let played = queue.current().cloned().unwrap();
+
+        queue.insert_next(vec![track("x"), track("y")]);
+        assert_eq!(queue.peek_next(), Some(&track("x")));
+        assert_eq!(queue.next(), Some(&track("x")));
+        assert_eq!(queue.next(), Some(&track("y")));
+
+        // The track played before the insert still counts as played
+        for _ in 0..18 {
+            assert_ne!(queue.next(), Some(&played));
+        }
+    }
 }
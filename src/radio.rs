@@ -0,0 +1,548 @@
+use crate::logger;
+use crate::metadata::TrackMetadata;
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, Response};
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use symphonia::core::io::MediaSource;
+
+// Bytes held ahead of the decoder; the connection waits while the buffer is full
+const BUFFER_BYTES: usize = 512 * 1024;
+// Bytes gathered before playback starts, and again whenever the buffer ran dry
+const PREBUFFER_BYTES: usize = 32 * 1024;
+// Bytes left under which the buffer counts as dry, so no packet runs out half way
+const PACKET_BYTES: usize = 16 * 1024;
+// Connection attempts in a row after the stream dropped, before giving up
+const RECONNECT_ATTEMPTS: u32 = 5;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Longest wait for a single read of the body before the connection counts as stalled
+const READ_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Whether a queue entry is an http(s) stream rather than a local file.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// What a station tells about itself and the song on air.
+#[derive(Debug, Default)]
+pub struct StationInfo {
+    pub name: Option<String>,
+    pub genre: Option<String>,
+    title: Mutex<Option<String>>,
+}
+
+impl StationInfo {
+    /// The latest ICY `StreamTitle`, usually "Artist - Title".
+    pub fn title(&self) -> Option<String> {
+        self.title.lock().unwrap().clone()
+    }
+
+    /// Shows the song on air as the track, and the station as its album.
+    pub fn apply(&self, metadata: &mut TrackMetadata) {
+        match self.title() {
+            Some(title) => match title.split_once(" - ") {
+                Some((artist, song)) => {
+                    metadata.artist = Some(artist.trim().to_string());
+                    metadata.title = Some(song.trim().to_string());
+                }
+                None => metadata.title = Some(title),
+            },
+            None => metadata.title.clone_from(&self.name),
+        }
+        metadata.album.clone_from(&self.name);
+        metadata.genre.clone_from(&self.genre);
+    }
+}
+
+#[derive(Default)]
+struct Buffer {
+    data: VecDeque<u8>,
+    // Playback waits for `PREBUFFER_BYTES` while set
+    buffering: bool,
+    // No more data is coming
+    finished: bool,
+    // The reader is gone, so the connection can be dropped
+    closed: bool,
+}
+
+struct Shared {
+    buffer: Mutex<Buffer>,
+    changed: Condvar,
+    info: Arc<StationInfo>,
+}
+
+impl Shared {
+    // Waits for room, then appends. Returns false once the reader is gone.
+    fn push(&self, bytes: &[u8]) -> bool {
+        let mut buffer = self.buffer.lock().unwrap();
+        while buffer.data.len() >= BUFFER_BYTES && !buffer.closed {
+            buffer = self.changed.wait(buffer).unwrap();
+        }
+        if buffer.closed {
+            return false;
+        }
+        buffer.data.extend(bytes);
+        self.changed.notify_all();
+        true
+    }
+
+    fn is_closed(&self) -> bool {
+        self.buffer.lock().unwrap().closed
+    }
+
+    fn finish(&self) {
+        self.buffer.lock().unwrap().finished = true;
+        self.changed.notify_all();
+    }
+}
+
+/// An http(s) audio stream read by symphonia like a file that cannot seek. A background
+/// thread downloads into a buffer, strips ICY metadata and reconnects when the
+/// connection drops.
+pub struct HttpStream {
+    shared: Arc<Shared>,
+    content_type: Option<String>,
+}
+
+impl HttpStream {
+    /// Connects to `url` and starts buffering. Fails when the server cannot be reached
+    /// or answers with an error.
+    pub fn open(url: &str) -> Result<Self> {
+        Self::open_with_timeout(url, READ_TIMEOUT)
+    }
+
+    fn open_with_timeout(url: &str, read_timeout: Duration) -> Result<Self> {
+        // The blocking client times each read of the body, not the whole stream
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(read_timeout)
+            .build()?;
+        let response = connect(&client, url)?;
+
+        let content_type = header(&response, "content-type");
+        let info = Arc::new(StationInfo {
+            name: header(&response, "icy-name"),
+            genre: header(&response, "icy-genre"),
+            title: Mutex::new(None),
+        });
+        let shared = Arc::new(Shared {
+            buffer: Mutex::new(Buffer {
+                buffering: true,
+                ..Buffer::default()
+            }),
+            changed: Condvar::new(),
+            info,
+        });
+
+        let fetch_shared = Arc::clone(&shared);
+        let url = url.to_string();
+        thread::spawn(move || fetch(&client, &url, response, &fetch_shared));
+
+        Ok(Self {
+            shared,
+            content_type,
+        })
+    }
+
+    pub fn info(&self) -> Arc<StationInfo> {
+        Arc::clone(&self.shared.info)
+    }
+
+    pub fn gate(&self) -> StreamGate {
+        StreamGate(Arc::clone(&self.shared))
+    }
+
+    /// File extension matching the announced content type, as a hint for symphonia.
+    pub fn extension(&self) -> Option<&'static str> {
+        let content_type = self.content_type.as_deref()?;
+        let mime = content_type.split(';').next()?.trim();
+        match mime {
+            "audio/mpeg" | "audio/mp3" => Some("mp3"),
+            "audio/aac" | "audio/aacp" => Some("aac"),
+            "audio/ogg" | "application/ogg" | "audio/opus" => Some("ogg"),
+            "audio/flac" | "audio/x-flac" => Some("flac"),
+            "audio/wav" | "audio/x-wav" | "audio/wave" => Some("wav"),
+            _ => None,
+        }
+    }
+}
+
+/// Tells the audio thread whether the next packet can be read without waiting on the
+/// network. Reads made regardless wait for the buffer, which is fine while probing.
+pub struct StreamGate(Arc<Shared>);
+
+impl StreamGate {
+    /// False from the moment the buffer runs dry until it gathered a little again.
+    pub fn is_ready(&self) -> bool {
+        let mut buffer = self.0.buffer.lock().unwrap();
+        if buffer.finished {
+            return true;
+        }
+        if buffer.buffering {
+            buffer.buffering = buffer.data.len() < PREBUFFER_BYTES;
+        } else if buffer.data.len() < PACKET_BYTES {
+            buffer.buffering = true;
+        }
+        !buffer.buffering
+    }
+}
+
+impl Drop for HttpStream {
+    fn drop(&mut self) {
+        self.shared.buffer.lock().unwrap().closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        loop {
+            if buffer.buffering && buffer.data.len() < PREBUFFER_BYTES && !buffer.finished {
+                buffer = self.shared.changed.wait(buffer).unwrap();
+                continue;
+            }
+            buffer.buffering = false;
+            if !buffer.data.is_empty() {
+                break;
+            }
+            if buffer.finished {
+                return Ok(0);
+            }
+            // Ran dry: gather a little again rather than stutter along
+            buffer.buffering = true;
+        }
+
+        let len = buf.len().min(buffer.data.len());
+        for (to, from) in buf.iter_mut().zip(buffer.data.drain(..len)) {
+            *to = from;
+        }
+        self.shared.changed.notify_all();
+        Ok(len)
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "streams cannot seek",
+        ))
+    }
+}
+
+impl MediaSource for HttpStream {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+fn connect(client: &Client, url: &str) -> Result<Response> {
+    client
+        .get(url)
+        .header("Icy-MetaData", "1")
+        .send()
+        .and_then(Response::error_for_status)
+        .with_context(|| format!("Failed to connect to {url}"))
+}
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)?
+        .to_str()
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+enum Outcome {
+    // The reader is gone
+    Closed,
+    // The server closed the connection
+    Ended,
+    Failed(io::Error),
+}
+
+// Downloads until the reader is gone, reconnecting when a live stream drops
+fn fetch(client: &Client, url: &str, mut response: Response, shared: &Shared) {
+    let mut attempts = 0;
+    loop {
+        // A known length means a plain file, which ends for good
+        let is_file = response.content_length().is_some();
+        let metaint = header(&response, "icy-metaint").and_then(|n| n.parse().ok());
+        let (outcome, received) = pump(&mut response, metaint, shared);
+        match outcome {
+            Outcome::Closed => return,
+            Outcome::Ended if is_file => break,
+            Outcome::Ended => logger::log(&format!("Stream {url} ended, reconnecting")),
+            Outcome::Failed(e) => logger::log(&format!("Stream {url} dropped: {e}, reconnecting")),
+        }
+        if received > 0 {
+            attempts = 0;
+        }
+
+        let reconnected = loop {
+            if attempts >= RECONNECT_ATTEMPTS || shared.is_closed() {
+                break None;
+            }
+            // The first retry goes right away, later ones back off
+            thread::sleep(Duration::from_secs(u64::from(attempts)));
+            attempts += 1;
+            match connect(client, url) {
+                Ok(response) => break Some(response),
+                Err(e) => {
+                    logger::log(&format!("{e:#}"));
+                    // An error status will not change by asking again
+                    if e.downcast_ref::<reqwest::Error>()
+                        .is_some_and(reqwest::Error::is_status)
+                    {
+                        break None;
+                    }
+                }
+            }
+        };
+        match reconnected {
+            Some(next) => response = next,
+            None => break,
+        }
+    }
+    shared.finish();
+}
+
+// Copies the body into the buffer, taking out the metadata block that follows every
+// `metaint` bytes of audio. Returns how it ended and how much audio came through.
+fn pump(response: &mut impl Read, metaint: Option<usize>, shared: &Shared) -> (Outcome, usize) {
+    let mut chunk = [0u8; 8192];
+    let mut received = 0;
+    let mut until_metadata = metaint;
+    loop {
+        let want = until_metadata.map_or(chunk.len(), |left| left.min(chunk.len()));
+        let len = match response.read(&mut chunk[..want]) {
+            Ok(0) => return (Outcome::Ended, received),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return (Outcome::Failed(e), received),
+        };
+        if !shared.push(&chunk[..len]) {
+            return (Outcome::Closed, received);
+        }
+        received += len;
+
+        if let (Some(left), Some(interval)) = (&mut until_metadata, metaint) {
+            *left -= len;
+            if *left == 0 {
+                match read_metadata(response) {
+                    Ok(Some(title)) => *shared.info.title.lock().unwrap() = Some(title),
+                    Ok(None) => {}
+                    Err(e) => return (Outcome::Failed(e), received),
+                }
+                *left = interval;
+            }
+        }
+    }
+}
+
+// Reads one ICY metadata block: a length byte counting 16-byte units, then the text
+fn read_metadata(response: &mut impl Read) -> io::Result<Option<String>> {
+    let mut len = [0u8];
+    response.read_exact(&mut len)?;
+    if len[0] == 0 {
+        return Ok(None);
+    }
+    let mut block = vec![0u8; usize::from(len[0]) * 16];
+    response.read_exact(&mut block)?;
+    Ok(parse_stream_title(&block))
+}
+
+// Picks the title out of "StreamTitle='Artist - Title';StreamUrl='...';"
+fn parse_stream_title(block: &[u8]) -> Option<String> {
+    let text = match std::str::from_utf8(block) {
+        Ok(text) => text.to_string(),
+        // Older servers send Latin-1
+        Err(_) => block.iter().map(|&b| char::from(b)).collect(),
+    };
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &text[start..];
+    let end = rest.find("';").unwrap_or(rest.trim_end_matches('\0').len());
+    let title = rest[..end].trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    /// Answers each connection with the next response, then closes it.
+    pub(crate) fn serve(responses: Vec<Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for response in responses {
+                let Ok((mut socket, _)) = listener.accept() else {
+                    return;
+                };
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request);
+                let _ = socket.write_all(&response);
+            }
+        });
+        format!("http://{address}/stream")
+    }
+
+    /// Like `serve`, but the first connection stays open with nothing more to say.
+    fn serve_stalling(responses: Vec<Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut stalled = None;
+            for response in responses {
+                let Ok((mut socket, _)) = listener.accept() else {
+                    return;
+                };
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request);
+                let _ = socket.write_all(&response);
+                if stalled.is_none() {
+                    stalled = Some(socket);
+                }
+            }
+            // Holds the stalled connection until the client gives up on it
+            let _ = listener.accept();
+        });
+        format!("http://{address}/stream")
+    }
+
+    /// An ICY response carrying `audio` with `title` sent every `metaint` bytes.
+    pub(crate) fn icy_response(audio: &[u8], metaint: usize, title: &str) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.0 200 OK\r\nContent-Type: audio/wav\r\nicy-name: Test FM\r\n\
+             icy-metaint: {metaint}\r\n\r\n"
+        )
+        .into_bytes();
+        let mut metadata = format!("StreamTitle='{title}';").into_bytes();
+        metadata.resize(metadata.len().div_ceil(16) * 16, 0);
+        for chunk in audio.chunks(metaint) {
+            response.extend_from_slice(chunk);
+            if chunk.len() == metaint {
+                response.push((metadata.len() / 16) as u8);
+                response.extend_from_slice(&metadata);
+            }
+        }
+        response
+    }
+
+    fn not_found() -> Vec<u8> {
+        b"HTTP/1.0 404 Not Found\r\n\r\n".to_vec()
+    }
+
+    #[test]
+    fn test_strips_icy_metadata() {
+        let audio: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let url = serve(vec![
+            icy_response(&audio, 8000, "Artist - Song"),
+            not_found(),
+        ]);
+
+        let mut stream = HttpStream::open(&url).unwrap();
+        assert_eq!(stream.extension(), Some("wav"));
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert_eq!(received, audio);
+
+        let info = stream.info();
+        assert_eq!(info.name.as_deref(), Some("Test FM"));
+        assert_eq!(info.title().as_deref(), Some("Artist - Song"));
+
+        let mut metadata = TrackMetadata::default();
+        info.apply(&mut metadata);
+        assert_eq!(metadata.artist.as_deref(), Some("Artist"));
+        assert_eq!(metadata.title.as_deref(), Some("Song"));
+        assert_eq!(metadata.album.as_deref(), Some("Test FM"));
+    }
+
+    #[test]
+    fn test_reconnects_after_a_drop() {
+        let audio: Vec<u8> = (0..60_000u32).map(|i| (i % 241) as u8).collect();
+        let (first, second) = audio.split_at(25_000);
+        let url = serve(vec![
+            icy_response(first, 5000, "One"),
+            icy_response(second, 5000, "Two"),
+            not_found(),
+        ]);
+
+        let mut stream = HttpStream::open(&url).unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert_eq!(received, audio);
+        assert_eq!(stream.info().title().as_deref(), Some("Two"));
+    }
+
+    #[test]
+    fn test_reconnects_after_a_stall() {
+        let audio: Vec<u8> = (0..60_000u32).map(|i| (i % 239) as u8).collect();
+        let (first, second) = audio.split_at(30_000);
+        let responses = vec![
+            icy_response(first, 5000, "One"),
+            icy_response(second, 5000, "Two"),
+            not_found(),
+        ];
+
+        let url = serve_stalling(responses);
+        let mut stream = HttpStream::open_with_timeout(&url, Duration::from_millis(300)).unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert_eq!(received, audio);
+        assert_eq!(stream.info().title().as_deref(), Some("Two"));
+    }
+
+    #[test]
+    fn test_gate_closes_when_the_buffer_runs_dry() {
+        let audio = vec![1u8; 40_000];
+        let url = serve_stalling(vec![icy_response(&audio, 8000, "Song")]);
+        let mut stream = HttpStream::open(&url).unwrap();
+        let gate = stream.gate();
+
+        let mut waited = 0;
+        while !gate.is_ready() {
+            assert!(waited < 500, "the stream never buffered");
+            thread::sleep(Duration::from_millis(10));
+            waited += 1;
+        }
+        let mut played = vec![0u8; 30_000];
+        stream.read_exact(&mut played).unwrap();
+        assert!(!gate.is_ready());
+    }
+
+    #[test]
+    fn test_error_status_fails_to_open() {
+        let url = serve(vec![not_found()]);
+        assert!(HttpStream::open(&url).is_err());
+    }
+
+    #[test]
+    fn test_parse_stream_title() {
+        assert_eq!(
+            parse_stream_title(b"StreamTitle='A - B';StreamUrl='';\0\0"),
+            Some(String::from("A - B"))
+        );
+        assert_eq!(parse_stream_title(b"StreamTitle='';\0"), None);
+        assert_eq!(
+            parse_stream_title(b"StreamTitle='Caf\xe9';"),
+            Some(String::from("Café"))
+        );
+        assert!(is_url(Path::new("https://radio.example/live")));
+        assert!(!is_url(Path::new("/music/http.mp3")));
+    }
+}
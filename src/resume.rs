@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Closer than this to either end, there is nothing worth coming back to
+const MARGIN: Duration = Duration::from_secs(10);
+
+/// Which tracks get their playback position remembered: the long ones, and anything
+/// under the configured directories.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResumePolicy {
+    /// Shortest duration remembered; `None` to only go by directory.
+    pub min_duration: Option<Duration>,
+    pub dirs: Vec<PathBuf>,
+}
+
+impl ResumePolicy {
+    pub fn applies(&self, path: &Path, duration: Option<Duration>) -> bool {
+        self.dirs.iter().any(|dir| path.starts_with(dir))
+            || self
+                .min_duration
+                .zip(duration)
+                .is_some_and(|(min, duration)| duration >= min)
+    }
+}
+
+/// Where playback of a track was left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumePoint {
+    pub position_ms: u64,
+    // Zero when the length of the track is unknown
+    pub duration_ms: u64,
+}
+
+impl ResumePoint {
+    pub fn position(self) -> Duration {
+        Duration::from_millis(self.position_ms)
+    }
+
+    /// How far into the track playback got, from 0 to 100.
+    pub fn percent(self) -> Option<u64> {
+        (self.duration_ms > 0).then(|| (self.position_ms * 100 / self.duration_ms).min(100))
+    }
+}
+
+/// Resume points by track, stored next to the queue.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResumePositions {
+    positions: BTreeMap<PathBuf, ResumePoint>,
+}
+
+impl ResumePositions {
+    pub fn get(&self, path: &Path) -> Option<ResumePoint> {
+        self.positions.get(path).copied()
+    }
+
+    /// Records that playback of `path` stopped at `position`. Stopping near the start or
+    /// the end forgets the track instead, so it plays from the top next time.
+    pub fn update(&mut self, path: &Path, position: Duration, duration: Option<Duration>) {
+        let near_end = duration.is_some_and(|duration| position + MARGIN >= duration);
+        if position < MARGIN || near_end {
+            self.positions.remove(path);
+            return;
+        }
+        self.positions.insert(
+            path.to_path_buf(),
+            ResumePoint {
+                position_ms: position.as_millis() as u64,
+                duration_ms: duration.map_or(0, |duration| duration.as_millis() as u64),
+            },
+        );
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.positions.remove(path);
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create data directory {}", parent.display()))?;
+        }
+
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write resume positions at {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if path.exists() {
+            let content = fs::read_to_string(&path)?;
+            Ok(serde_json::from_str(&content)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    fn path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("impulse")
+            .join("resume.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_by_duration_and_directory() {
+        let policy = ResumePolicy {
+            min_duration: Some(Duration::from_secs(1200)),
+            dirs: vec![PathBuf::from("/music/Audiobooks")],
+        };
+        let hour = Some(Duration::from_secs(3600));
+        let song = Some(Duration::from_secs(200));
+        assert!(policy.applies(Path::new("/music/Mixes/set.mp3"), hour));
+        assert!(!policy.applies(Path::new("/music/Album/song.mp3"), song));
+        assert!(!policy.applies(Path::new("/music/Album/song.mp3"), None));
+        assert!(policy.applies(Path::new("/music/Audiobooks/ch1.mp3"), song));
+        assert!(!policy.applies(Path::new("/music/AudiobooksOld/ch1.mp3"), song));
+
+        let dirs_only = ResumePolicy {
+            min_duration: None,
+            ..policy
+        };
+        assert!(!dirs_only.applies(Path::new("/music/Mixes/set.mp3"), hour));
+    }
+
+    #[test]
+    fn test_positions_forget_both_ends() {
+        let path = Path::new("/music/Mixes/set.mp3");
+        let duration = Some(Duration::from_secs(3600));
+        let mut positions = ResumePositions::default();
+
+        positions.update(path, Duration::from_secs(900), duration);
+        let point = positions.get(path).unwrap();
+        assert_eq!(point.position(), Duration::from_secs(900));
+        assert_eq!(point.percent(), Some(25));
+
+        positions.update(path, Duration::from_secs(3595), duration);
+        assert_eq!(positions.get(path), None);
+
+        positions.update(path, Duration::from_secs(900), None);
+        assert_eq!(positions.get(path).unwrap().percent(), None);
+        positions.update(path, Duration::from_secs(3), duration);
+        assert_eq!(positions.get(path), None);
+    }
+
+    #[test]
+    fn test_positions_round_trip() {
+        let mut positions = ResumePositions::default();
+        positions.update(
+            Path::new("/books/chapter.m4b"),
+            Duration::from_millis(61_500),
+            Some(Duration::from_secs(600)),
+        );
+
+        let json = serde_json::to_string(&positions).unwrap();
+        let restored: ResumePositions = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            restored.get(Path::new("/books/chapter.m4b")),
+            positions.get(Path::new("/books/chapter.m4b"))
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+/// Longest fade-out accepted from the config.
+pub const MAX_FADE_SECS: f32 = 600.0;
+
+/// When the sleep timer goes off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepTarget {
+    At(Instant),
+    EndOfTrack,
+    EndOfQueue,
+}
+
+impl SleepTarget {
+    /// Parses the argument of `:sleep`: a duration such as `30m`, `1h15m` or `90s`
+    /// (plain numbers are minutes), `end-of-track` or `end-of-queue`.
+    pub fn parse(value: &str, now: Instant) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "end-of-track" | "eot" | "track" => Some(Self::EndOfTrack),
+            "end-of-queue" | "eoq" | "queue" => Some(Self::EndOfQueue),
+            value => now.checked_add(parse_duration(value)?).map(Self::At),
+        }
+    }
+}
+
+// Sums `<number><unit>` groups, units being h, m or s. A lone number counts as minutes.
+// None as well when the total overflows.
+fn parse_duration(value: &str) -> Option<Duration> {
+    if let Ok(minutes) = value.parse::<u64>() {
+        return (minutes > 0)
+            .then(|| minutes.checked_mul(60).map(Duration::from_secs))
+            .flatten();
+    }
+
+    let mut total = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = number
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(unit)
+            .and_then(|secs| secs.checked_add(total))?;
+        number.clear();
+    }
+    (number.is_empty() && total > 0).then(|| Duration::from_secs(total))
+}
+
+/// Share of the volume to keep `remaining` before the timer goes off, ramping down
+/// to silence over the last `fade`.
+pub fn fade_factor(remaining: Duration, fade: Duration) -> f32 {
+    if remaining >= fade {
+        1.0
+    } else {
+        remaining.as_secs_f32() / fade.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_targets() {
+        let now = Instant::now();
+        let at = |secs| Some(SleepTarget::At(now + Duration::from_secs(secs)));
+        assert_eq!(SleepTarget::parse("30m", now), at(1800));
+        assert_eq!(SleepTarget::parse("45", now), at(2700));
+        assert_eq!(SleepTarget::parse("1h15m", now), at(4500));
+        assert_eq!(SleepTarget::parse("90s", now), at(90));
+        assert_eq!(
+            SleepTarget::parse("end-of-track", now),
+            Some(SleepTarget::EndOfTrack)
+        );
+        assert_eq!(
+            SleepTarget::parse("End-Of-Queue", now),
+            Some(SleepTarget::EndOfQueue)
+        );
+        assert_eq!(SleepTarget::parse("0", now), None);
+        assert_eq!(SleepTarget::parse("10x", now), None);
+        assert_eq!(SleepTarget::parse("1h30", now), None);
+        assert_eq!(SleepTarget::parse("m", now), None);
+        // Too far ahead to represent
+        assert_eq!(SleepTarget::parse("300000000000000000", now), None);
+        assert_eq!(SleepTarget::parse("18446744073709551615h", now), None);
+        assert_eq!(
+            SleepTarget::parse("10000000000000000000s10000000000000000000s", now),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fade_factor_ramps_down() {
+        let fade = Duration::from_secs(30);
+        assert_eq!(fade_factor(Duration::from_secs(60), fade), 1.0);
+        assert_eq!(fade_factor(Duration::from_secs(30), fade), 1.0);
+        assert_eq!(fade_factor(Duration::from_secs(15), fade), 0.5);
+        assert_eq!(fade_factor(Duration::ZERO, fade), 0.0);
+        // Without a fade the volume drops at once
+        assert_eq!(fade_factor(Duration::from_secs(1), Duration::ZERO), 1.0);
+    }
+}
@@ -1,9 +1,23 @@
 use crate::browser::Browser;
+use crate::channels::ChannelSettings;
+use crate::chapters;
 use crate::config::Config;
+use crate::cue;
+use crate::dynamics::DynamicsPreset;
+use crate::engine::{self, Command, Engine, PlaybackState};
+use crate::equalizer::{self, BAND_COUNT, EqState, MAX_GAIN_DB};
 use crate::lastfm::LastfmScrobbler;
-use crate::player::Player;
+use crate::logger;
+use crate::metadata::{StreamChoices, TrackMetadata};
+use crate::output::OutputBackend;
+use crate::player::{DecodeError, MAX_CROSSFADE_SECS, MAX_PREAMP_DB, ReplayGainMode};
 use crate::playlist::PlaylistManager;
-use crate::queue::Queue;
+use crate::queue::{self, Queue};
+use crate::radio;
+use crate::resume::{ResumePolicy, ResumePositions};
+use crate::sleep::{self, SleepTarget};
+use crate::visualizer::{Analyzer, FLOOR_DB, Level};
+use crate::waveform::WaveformLoader;
 use anyhow::Result;
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind};
 use fuzzy_matcher::FuzzyMatcher;
@@ -11,15 +25,28 @@ use fuzzy_matcher::skim::SkimMatcherV2;
 use ratatui::{
     Frame, Terminal,
     backend::Backend,
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Constraint, Direction, Layout, Position, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+    widgets::{
+        Bar, BarChart, BarGroup, Block, Borders, Clear, List, ListItem, ListState, Paragraph,
+    },
 };
 use ratatui_image::{picker::Picker, protocol::StatefulProtocol};
+use std::collections::{BTreeSet, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// Two clicks on the same list item within this long count as a double-click
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+// Longest wait between two redraws when nothing happens
+const IDLE_REDRAW: Duration = Duration::from_secs(1);
+
+// Resume points are written at most this often while playing, besides at exit
+const RESUME_SAVE_DELAY: Duration = Duration::from_secs(5);
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Tab {
@@ -56,13 +83,13 @@ impl Tab {
     fn help_text(&self) -> &str {
         match self {
             Tab::Browser => {
-                "Keys: j/k/↑/↓=nav, l/→/Enter=select, h/←=back, a=add, A=add-all, o=jump-to-playing, Space/p=play/pause, >=next, <=prev, r=random, R=repeat, Tab/1-3=switch-tab, /=search, Ctrl+F=recursive-search, q=quit"
+                "Keys: j/k/↑/↓=nav, l/→/Enter=select, h/←=back, a=add, N=play-next, A=add-all, o=jump-to-playing, Space/p=play/pause, >=next, <=prev, r=random, R=repeat, Tab/1-3=switch-tab, /=search, Ctrl+F=recursive-search, q=quit"
             }
             Tab::NowPlaying => {
-                "Keys: j/k/↑/↓=nav, Enter=jump, o=jump-to-playing, ←/→=seek, d=delete, K/J=move, c=clear, S=save-queue, Space/p=play/pause, >=next, <=prev, r=random, R=repeat, Tab/1-3=switch-tab, /=search, q=quit"
+                "Keys: j/k/↑/↓=nav, Enter=jump, o=jump-to-playing, ←/→=seek, e=equalizer, a=audio-track, v=visualizer, {/}=chapter, [/]=loop-A/B, \\=clear-loop, d=delete, K/J=move, c=clear, V=visual, m=mark, x=shuffle-selection, u/Ctrl+R=undo/redo, S=save-queue, Space/p=play/pause, >=next, <=prev, r=random, R=repeat, Tab/1-3=switch-tab, /=search, q=quit"
             }
             Tab::Playlists => {
-                "Keys: j/k/↑/↓=nav, l/Enter=add-to-queue, Space/p=play/pause, >=next, <=prev, r=random, R=repeat, Tab/1-3=switch-tab, /=search, q=quit"
+                "Keys: j/k/↑/↓=nav, l/Enter=add-to-queue, N=play-next, Space/p=play/pause, >=next, <=prev, r=random, R=repeat, Tab/1-3=switch-tab, /=search, q=quit"
             }
         }
     }
@@ -75,6 +102,31 @@ pub enum InputMode {
     Command,
 }
 
+// Lists that can be clicked and scrolled with the mouse
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListArea {
+    Browser,
+    Queue,
+    Playlists,
+    Search,
+}
+
+// Cover shown next to the track details, decoded on a background thread
+enum AlbumArt {
+    Missing,
+    Loading,
+    Ready(Box<dyn StatefulProtocol>),
+    Invalid,
+}
+
+// What wakes up the main loop
+enum AppEvent {
+    Input(Event),
+    Engine(engine::Event),
+    // Something drawn changed in the background
+    Redraw,
+}
+
 struct SearchResult {
     path: PathBuf,
     name: String,
@@ -82,7 +134,11 @@ struct SearchResult {
 }
 
 pub struct App {
-    player: Player,
+    engine: Engine,
+    // Playback as the engine last reported it
+    playback: PlaybackState,
+    events: mpsc::Receiver<AppEvent>,
+    event_sender: mpsc::Sender<AppEvent>,
     browser: Browser,
     queue: Queue,
     playlist_manager: PlaylistManager,
@@ -100,25 +156,99 @@ pub struct App {
     status_message: String,
     status_message_time: Option<SystemTime>,
     image_picker: Arc<Mutex<Picker>>,
-    album_art: Arc<Mutex<Option<Box<dyn StatefulProtocol>>>>,
-    last_album_art_track: Arc<Mutex<Option<PathBuf>>>,
+    album_art: Arc<Mutex<AlbumArt>>,
+    // Track the album art is for, and whether its tags had been read by then
+    last_album_art_track: Arc<Mutex<Option<(PathBuf, bool)>>>,
     lastfm_scrobbler: LastfmScrobbler,
-    track_play_time: Arc<Mutex<Option<SystemTime>>>,
+    scrobble_pending: bool,
+    // Track to tell Last.fm about once its tags have been read
+    now_playing_pending: Option<PathBuf>,
     browser_state: ListState,
     queue_state: ListState,
     playlist_state: ListState,
     search_state: ListState,
     progress_bar_area: Option<Rect>,
+    // Where the tabs and lists were drawn last, for mouse handling
+    tab_areas: Vec<(Tab, Rect)>,
+    list_areas: Vec<(ListArea, Rect)>,
+    last_click: Option<(Instant, ListArea, usize)>,
+    gapless_candidate: Option<PathBuf>,
+    equalizer: EqState,
+    // Band selected in the equalizer panel, while the panel is open
+    eq_panel: Option<usize>,
+    // Spectrum and meters shown in place of the album art
+    visualizer: bool,
+    analyzer: Analyzer,
+    // Overview of the current track drawn as the progress bar
+    waveform: WaveformLoader,
+    // Tracks cut short by decode errors, flagged in the queue
+    problem_tracks: HashSet<PathBuf>,
+    // Pending `:sleep`, cleared once playback was faded out and paused
+    sleep_timer: Option<SleepTarget>,
+    // Volume the sleep timer last faded to, so it is only sent when it changes
+    sleep_volume: Option<f32>,
+    // When the engine first reported resume points that are not on disk yet
+    resume_changed: Option<Instant>,
+    // Set while moving on from a track that ended, counting the tracks that failed to
+    // play since, so that broken ones are skipped without going round the queue forever
+    auto_advance: Option<usize>,
+    // Queue rows toggled with `m`, and where visual mode started
+    queue_marks: BTreeSet<usize>,
+    visual_anchor: Option<usize>,
 }
 
 impl App {
     pub fn new(config: Config) -> Result<Self> {
-        let player = Player::new()?;
-        player.set_volume(config.volume);
+        let mut status_message = String::new();
+        let (event_sender, events) = mpsc::channel();
+        let spawn_engine = |backend: OutputBackend| {
+            let sender = event_sender.clone();
+            Engine::spawn(backend, move |event| {
+                let _ = sender.send(AppEvent::Engine(event));
+            })
+        };
+        let engine = match spawn_engine(config.output.clone()) {
+            Ok(engine) => engine,
+            Err(e) if config.output == OutputBackend::Device => {
+                // Keep the player usable without a sound card, just silent
+                logger::log(&format!("{e:#}, falling back to the null output"));
+                status_message = String::from("No audio device available, playing silently");
+                spawn_engine(OutputBackend::Null)?
+            }
+            Err(e) => return Err(e),
+        };
+        engine.send(Command::SetVolume(config.volume));
+        engine.send(Command::SetCrossfade(
+            config.crossfade_secs,
+            config.crossfade_curve,
+        ));
+        engine.send(Command::SetDynamics(config.dynamics));
+        engine.send(Command::SetDecodeErrorLimit(config.decode_error_limit));
+        engine.send(Command::SetChannels(ChannelSettings {
+            balance: config.balance,
+            mono: config.mono,
+        }));
 
         let browser = Browser::new(config.music_dir.clone());
         let queue = Queue::load().unwrap_or_else(|_| Queue::new());
+        engine.send(Command::SetReplayGain(
+            config.replaygain.resolve(queue.is_random()),
+            config.replaygain_preamp,
+        ));
         let playlist_manager = PlaylistManager::new(config.playlist_dir.clone());
+        let equalizer = EqState::load().unwrap_or_default();
+        engine.send(Command::SetEqualizer(equalizer.active_bands()));
+        engine.send(Command::SetStreamChoices(
+            StreamChoices::load().unwrap_or_default(),
+        ));
+        engine.send(Command::SetResumePolicy(ResumePolicy {
+            min_duration: (config.resume_min_secs > 0)
+                .then(|| Duration::from_secs(config.resume_min_secs)),
+            dirs: config.resume_dirs.clone(),
+        }));
+        engine.send(Command::SetResumePositions(
+            ResumePositions::load().unwrap_or_default(),
+        ));
 
         // Initialize image picker for album art display
         let mut picker = Picker::new((8, 12));
@@ -130,13 +260,16 @@ impl App {
         // If queue was loaded from JSON and has tracks, load the current track but start paused
         if !queue.is_empty() {
             if let Some(track) = queue.current() {
-                let _ = player.play(track);
-                player.pause();
+                engine.send(Command::Play(track.clone()));
+                engine.send(Command::Pause);
             }
         }
 
         Ok(Self {
-            player,
+            engine,
+            playback: PlaybackState::default(),
+            events,
+            event_sender,
             browser,
             queue,
             playlist_manager,
@@ -151,18 +284,35 @@ impl App {
             playlist_selected: 0,
             playlist_track_selected: 0,
             should_quit: false,
-            status_message: String::new(),
-            status_message_time: None,
+            status_message_time: (!status_message.is_empty()).then(SystemTime::now),
+            status_message,
             image_picker: Arc::new(Mutex::new(picker)),
-            album_art: Arc::new(Mutex::new(None)),
+            album_art: Arc::new(Mutex::new(AlbumArt::Missing)),
             last_album_art_track: Arc::new(Mutex::new(None)),
             lastfm_scrobbler,
-            track_play_time: Arc::new(Mutex::new(None)),
+            scrobble_pending: false,
+            now_playing_pending: None,
             browser_state: ListState::default(),
             queue_state: ListState::default(),
             playlist_state: ListState::default(),
             search_state: ListState::default(),
             progress_bar_area: None,
+            tab_areas: Vec::new(),
+            list_areas: Vec::new(),
+            last_click: None,
+            gapless_candidate: None,
+            equalizer,
+            eq_panel: None,
+            visualizer: false,
+            analyzer: Analyzer::default(),
+            waveform: WaveformLoader::default(),
+            problem_tracks: HashSet::new(),
+            sleep_timer: None,
+            sleep_volume: None,
+            resume_changed: None,
+            auto_advance: None,
+            queue_marks: BTreeSet::new(),
+            visual_anchor: None,
         })
     }
 
@@ -173,29 +323,33 @@ impl App {
         // Switch to Now Playing tab but start paused
         if !self.queue.is_empty() {
             self.current_tab = Tab::NowPlaying;
-            if let Some(track) = self.queue.current() {
-                let _ = self.player.play(track);
-                self.player.pause();
-                self.track_play_time = Arc::new(Mutex::new(Some(SystemTime::now())));
-                // Update Last.fm now playing if enabled
-                if self.lastfm_scrobbler.is_enabled() {
-                    if let Some(metadata) = self.player.current_metadata() {
-                        let _ = self.lastfm_scrobbler.now_playing(track, &metadata);
-                    }
-                }
+            if let Some(track) = self.queue.current().cloned() {
+                self.play_track(&track);
+                self.engine.send(Command::Pause);
             }
         }
     }
 
     pub fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
+        // Terminal input comes in through the same channel as the engine events, so
+        // either one wakes up the loop
+        let input = self.event_sender.clone();
+        thread::spawn(move || {
+            while let Ok(event) = event::read() {
+                if input.send(AppEvent::Input(event)).is_err() {
+                    break;
+                }
+            }
+        });
+
         loop {
             terminal.draw(|f| self.draw(f))?;
 
-            if event::poll(Duration::from_millis(100))? {
-                match event::read()? {
-                    Event::Key(key) => self.handle_key(key)?,
-                    Event::Mouse(mouse) => self.handle_mouse(mouse)?,
-                    _ => {}
+            if let Ok(event) = self.events.recv_timeout(self.redraw_timeout()) {
+                self.handle_event(event)?;
+                // Catch up on whatever else came in before drawing again
+                while let Ok(event) = self.events.try_recv() {
+                    self.handle_event(event)?;
                 }
             }
 
@@ -209,34 +363,116 @@ impl App {
                 }
             }
 
-            // Check if current track finished
-            if self.player.is_finished() && !self.queue.is_empty() {
-                // Scrobble the finished track if enough time has passed
-                self.scrobble_if_needed();
-                self.play_next();
-            } else if self.player.is_finished() && self.player.current_track().is_some() {
-                // Track finished but queue is empty
-                use crate::queue::RepeatMode;
-                if self.queue.repeat_mode() != RepeatMode::Off {
-                    // If repeat is enabled, keep playing
-                    self.scrobble_if_needed();
-                    self.play_next();
-                } else {
-                    // Otherwise scrobble and stop player
-                    self.scrobble_if_needed();
-                    self.player.stop();
-                }
-            }
+            self.prepare_gapless_next();
+            self.waveform
+                .request(self.playback.current_track().as_deref());
+
+            self.update_sleep_timer();
+            self.update_now_playing();
+            self.save_resume_positions();
 
             if self.should_quit {
-                // Save queue before quitting
+                // Save queue, equalizer, audio stream choices and resume points before quitting
                 let _ = self.queue.save();
+                let _ = self.equalizer.save();
+                if let Some((stream_choices, resume_positions)) = self.engine.shutdown() {
+                    let _ = stream_choices.save();
+                    let _ = resume_positions.save();
+                }
                 break;
             }
         }
         Ok(())
     }
 
+    // How long the loop may wait for an event before drawing again. The engine reports
+    // playback as it goes, so only what runs on the clock needs a deadline.
+    fn redraw_timeout(&self) -> Duration {
+        if self.visualizer && self.current_tab == Tab::NowPlaying {
+            // Redraw faster while the visualizer is on screen
+            Duration::from_millis(33)
+        } else if self.sleep_timer.is_some() || self.status_message_time.is_some() {
+            Duration::from_millis(100)
+        } else {
+            IDLE_REDRAW
+        }
+    }
+
+    fn handle_event(&mut self, event: AppEvent) -> Result<()> {
+        match event {
+            AppEvent::Input(Event::Key(key)) => self.handle_key(key)?,
+            AppEvent::Input(Event::Mouse(mouse)) => self.handle_mouse(mouse)?,
+            AppEvent::Engine(event) => self.handle_engine_event(event),
+            AppEvent::Input(_) | AppEvent::Redraw => {}
+        }
+        Ok(())
+    }
+
+    fn handle_engine_event(&mut self, event: engine::Event) {
+        // The engine moved on to the preloaded track by itself: the scrobble is still
+        // about the one before
+        if matches!(event, engine::Event::TrackStarted { gapless: true, .. }) {
+            self.scrobble_if_needed();
+        }
+        self.playback.apply(&event);
+
+        match event {
+            engine::Event::TrackStarted { path, gapless, .. } => {
+                self.auto_advance = None;
+                if gapless {
+                    self.follow_gapless_transition(&path);
+                }
+                self.start_track(&path);
+            }
+            engine::Event::TrackEnded => {
+                self.auto_advance = Some(0);
+                self.track_finished();
+            }
+            engine::Event::Stopped => self.auto_advance = None,
+            engine::Event::DecodeError(error) => self.report_decode_error(error),
+            engine::Event::PlayFailed { path, error } => {
+                logger::log(&format!("Failed to play {}: {error}", path.display()));
+                self.set_status(format!("Error playing: {error}"));
+                if let Some(failures) = self.auto_advance.as_mut() {
+                    *failures += 1;
+                    if *failures < self.queue.len() {
+                        self.track_finished();
+                    } else {
+                        self.auto_advance = None;
+                    }
+                }
+            }
+            engine::Event::Error(message) => self.set_status(message),
+            engine::Event::ResumePositions(_) => {
+                self.resume_changed.get_or_insert_with(Instant::now);
+            }
+            _ => {}
+        }
+    }
+
+    // The current track played to its end
+    fn track_finished(&mut self) {
+        if self.sleeps_after_current_track() {
+            self.sleep_at_track_end();
+        } else if !self.queue.is_empty() {
+            // Scrobble the finished track if enough time has passed
+            self.scrobble_if_needed();
+            self.play_next();
+        } else {
+            // Track finished but queue is empty
+            use crate::queue::RepeatMode;
+            if self.queue.repeat_mode() != RepeatMode::Off {
+                // If repeat is enabled, keep playing
+                self.scrobble_if_needed();
+                self.play_next();
+            } else {
+                // Otherwise scrobble and stop player
+                self.scrobble_if_needed();
+                self.engine.send(Command::Stop);
+            }
+        }
+    }
+
     fn handle_key(&mut self, key: KeyEvent) -> Result<()> {
         match self.input_mode {
             InputMode::Normal => self.handle_normal_mode(key)?,
@@ -248,38 +484,217 @@ impl App {
     }
 
     fn handle_mouse(&mut self, mouse: MouseEvent) -> Result<()> {
-        // Only handle left clicks
-        if let MouseEventKind::Down(crossterm::event::MouseButton::Left) = mouse.kind {
-            // Check if click is within progress bar area
-            if let Some(area) = self.progress_bar_area {
-                if mouse.row == area.y
-                    && mouse.column >= area.x
-                    && mouse.column < area.x + area.width
+        let (column, row) = (mouse.column, mouse.row);
+        match mouse.kind {
+            MouseEventKind::Down(crossterm::event::MouseButton::Left) => {
+                if let Some(area) = self.progress_bar_area {
+                    if row == area.y && column < area.x + area.width {
+                        if column >= area.x {
+                            self.seek_to_fraction(
+                                f64::from(column - area.x) / f64::from(area.width),
+                            );
+                        } else {
+                            // The time label left of the bar
+                            self.toggle_play_pause()?;
+                        }
+                        return Ok(());
+                    }
+                }
+
+                if let Some(tab) = self
+                    .tab_areas
+                    .iter()
+                    .find(|(_, area)| area.contains(Position::new(column, row)))
+                    .map(|(tab, _)| *tab)
                 {
-                    // Toggle play/pause
-                    self.toggle_play_pause()?;
+                    self.current_tab = tab;
+                    return Ok(());
+                }
+
+                if let Some((list, index)) = self.list_item_at(column, row) {
+                    self.select_list_item(list, index);
+                    let double = self
+                        .last_click
+                        .is_some_and(|(time, last_list, last_index)| {
+                            last_list == list
+                                && last_index == index
+                                && time.elapsed() < DOUBLE_CLICK
+                        });
+                    if double {
+                        self.last_click = None;
+                        self.activate_list_item(list)?;
+                    } else {
+                        self.last_click = Some((Instant::now(), list, index));
+                    }
+                }
+            }
+            MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+                let down = mouse.kind == MouseEventKind::ScrollDown;
+                if let Some(list) = self.list_at(column, row) {
+                    let selected = self.list_selection(list);
+                    let index = if down {
+                        selected + 1
+                    } else {
+                        selected.saturating_sub(1)
+                    };
+                    self.select_list_item(list, index);
                 }
             }
+            _ => {}
         }
         Ok(())
     }
 
+    fn seek_to_fraction(&mut self, fraction: f64) {
+        let Some(duration_secs) = self
+            .playback
+            .current_metadata()
+            .and_then(|m| m.duration_secs)
+            .filter(|secs| *secs > 0)
+        else {
+            return;
+        };
+        let target = Duration::from_secs_f64(duration_secs as f64 * fraction.clamp(0.0, 1.0));
+        self.engine.send(Command::SeekTo(target));
+        self.set_status(format!("Seeked to {}", format_time(target)));
+    }
+
+    // Topmost list drawn under the given cell. While searching only the results react.
+    fn list_at(&self, column: u16, row: u16) -> Option<ListArea> {
+        let (list, _) = self
+            .list_areas
+            .iter()
+            .rev()
+            .find(|(_, area)| area.contains(Position::new(column, row)))?;
+        let allowed = match self.input_mode {
+            InputMode::Normal => *list != ListArea::Search,
+            InputMode::Search | InputMode::RecursiveSearch => *list == ListArea::Search,
+            InputMode::Command => false,
+        };
+        allowed.then_some(*list)
+    }
+
+    // List and item index under the given cell, skipping the borders
+    fn list_item_at(&self, column: u16, row: u16) -> Option<(ListArea, usize)> {
+        let list = self.list_at(column, row)?;
+        let (_, area) = self.list_areas.iter().rev().find(|(l, _)| *l == list)?;
+        if row <= area.y || row + 1 >= area.y + area.height {
+            return None;
+        }
+
+        let offset = match list {
+            ListArea::Browser => self.browser_state.offset(),
+            ListArea::Queue => self.queue_state.offset(),
+            ListArea::Playlists => self.playlist_state.offset(),
+            ListArea::Search => self.search_state.offset(),
+        };
+        let index = offset + usize::from(row - area.y - 1);
+        (index < self.list_len(list)).then_some((list, index))
+    }
+
+    fn list_len(&self, list: ListArea) -> usize {
+        match list {
+            ListArea::Browser => self.browser.entries().len(),
+            ListArea::Queue => self.queue.len(),
+            ListArea::Playlists => self.playlist_manager.playlists().len(),
+            ListArea::Search => self.search_results.len(),
+        }
+    }
+
+    fn list_selection(&self, list: ListArea) -> usize {
+        match list {
+            ListArea::Browser => self.browser.selected(),
+            ListArea::Queue => self.queue_selected,
+            ListArea::Playlists => self.playlist_selected,
+            ListArea::Search => self.search_result_selected,
+        }
+    }
+
+    fn select_list_item(&mut self, list: ListArea, index: usize) {
+        let len = self.list_len(list);
+        if len == 0 {
+            return;
+        }
+        let index = index.min(len - 1);
+        match list {
+            ListArea::Browser => self.browser.select(index),
+            ListArea::Queue => self.queue_selected = index,
+            ListArea::Playlists => self.playlist_selected = index,
+            ListArea::Search => self.search_result_selected = index,
+        }
+    }
+
+    // Double-click on the selected item: plays a track or playlist, queueing it first
+    // unless it is queued already. Directories open as with Enter.
+    fn activate_list_item(&mut self, list: ListArea) -> Result<()> {
+        match list {
+            ListArea::Browser => match self.browser.selected_entry() {
+                Some(entry) if !entry.is_dir() => {
+                    let name = entry.name();
+                    self.play_tracks(vec![entry.path().to_path_buf()], &name);
+                }
+                _ => self.handle_key(KeyEvent::from(KeyCode::Enter))?,
+            },
+            ListArea::Queue => self.handle_key(KeyEvent::from(KeyCode::Enter))?,
+            ListArea::Playlists => {
+                if let Some(playlist) = self.playlist_manager.get_playlist(self.playlist_selected) {
+                    let tracks = playlist.tracks.clone();
+                    let name = format!("playlist '{}'", playlist.name);
+                    self.play_tracks(tracks, &name);
+                }
+            }
+            ListArea::Search => match self.search_results.get(self.search_result_selected) {
+                Some(result) if !result.path.is_dir() => {
+                    let (path, name) = (result.path.clone(), result.name.clone());
+                    self.input_mode = InputMode::Normal;
+                    self.clear_search_results();
+                    self.play_tracks(vec![path], &name);
+                }
+                _ => self.handle_key(KeyEvent::from(KeyCode::Enter))?,
+            },
+        }
+        Ok(())
+    }
+
+    // Plays `tracks` from the first one on, where they already follow each other in the
+    // queue, or else after adding them to its end
+    fn play_tracks(&mut self, tracks: Vec<PathBuf>, name: &str) {
+        if tracks.is_empty() {
+            self.set_status(format!("Nothing to play in {name}"));
+            return;
+        }
+        let queued = self
+            .queue
+            .tracks()
+            .windows(tracks.len())
+            .position(|window| window == tracks.as_slice());
+        let index = queued.unwrap_or_else(|| {
+            self.queue.checkpoint("add to queue", self.queue_selected);
+            let index = self.queue.len();
+            self.queue.add_multiple(tracks);
+            index
+        });
+
+        self.scrobble_if_needed();
+        if let Some(track) = self.queue.jump_to(index).cloned() {
+            self.queue_selected = index;
+            self.play_track(&track);
+            self.set_status(format!("Playing: {name}"));
+        }
+    }
+
     fn toggle_play_pause(&mut self) -> Result<()> {
-        if self.player.is_playing() {
-            self.player.pause();
+        if self.playback.is_playing() {
+            self.engine.send(Command::Pause);
             self.set_status(String::from("Paused"));
-        } else if self.player.is_paused() {
-            self.player.resume();
+        } else if self.playback.is_paused() {
+            self.engine.send(Command::Resume);
             self.set_status(String::from("Resumed"));
         } else if let Some(track) = self.queue.current() {
             let track_clone = track.clone();
             let display_path = track_clone.display().to_string();
-            if let Err(e) = self.player.play(&track_clone) {
-                self.set_status(format!("Error playing: {}", e));
-            } else {
-                self.set_status(format!("Playing: {}", display_path));
-                self.start_track(&track_clone);
-            }
+            self.play_track(&track_clone);
+            self.set_status(format!("Playing: {}", display_path));
         }
         Ok(())
     }
@@ -334,8 +749,10 @@ impl App {
             KeyCode::Char('<') => {
                 self.play_prev();
             }
-            KeyCode::Char('r') => {
+            KeyCode::Char('r') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
                 self.queue.toggle_random();
+                // Auto ReplayGain follows the playback order
+                self.apply_replaygain();
                 let status = if self.queue.is_random() {
                     "Random mode enabled"
                 } else {
@@ -357,7 +774,7 @@ impl App {
             }
             KeyCode::Char('s') => {
                 self.scrobble_if_needed();
-                self.player.stop();
+                self.engine.send(Command::Stop);
                 self.set_status(String::from("Stopped"));
             }
             _ => {
@@ -377,15 +794,56 @@ impl App {
     }
 
     fn handle_now_playing_keys(&mut self, key: KeyEvent) -> Result<()> {
+        // The equalizer panel takes over navigation while it is open
+        if self.handle_equalizer_keys(key) {
+            return Ok(());
+        }
+
         // Handle seek keys first (specific to now playing)
         match key.code {
+            KeyCode::Char('e') => {
+                self.eq_panel = Some(0);
+                return Ok(());
+            }
+            KeyCode::Char('a') => {
+                self.cycle_audio_stream();
+                return Ok(());
+            }
+            KeyCode::Char('v') => {
+                self.visualizer = !self.visualizer;
+                self.engine.tap().set_enabled(self.visualizer);
+                return Ok(());
+            }
+            KeyCode::Char('}') => {
+                self.seek_chapter(true);
+                return Ok(());
+            }
+            KeyCode::Char('{') => {
+                self.seek_chapter(false);
+                return Ok(());
+            }
+            KeyCode::Char('[') => {
+                self.set_loop_start();
+                return Ok(());
+            }
+            KeyCode::Char(']') => {
+                self.set_loop_end();
+                return Ok(());
+            }
+            KeyCode::Char('\\') => {
+                self.engine.send(Command::SetAbLoop(None, None));
+                self.set_status(String::from("Loop cleared"));
+                return Ok(());
+            }
             KeyCode::Left => {
-                self.player.seek_backward(5);
+                self.engine
+                    .send(Command::SeekBackward(Duration::from_secs(5)));
                 self.set_status(String::from("Seeked backward 5s"));
                 return Ok(());
             }
             KeyCode::Right => {
-                self.player.seek_forward(5);
+                self.engine
+                    .send(Command::SeekForward(Duration::from_secs(5)));
                 self.set_status(String::from("Seeked forward 5s"));
                 return Ok(());
             }
@@ -398,6 +856,159 @@ impl App {
         Ok(())
     }
 
+    fn handle_equalizer_keys(&mut self, key: KeyEvent) -> bool {
+        let Some(band) = self.eq_panel else {
+            return false;
+        };
+        match key.code {
+            KeyCode::Char('h') | KeyCode::Left => {
+                self.eq_panel = Some(band.saturating_sub(1));
+            }
+            KeyCode::Char('l') | KeyCode::Right => {
+                self.eq_panel = Some((band + 1).min(BAND_COUNT - 1));
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.adjust_eq_band(band, 1.0);
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.adjust_eq_band(band, -1.0);
+            }
+            KeyCode::Char('0') => {
+                self.adjust_eq_band(band, -self.equalizer.bands[band]);
+            }
+            KeyCode::Char('t') => {
+                self.equalizer.enabled = !self.equalizer.enabled;
+                self.apply_equalizer();
+                let status = if self.equalizer.enabled {
+                    "Equalizer enabled"
+                } else {
+                    "Equalizer bypassed"
+                };
+                self.set_status(String::from(status));
+            }
+            KeyCode::Char('e') | KeyCode::Esc => {
+                self.eq_panel = None;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Jumps to the start of the next chapter, or back to the current or previous one
+    fn seek_chapter(&mut self, forward: bool) {
+        let chapters = self
+            .playback
+            .current_metadata()
+            .map(|meta| meta.chapters)
+            .unwrap_or_default();
+        if chapters.is_empty() {
+            self.set_status(String::from("No chapters in this track"));
+            return;
+        }
+
+        let position = self.playback.position();
+        let target = if forward {
+            chapters::next_start(&chapters, position)
+        } else {
+            // Before the first chapter, the only way back is the start of the track
+            Some(chapters::previous_start(&chapters, position).unwrap_or_default())
+        };
+        let Some(start) = target else {
+            self.set_status(String::from("Already in the last chapter"));
+            return;
+        };
+
+        self.engine.send(Command::SeekTo(start));
+        match chapters::index_at(&chapters, start) {
+            Some(index) => self.set_status(format!(
+                "Chapter {}/{}: {}",
+                index + 1,
+                chapters.len(),
+                chapters[index].title
+            )),
+            None => self.set_status(String::from("Start of track")),
+        }
+    }
+
+    // Marks A at the current position, keeping B only if it still comes after it
+    fn set_loop_start(&mut self) {
+        if self.playback.current_track().is_none() {
+            return;
+        }
+        let start = self.playback.position();
+        let (_, end) = self.playback.ab_loop();
+        let end = end.filter(|end| *end > start);
+        self.engine.send(Command::SetAbLoop(Some(start), end));
+        self.set_status(format!("Loop A: {}", format_time(start)));
+    }
+
+    fn set_loop_end(&mut self) {
+        let (start, _) = self.playback.ab_loop();
+        let Some(start) = start else {
+            self.set_status(String::from("Set the loop start with [ first"));
+            return;
+        };
+        let end = self.playback.position();
+        if end <= start {
+            self.set_status(String::from("Loop end must come after the start"));
+            return;
+        }
+        self.engine.send(Command::SetAbLoop(Some(start), Some(end)));
+        self.set_status(format!(
+            "Looping {} - {}",
+            format_time(start),
+            format_time(end)
+        ));
+    }
+
+    // Switches the playing file to its next audio stream
+    fn cycle_audio_stream(&mut self) {
+        let Some(metadata) = self.playback.current_metadata() else {
+            return;
+        };
+        if metadata.streams.len() < 2 {
+            self.set_status(String::from("No other audio track in this file"));
+            return;
+        }
+
+        let next = self
+            .active_stream(&metadata)
+            .map_or(0, |index| (index + 1) % metadata.streams.len());
+        let stream = &metadata.streams[next];
+        self.engine.send(Command::SelectStream(stream.id));
+        // The preloaded next track goes away with the old stream
+        self.gapless_candidate = None;
+        self.set_status(format!(
+            "Audio track {}/{}: {}",
+            next + 1,
+            metadata.streams.len(),
+            stream.describe()
+        ));
+    }
+
+    // Index in `metadata.streams` of the stream being played
+    fn active_stream(&self, metadata: &TrackMetadata) -> Option<usize> {
+        let chosen = self
+            .playback
+            .current_track()
+            .and_then(|track| self.playback.chosen_stream(&track));
+        [chosen, metadata.default_stream]
+            .into_iter()
+            .flatten()
+            .find_map(|id| metadata.streams.iter().position(|s| s.id == id))
+    }
+
+    fn adjust_eq_band(&mut self, band: usize, delta_db: f32) {
+        self.equalizer.adjust(band, delta_db);
+        self.equalizer.enabled = true;
+        self.apply_equalizer();
+        self.set_status(format!(
+            "EQ {} Hz: {:+.0} dB",
+            equalizer::band_label(band),
+            self.equalizer.bands[band]
+        ));
+    }
+
     fn handle_browser_keys(&mut self, key: KeyEvent) -> Result<()> {
         match key.code {
             KeyCode::Char('j') | KeyCode::Down => {
@@ -425,16 +1036,13 @@ impl App {
             KeyCode::Char('l') | KeyCode::Enter | KeyCode::Right => {
                 if let Some(track) = self.browser.enter_selected() {
                     let was_empty = self.queue.is_empty();
+                    self.queue.checkpoint("add to queue", self.queue_selected);
                     self.queue.add(track.clone());
                     self.set_status(format!("Added to queue: {}", track.display()));
 
-                    if was_empty && !self.player.is_playing() {
-                        if let Err(e) = self.player.play(&track) {
-                            self.set_status(format!("Error playing: {}", e));
-                        } else {
-                            self.player.pause();
-                            self.start_track(&track);
-                        }
+                    if was_empty && !self.playback.is_playing() {
+                        self.play_track(&track);
+                        self.engine.send(Command::Pause);
                     }
                 }
             }
@@ -444,14 +1052,26 @@ impl App {
             KeyCode::Char('a') => {
                 if let Some(entry) = self.browser.selected_entry() {
                     if !entry.is_dir() {
+                        self.queue.checkpoint("add to queue", self.queue_selected);
                         self.queue.add(entry.path().to_path_buf());
                         self.set_status(format!("Added to queue: {}", entry.name()));
                     }
                 }
             }
+            KeyCode::Char('N') => {
+                if let Some(entry) = self.browser.selected_entry() {
+                    if entry.is_dir() {
+                        self.set_status(String::from("Select a track to play next"));
+                    } else {
+                        let (path, name) = (entry.path().to_path_buf(), entry.name());
+                        self.insert_next(vec![path], &name);
+                    }
+                }
+            }
             KeyCode::Char('A') => {
                 let files = self.browser.get_all_audio_files();
                 let count = files.len();
+                self.queue.checkpoint("add to queue", self.queue_selected);
                 self.queue.add_multiple(files);
                 self.set_status(format!("Added {} tracks to queue", count));
             }
@@ -460,6 +1080,8 @@ impl App {
         Ok(())
     }
 
+    // Emptiness checks stay inside the arms, so those keys never fall through to `_`
+    #[allow(clippy::collapsible_match)]
     fn handle_queue_keys(&mut self, key: KeyEvent) -> Result<()> {
         match key.code {
             KeyCode::Char('j') | KeyCode::Down => {
@@ -493,45 +1115,110 @@ impl App {
                 if let Some(track) = self.queue.jump_to(self.queue_selected) {
                     let track_clone = track.clone();
                     let display_path = track_clone.display().to_string();
-                    if let Err(e) = self.player.play(&track_clone) {
-                        self.set_status(format!("Error playing: {}", e));
-                    } else {
-                        self.set_status(format!("Playing: {}", display_path));
-                        self.start_track(&track_clone);
-                    }
+                    self.play_track(&track_clone);
+                    self.set_status(format!("Playing: {}", display_path));
                 }
             }
+            KeyCode::Char('V') => {
+                // Pressed again, it leaves visual mode
+                let leaving = self.visual_anchor.take().is_some();
+                if !leaving && !self.queue.is_empty() {
+                    self.visual_anchor = Some(self.queue_selected);
+                    self.set_status(String::from("Visual mode: move to extend, Esc to cancel"));
+                }
+            }
+            KeyCode::Char('m') => {
+                if let Some(anchor) = self.visual_anchor.take() {
+                    // The range stays marked, so that rows elsewhere can be added to it
+                    let (start, end) = (
+                        anchor.min(self.queue_selected),
+                        anchor.max(self.queue_selected),
+                    );
+                    self.queue_marks.extend(start..=end);
+                } else if !self.queue.is_empty() && !self.queue_marks.remove(&self.queue_selected) {
+                    self.queue_marks.insert(self.queue_selected);
+                }
+            }
+            KeyCode::Esc => self.clear_queue_selection(),
             KeyCode::Char('d') | KeyCode::Delete | KeyCode::Backspace => {
-                if !self.queue.is_empty() {
-                    self.queue.remove(self.queue_selected);
-                    self.set_status(String::from("Removed from queue"));
-                    if self.queue_selected >= self.queue.len() && !self.queue.is_empty() {
-                        self.queue_selected = self.queue.len() - 1;
+                let rows = self.queue_selection();
+                if let Some(&first) = rows.first() {
+                    self.queue.checkpoint("remove tracks", self.queue_selected);
+                    self.queue.remove_many(&rows);
+                    self.clear_queue_selection();
+                    if rows.len() == 1 {
+                        self.set_status(String::from("Removed from queue"));
+                    } else {
+                        self.set_status(format!("Removed {} tracks from queue", rows.len()));
                     }
+                    // Land on the row after the first one removed
+                    self.queue_selected = first.min(self.queue.len().saturating_sub(1));
                 }
             }
             KeyCode::Char('K') => {
-                if self.queue_selected > 0 {
-                    self.queue.move_up(self.queue_selected);
-                    self.queue_selected -= 1;
-                    self.set_status(String::from("Moved track up"));
+                let rows = self.queue_selection();
+                if rows.first().is_some_and(|&first| first > 0) {
+                    self.queue.checkpoint("move tracks", self.queue_selected);
+                    self.queue.move_block_up(&rows);
+                    self.shift_queue_selection(&rows, true);
+                    self.set_status(Self::moved_status(rows.len(), "up"));
                 }
             }
             KeyCode::Char('J') => {
-                if self.queue_selected + 1 < self.queue.len() {
-                    self.queue.move_down(self.queue_selected);
-                    self.queue_selected += 1;
-                    self.set_status(String::from("Moved track down"));
+                let rows = self.queue_selection();
+                if rows.last().is_some_and(|&last| last + 1 < self.queue.len()) {
+                    self.queue.checkpoint("move tracks", self.queue_selected);
+                    self.queue.move_block_down(&rows);
+                    self.shift_queue_selection(&rows, false);
+                    self.set_status(Self::moved_status(rows.len(), "down"));
+                }
+            }
+            KeyCode::Char('x') => {
+                let rows = self.queue_selection();
+                if rows.len() > 1 {
+                    self.queue.checkpoint("shuffle tracks", self.queue_selected);
+                    self.queue.shuffle_tracks(&rows);
+                    self.set_status(format!("Shuffled {} tracks", rows.len()));
+                } else {
+                    self.set_status(String::from("Select tracks to shuffle with V or m first"));
                 }
             }
             KeyCode::Char('c') => {
+                if !self.queue.is_empty() {
+                    self.queue.checkpoint("clear queue", self.queue_selected);
+                }
                 self.queue.clear();
                 self.queue_selected = 0;
+                self.clear_queue_selection();
                 self.set_status(String::from("Queue cleared"));
             }
+            KeyCode::Char('u') => match self.queue.undo(self.queue_selected) {
+                Some((edit, selected)) => {
+                    self.queue_selected = selected;
+                    self.clear_queue_selection();
+                    self.set_status(format!("Undo: {edit}"));
+                }
+                None => self.set_status(String::from("Nothing to undo")),
+            },
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                match self.queue.redo(self.queue_selected) {
+                    Some((edit, selected)) => {
+                        self.queue_selected = selected;
+                        self.clear_queue_selection();
+                        self.set_status(format!("Redo: {edit}"));
+                    }
+                    None => self.set_status(String::from("Nothing to redo")),
+                }
+            }
             KeyCode::Char('S') => {
                 if self.queue.is_empty() {
                     self.set_status(String::from("Queue is empty - nothing to save"));
+                } else if self.has_queue_selection() {
+                    self.input_mode = InputMode::Command;
+                    self.command_input = String::from("save-selection ");
+                    self.set_status(String::from(
+                        "Enter name to save the selected tracks as playlist (default folder)",
+                    ));
                 } else {
                     self.input_mode = InputMode::Command;
                     self.command_input = String::from("save-queue ");
@@ -545,16 +1232,58 @@ impl App {
         Ok(())
     }
 
+    // Rows the queue keys act on, in order: the marked ones and the visual range, or
+    // else the selected row alone
+    fn queue_selection(&self) -> Vec<usize> {
+        let mut rows = self.queue_marks.clone();
+        if let Some(anchor) = self.visual_anchor {
+            rows.extend(anchor.min(self.queue_selected)..=anchor.max(self.queue_selected));
+        }
+        if rows.is_empty() {
+            rows.insert(self.queue_selected);
+        }
+        rows.into_iter()
+            .filter(|&row| row < self.queue.len())
+            .collect()
+    }
+
+    fn has_queue_selection(&self) -> bool {
+        self.visual_anchor.is_some() || !self.queue_marks.is_empty()
+    }
+
+    fn moved_status(count: usize, direction: &str) -> String {
+        if count == 1 {
+            format!("Moved track {direction}")
+        } else {
+            format!("Moved {count} tracks {direction}")
+        }
+    }
+
+    fn clear_queue_selection(&mut self) {
+        self.queue_marks.clear();
+        self.visual_anchor = None;
+    }
+
+    // Follows `rows` after a block move took them one row up or down
+    fn shift_queue_selection(&mut self, rows: &[usize], up: bool) {
+        let shift = |row: usize| if up { row - 1 } else { row + 1 };
+        self.queue_marks = self.queue_marks.iter().map(|&row| shift(row)).collect();
+        self.visual_anchor = self.visual_anchor.map(shift);
+        if rows.contains(&self.queue_selected) {
+            self.queue_selected = shift(self.queue_selected);
+        }
+    }
+
     fn handle_player_keys(&mut self, key: KeyEvent) -> Result<()> {
         match key.code {
             KeyCode::Char('+' | '=') => {
                 self.config.volume = (self.config.volume + 0.1).min(1.0);
-                self.player.set_volume(self.config.volume);
+                self.engine.send(Command::SetVolume(self.config.volume));
                 self.set_status(format!("Volume: {:.0}%", self.config.volume * 100.0));
             }
             KeyCode::Char('-') => {
                 self.config.volume = (self.config.volume - 0.1).max(0.0);
-                self.player.set_volume(self.config.volume);
+                self.engine.send(Command::SetVolume(self.config.volume));
                 self.set_status(format!("Volume: {:.0}%", self.config.volume * 100.0));
             }
             _ => {}
@@ -599,17 +1328,30 @@ impl App {
             }
             KeyCode::Char('l') | KeyCode::Enter => {
                 if let Some(playlist) = self.playlist_manager.get_playlist(self.playlist_selected) {
+                    self.queue.checkpoint("add playlist", self.queue_selected);
                     self.queue.add_multiple(playlist.tracks.clone());
                     self.set_status(format!("Added playlist '{}' to queue", playlist.name));
                 }
             }
+            KeyCode::Char('N') => {
+                if let Some(playlist) = self.playlist_manager.get_playlist(self.playlist_selected) {
+                    let tracks = playlist.tracks.clone();
+                    let name = format!("playlist '{}'", playlist.name);
+                    self.insert_next(tracks, &name);
+                }
+            }
             _ => {}
         }
         Ok(())
     }
 
+    // Emptiness checks stay inside the arms, so those keys never fall through to `_`
+    #[allow(clippy::collapsible_match)]
     fn handle_search_mode(&mut self, key: KeyEvent) -> Result<()> {
         match key.code {
+            KeyCode::Char('n') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.insert_search_result_next();
+            }
             KeyCode::Enter => {
                 if !self.search_results.is_empty() {
                     if let Some(result) = self.search_results.get(self.search_result_selected) {
@@ -654,8 +1396,13 @@ impl App {
         Ok(())
     }
 
+    // Emptiness checks stay inside the arms, so those keys never fall through to `_`
+    #[allow(clippy::collapsible_match)]
     fn handle_recursive_search_mode(&mut self, key: KeyEvent) -> Result<()> {
         match key.code {
+            KeyCode::Char('n') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.insert_search_result_next();
+            }
             KeyCode::Enter => {
                 if !self.search_results.is_empty() {
                     if let Some(result) = self.search_results.get(self.search_result_selected) {
@@ -700,6 +1447,47 @@ impl App {
         Ok(())
     }
 
+    // Queues the highlighted search result to play next, leaving the overlay open so
+    // that more can follow
+    fn insert_search_result_next(&mut self) {
+        let Some(result) = self.search_results.get(self.search_result_selected) else {
+            return;
+        };
+        if result.path.is_dir() {
+            self.set_status(String::from("Select a track to play next"));
+            return;
+        }
+        let (path, name) = (result.path.clone(), result.name.clone());
+        self.insert_next(vec![path], &name);
+    }
+
+    // Queues `tracks` right after the current one, after those queued this way before
+    fn insert_next(&mut self, tracks: Vec<PathBuf>, name: &str) {
+        if tracks.is_empty() {
+            self.set_status(format!("Nothing to play in {name}"));
+            return;
+        }
+        let was_empty = self.queue.is_empty();
+        let count = tracks.len();
+        self.queue.checkpoint("play next", self.queue_selected);
+        let index = self.queue.insert_next(tracks);
+        // Marks and the cursor stay on the tracks they were on
+        let shift = |row| queue::shift_for_insert(row, index, count);
+        self.queue_marks = self.queue_marks.iter().map(|&row| shift(row)).collect();
+        self.visual_anchor = self.visual_anchor.map(shift);
+        if !was_empty {
+            self.queue_selected = shift(self.queue_selected);
+        }
+        self.set_status(format!("Playing next: {name}"));
+
+        if was_empty && !self.playback.is_playing() {
+            if let Some(track) = self.queue.current().cloned() {
+                self.play_track(&track);
+                self.engine.send(Command::Pause);
+            }
+        }
+    }
+
     fn handle_command_mode(&mut self, key: KeyEvent) -> Result<()> {
         match key.code {
             KeyCode::Enter => {
@@ -834,17 +1622,150 @@ impl App {
                 } else {
                     None
                 };
-                self.save_queue_as_playlist(name.as_deref())?;
-            }
-            "vol" | "volume" => {
-                if parts.len() > 1 {
-                    if let Ok(vol) = parts[1].parse::<f32>() {
-                        self.config.volume = (vol / 100.0).clamp(0.0, 1.0);
-                        self.player.set_volume(self.config.volume);
-                        self.set_status(format!("Volume: {:.0}%", self.config.volume * 100.0));
+                self.save_queue_as_playlist(name.as_deref())?;
+            }
+            "save-selection" => {
+                let name = if parts.len() > 1 {
+                    Some(parts[1..].join(" "))
+                } else {
+                    None
+                };
+                self.save_selection_as_playlist(name.as_deref())?;
+            }
+            "vol" | "volume" => {
+                if parts.len() > 1 {
+                    if let Ok(vol) = parts[1].parse::<f32>() {
+                        self.config.volume = (vol / 100.0).clamp(0.0, 1.0);
+                        self.engine.send(Command::SetVolume(self.config.volume));
+                        self.set_status(format!("Volume: {:.0}%", self.config.volume * 100.0));
+                    }
+                }
+            }
+            "replaygain" | "rg" => {
+                if let Some(mode) = parts.get(1).and_then(|m| ReplayGainMode::parse(m)) {
+                    self.config.replaygain = mode;
+                }
+                if let Some(preamp) = parts
+                    .get(2)
+                    .and_then(|p| p.parse::<f32>().ok())
+                    .filter(|p| !p.is_nan())
+                {
+                    self.config.replaygain_preamp = preamp.clamp(-MAX_PREAMP_DB, MAX_PREAMP_DB);
+                }
+                self.apply_replaygain();
+                self.set_status(format!(
+                    "ReplayGain: {} (preamp {:+.1} dB)",
+                    self.config.replaygain.as_str(),
+                    self.config.replaygain_preamp
+                ));
+            }
+            "crossfade" | "xf" => {
+                if parts.len() > 1 {
+                    if let Some(secs) = parts[1].parse::<f32>().ok().filter(|s| !s.is_nan()) {
+                        self.config.crossfade_secs = secs.clamp(0.0, MAX_CROSSFADE_SECS);
+                        self.engine.send(Command::SetCrossfade(
+                            self.config.crossfade_secs,
+                            self.config.crossfade_curve,
+                        ));
+                        // Re-queue the upcoming track so it picks up the new fade
+                        self.gapless_candidate = None;
+                    }
+                }
+                if self.config.crossfade_secs > 0.0 {
+                    self.set_status(format!("Crossfade: {:.1}s", self.config.crossfade_secs));
+                } else {
+                    self.set_status(String::from("Crossfade: Off"));
+                }
+            }
+            "dynamics" | "drc" => match parts.get(1).map(|p| DynamicsPreset::parse(p)) {
+                Some(Some(preset)) => {
+                    self.config.dynamics = preset;
+                    self.engine.send(Command::SetDynamics(preset));
+                    self.set_status(format!("Dynamics: {}", preset.as_str()));
+                }
+                Some(None) => {
+                    self.set_status(String::from("Dynamics presets: off, light, night"));
+                }
+                None => {
+                    self.set_status(format!("Dynamics: {}", self.config.dynamics.as_str()));
+                }
+            },
+            "balance" => match parts
+                .get(1)
+                .map(|p| p.parse::<f32>().ok().filter(|p| p.is_finite()))
+            {
+                Some(None) => {
+                    self.set_status(String::from(
+                        "Balance: -100 (left) to 100 (right), 0 for center",
+                    ));
+                }
+                value => {
+                    if let Some(Some(percent)) = value {
+                        self.config.balance = (percent / 100.0).clamp(-1.0, 1.0);
+                        self.apply_channels();
+                    }
+                    self.set_status(format!(
+                        "Balance: {}",
+                        self.channel_settings().balance_label()
+                    ));
+                }
+            },
+            "mono" => {
+                self.config.mono = match parts.get(1).copied() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => !self.config.mono,
+                };
+                self.apply_channels();
+                let state = if self.config.mono { "On" } else { "Off" };
+                self.set_status(format!("Mono: {state}"));
+            }
+            "sleep" => match parts.get(1).copied() {
+                Some("off") => {
+                    self.cancel_sleep_timer();
+                    self.set_status(String::from("Sleep timer: Off"));
+                }
+                Some(value) => {
+                    if let Some(target) = SleepTarget::parse(value, Instant::now()) {
+                        self.sleep_timer = Some(target);
+                        let label = self.sleep_label().unwrap_or_default();
+                        self.set_status(format!("Sleep timer: {label}"));
+                    } else {
+                        self.set_status(String::from(
+                            "Sleep timer: 30m, 1h15m, end-of-track, end-of-queue or off",
+                        ));
                     }
                 }
-            }
+                None => {
+                    let label = self.sleep_label().unwrap_or_else(|| String::from("Off"));
+                    self.set_status(format!("Sleep timer: {label}"));
+                }
+            },
+            "eq" | "equalizer" => match parts.get(1).copied() {
+                Some("off") => {
+                    self.equalizer.enabled = false;
+                    self.apply_equalizer();
+                    self.set_status(String::from("Equalizer: Off"));
+                }
+                Some("on") => {
+                    self.equalizer.enabled = true;
+                    self.apply_equalizer();
+                    self.set_status(format!("Equalizer: {}", self.equalizer.preset));
+                }
+                Some(name) => {
+                    if self.equalizer.apply_preset(name, &self.config.eq_presets) {
+                        self.apply_equalizer();
+                        self.set_status(format!("Equalizer: {}", name));
+                    } else {
+                        self.set_status(format!("Unknown equalizer preset: {}", name));
+                    }
+                }
+                None => {
+                    let names: Vec<&str> =
+                        self.config.eq_presets.keys().map(String::as_str).collect();
+                    self.set_status(format!("Equalizer presets: {}", names.join(", ")));
+                }
+            },
             _ => {
                 self.set_status(format!("Unknown command: {}", parts[0]));
             }
@@ -854,6 +1775,30 @@ impl App {
         Ok(())
     }
 
+    fn apply_equalizer(&self) {
+        self.engine
+            .send(Command::SetEqualizer(self.equalizer.active_bands()));
+    }
+
+    fn channel_settings(&self) -> ChannelSettings {
+        ChannelSettings {
+            balance: self.config.balance,
+            mono: self.config.mono,
+        }
+    }
+
+    fn apply_channels(&self) {
+        self.engine
+            .send(Command::SetChannels(self.channel_settings()));
+    }
+
+    fn apply_replaygain(&self) {
+        self.engine.send(Command::SetReplayGain(
+            self.config.replaygain.resolve(self.queue.is_random()),
+            self.config.replaygain_preamp,
+        ));
+    }
+
     fn clear_search_results(&mut self) {
         self.search_results.clear();
         self.search_result_selected = 0;
@@ -865,6 +1810,31 @@ impl App {
             return Ok(());
         }
 
+        let tracks = self.queue.tracks().to_vec();
+        self.save_as_playlist(name, &tracks, "Queue")
+    }
+
+    fn save_selection_as_playlist(&mut self, name: Option<&str>) -> Result<()> {
+        if !self.has_queue_selection() {
+            self.set_status(String::from("No tracks selected - use V or m in the queue"));
+            return Ok(());
+        }
+
+        let tracks: Vec<PathBuf> = self
+            .queue_selection()
+            .into_iter()
+            .map(|row| self.queue.tracks()[row].clone())
+            .collect();
+        let what = format!("{} selected tracks", tracks.len());
+        self.save_as_playlist(name, &tracks, &what)
+    }
+
+    fn save_as_playlist(
+        &mut self,
+        name: Option<&str>,
+        tracks: &[PathBuf],
+        what: &str,
+    ) -> Result<()> {
         let playlist_name = name
             .map(|n| n.trim())
             .filter(|n| !n.is_empty())
@@ -873,10 +1843,11 @@ impl App {
 
         let path = self
             .playlist_manager
-            .save_playlist(&playlist_name, self.queue.tracks())?;
+            .save_playlist(&playlist_name, tracks)?;
 
         self.set_status(format!(
-            "Queue saved as '{}' at {}",
+            "{} saved as '{}' at {}",
+            what,
             playlist_name,
             path.display()
         ));
@@ -900,49 +1871,212 @@ impl App {
         use crate::queue::RepeatMode;
         let repeat_mode = self.queue.repeat_mode();
 
-        if repeat_mode == RepeatMode::Track {
+        // A track that had to be cut short would only fail again
+        let broken = self
+            .queue
+            .current()
+            .is_some_and(|track| self.problem_tracks.contains(track));
+
+        if repeat_mode == RepeatMode::Track && !broken {
             // Repeat current track
             if let Some(track) = self.queue.current() {
                 let track_clone = track.clone();
                 let display_path = track_clone.display().to_string();
-                if let Err(e) = self.player.play(&track_clone) {
-                    self.set_status(format!("Error playing: {}", e));
-                } else {
-                    self.set_status(format!("Repeating: {}", display_path));
-                    self.start_track(&track_clone);
-                }
+                self.play_track(&track_clone);
+                self.set_status(format!("Repeating: {}", display_path));
             }
         } else if let Some(track) = self.queue.next() {
             // Normal next or random mode
             let track_clone = track.clone();
             let display_path = track_clone.display().to_string();
-            if let Err(e) = self.player.play(&track_clone) {
-                self.set_status(format!("Error playing: {}", e));
-            } else {
-                self.set_status(format!("Playing: {}", display_path));
-                self.start_track(&track_clone);
-                // Sync queue selection to current playing track
-                if let Some(current_idx) = self.queue.current_index() {
-                    self.queue_selected = current_idx;
-                }
+            self.play_track(&track_clone);
+            self.set_status(format!("Playing: {}", display_path));
+            // Sync queue selection to current playing track
+            if let Some(current_idx) = self.queue.current_index() {
+                self.queue_selected = current_idx;
             }
         } else if repeat_mode == RepeatMode::Queue {
             // Reached end of queue, restart from beginning
             if let Some(track) = self.queue.restart() {
                 let track_clone = track.clone();
                 let display_path = track_clone.display().to_string();
-                if let Err(e) = self.player.play(&track_clone) {
-                    self.set_status(format!("Error playing: {}", e));
-                } else {
-                    self.set_status(format!("Restarting queue: {}", display_path));
-                    self.start_track(&track_clone);
-                    // Sync queue selection to current playing track
-                    if let Some(current_idx) = self.queue.current_index() {
-                        self.queue_selected = current_idx;
-                    }
+                self.play_track(&track_clone);
+                self.set_status(format!("Restarting queue: {}", display_path));
+                // Sync queue selection to current playing track
+                if let Some(current_idx) = self.queue.current_index() {
+                    self.queue_selected = current_idx;
+                }
+            }
+        }
+    }
+
+    // Catches up with the engine, which moved on to the preloaded `track` by itself
+    fn follow_gapless_transition(&mut self, track: &Path) {
+        use crate::queue::RepeatMode;
+
+        // Advance the queue the same way play_next would have
+        let repeat_mode = self.queue.repeat_mode();
+        if repeat_mode != RepeatMode::Track
+            && self.queue.next().is_none()
+            && repeat_mode == RepeatMode::Queue
+        {
+            self.queue.restart();
+        }
+
+        self.set_status(format!("Playing: {}", track.display()));
+        // Nothing is preloaded after it yet
+        self.gapless_candidate = None;
+        // Sync queue selection to current playing track
+        if let Some(current_idx) = self.queue.current_index() {
+            self.queue_selected = current_idx;
+        }
+    }
+
+    // Logs a decode error of the playing track and flags it if it was cut short
+    fn report_decode_error(&mut self, error: DecodeError) {
+        logger::log(&format!(
+            "Decode error in {} at {}: {}",
+            error.path.display(),
+            format_time(error.position),
+            error.message
+        ));
+
+        let name = cue::display_name(&error.path);
+        if error.gave_up {
+            self.set_status(format!(
+                "Skipped {} after {} decode error(s)",
+                name, error.count
+            ));
+            self.problem_tracks.insert(error.path);
+        } else {
+            self.set_status(format!(
+                "Decode error in {} at {} ({} so far)",
+                name,
+                format_time(error.position),
+                error.count
+            ));
+        }
+    }
+
+    // Whether the sleep timer goes off once the playing track ends
+    fn sleeps_after_current_track(&self) -> bool {
+        match self.sleep_timer {
+            Some(SleepTarget::EndOfTrack) => true,
+            Some(SleepTarget::EndOfQueue) => match self.queue.repeat_mode() {
+                // A repeated queue never runs out, so it ends where it starts over
+                queue::RepeatMode::Queue if !self.queue.is_random() => self
+                    .queue
+                    .current_index()
+                    .is_some_and(|index| index + 1 == self.queue.len()),
+                _ => !self.queue.has_next(),
+            },
+            _ => false,
+        }
+    }
+
+    // Time left before the sleep timer goes off, when it can be told
+    fn sleep_remaining(&self) -> Option<Duration> {
+        match self.sleep_timer? {
+            SleepTarget::At(deadline) => Some(deadline.saturating_duration_since(Instant::now())),
+            _ if self.sleeps_after_current_track() => {
+                let duration = self.playback.current_metadata()?.duration_secs?;
+                Some(Duration::from_secs(duration).saturating_sub(self.playback.position()))
+            }
+            _ => None,
+        }
+    }
+
+    fn sleep_label(&self) -> Option<String> {
+        let target = self.sleep_timer?;
+        let remaining = self.sleep_remaining();
+        let label = match target {
+            SleepTarget::At(_) => {
+                return Some(format!(
+                    "Sleep in {}",
+                    format_time(remaining.unwrap_or_default())
+                ));
+            }
+            SleepTarget::EndOfTrack => "Sleep at end of track",
+            SleepTarget::EndOfQueue => "Sleep at end of queue",
+        };
+        Some(match remaining {
+            Some(remaining) => format!("{label} ({})", format_time(remaining)),
+            None => label.to_string(),
+        })
+    }
+
+    // Lowers the volume over the last seconds before the sleep timer, and pauses once
+    // its time is up
+    fn update_sleep_timer(&mut self) {
+        let Some(target) = self.sleep_timer else {
+            return;
+        };
+        let remaining = self.sleep_remaining();
+        let fade = Duration::from_secs_f32(self.config.sleep_fade_secs.max(0.0));
+        let factor = remaining.map_or(1.0, |remaining| sleep::fade_factor(remaining, fade));
+        let volume = self.config.volume * factor;
+        // Nothing to send before the fade starts, nor while it stands still
+        if (factor < 1.0 || self.sleep_volume.is_some()) && self.sleep_volume != Some(volume) {
+            self.engine.send(Command::SetVolume(volume));
+            self.sleep_volume = Some(volume);
+        }
+
+        if matches!(target, SleepTarget::At(_)) && remaining == Some(Duration::ZERO) {
+            self.engine.send(Command::Pause);
+            self.cancel_sleep_timer();
+            self.set_status(String::from("Sleep timer: Paused"));
+        }
+    }
+
+    // The track the sleep timer waited for played out. The next one is loaded paused,
+    // unless the queue is over.
+    fn sleep_at_track_end(&mut self) {
+        self.scrobble_if_needed();
+        let next = match self.sleep_timer {
+            Some(SleepTarget::EndOfTrack) => self.queue.next().cloned(),
+            _ => None,
+        };
+        match next {
+            Some(track) => {
+                self.play_track(&track);
+                self.engine.send(Command::Pause);
+                if let Some(current_idx) = self.queue.current_index() {
+                    self.queue_selected = current_idx;
                 }
             }
+            None => self.engine.send(Command::Stop),
+        }
+        // Should that track fail, playback stays stopped
+        self.auto_advance = None;
+        self.cancel_sleep_timer();
+        self.set_status(String::from("Sleep timer: Paused"));
+    }
+
+    // Drops the sleep timer and brings back the volume it may have faded
+    fn cancel_sleep_timer(&mut self) {
+        self.sleep_timer = None;
+        self.sleep_volume = None;
+        self.engine.send(Command::SetVolume(self.config.volume));
+    }
+
+    fn prepare_gapless_next(&mut self) {
+        if self.playback.current_track().is_none() || self.playback.is_finished() {
+            return;
+        }
+
+        // Only decode again when the upcoming track changed (queue edits, mode toggles).
+        // Nothing follows a track the sleep timer waits for.
+        let upcoming = if self.sleeps_after_current_track() {
+            None
+        } else {
+            self.queue.peek_next().cloned()
+        };
+        if upcoming == self.gapless_candidate {
+            return;
         }
+        self.gapless_candidate.clone_from(&upcoming);
+
+        self.engine.send(Command::QueueNext(upcoming));
     }
 
     fn play_prev(&mut self) {
@@ -952,25 +2086,23 @@ impl App {
         if let Some(track) = self.queue.prev() {
             let track_clone = track.clone();
             let display_path = track_clone.display().to_string();
-            if let Err(e) = self.player.play(&track_clone) {
-                self.set_status(format!("Error playing: {}", e));
-            } else {
-                self.set_status(format!("Playing: {}", display_path));
-                self.start_track(&track_clone);
-                // Sync queue selection to current playing track
-                if let Some(current_idx) = self.queue.current_index() {
-                    self.queue_selected = current_idx;
-                }
+            self.play_track(&track_clone);
+            self.set_status(format!("Playing: {}", display_path));
+            // Sync queue selection to current playing track
+            if let Some(current_idx) = self.queue.current_index() {
+                self.queue_selected = current_idx;
             }
         }
     }
 
     fn jump_to_playing(&mut self) {
-        if let Some(current_track) = self.player.current_track() {
+        if let Some(current_track) = self.playback.current_track() {
             match self.current_tab {
                 Tab::Browser => {
                     // Navigate to the directory containing the playing track
-                    if let Some(parent) = current_track.parent() {
+                    if radio::is_url(&current_track) {
+                        self.set_status(String::from("Streams are not in the browser"));
+                    } else if let Some(parent) = current_track.parent() {
                         self.browser.navigate_to(parent.to_path_buf());
                         // Select the currently playing file in the browser
                         self.browser.select_entry_by_path(&current_track);
@@ -1000,8 +2132,11 @@ impl App {
     }
 
     fn draw(&mut self, f: &mut Frame) {
+        self.list_areas.clear();
+        self.progress_bar_area = None;
+
         // Determine if we should show the progress bar
-        let show_progress = !self.queue.is_empty() && self.player.current_track().is_some();
+        let show_progress = !self.queue.is_empty() && self.playback.current_track().is_some();
 
         let constraints = if show_progress {
             vec![
@@ -1041,15 +2176,22 @@ impl App {
         }
     }
 
-    fn draw_tabs(&self, f: &mut Frame, area: Rect) {
+    fn draw_tabs(&mut self, f: &mut Frame, area: Rect) {
         let tabs = [Tab::Browser, Tab::NowPlaying, Tab::Playlists];
 
         let mut tab_text = String::new();
+        self.tab_areas.clear();
         for (i, tab) in tabs.iter().enumerate() {
             if i > 0 {
                 tab_text.push_str(" | ");
             }
-            tab_text.push_str(&format!(" {} ", tab.name()));
+            let label = format!(" {} ", tab.name());
+            // Inside the left border
+            let x = area.x + 1 + tab_text.chars().count() as u16;
+            let width = label.chars().count() as u16;
+            self.tab_areas
+                .push((*tab, Rect::new(x, area.y, width, area.height)));
+            tab_text.push_str(&label);
         }
 
         let tabs_widget =
@@ -1081,8 +2223,16 @@ impl App {
                 };
 
                 let prefix = if entry.is_dir() { "📁 " } else { "🎵 " };
-                let content = format!("{}{}", prefix, entry.name());
-                ListItem::new(content).style(style)
+                let mut spans = vec![Span::raw(format!("{}{}", prefix, entry.name()))];
+                // How far a long track got the last time it played
+                if let Some(point) = self.playback.resume_point(entry.path()) {
+                    let marker = match point.percent() {
+                        Some(percent) => format!(" [{percent}%]"),
+                        None => format!(" [{}]", format_time(point.position())),
+                    };
+                    spans.push(Span::styled(marker, Style::default().fg(Color::Cyan)));
+                }
+                ListItem::new(Line::from(spans)).style(style)
             })
             .collect();
 
@@ -1091,6 +2241,7 @@ impl App {
             .block(Block::default().borders(Borders::ALL).title(title))
             .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
 
+        self.list_areas.push((ListArea::Browser, area));
         self.browser_state.select(Some(self.browser.selected()));
         f.render_stateful_widget(list, area, &mut self.browser_state);
     }
@@ -1102,10 +2253,131 @@ impl App {
             .split(area);
 
         self.draw_queue(f, chunks[0]);
-        self.draw_player(f, chunks[1]);
+
+        if let Some(selected) = self.eq_panel {
+            let right = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Min(0),
+                    Constraint::Length(BAND_COUNT as u16 + 2),
+                ])
+                .split(chunks[1]);
+            self.draw_player(f, right[0]);
+            self.draw_equalizer(f, right[1], selected);
+        } else {
+            self.draw_player(f, chunks[1]);
+        }
+    }
+
+    fn draw_equalizer(&self, f: &mut Frame, area: Rect, selected: usize) {
+        // Label, bar and value; the bar is centered on 0 dB
+        let half = (area.width.saturating_sub(2 + 5 + 10) / 2).max(1) as usize;
+
+        let lines: Vec<Line> = self
+            .equalizer
+            .bands
+            .iter()
+            .enumerate()
+            .map(|(band, gain)| {
+                let filled = ((gain.abs() / MAX_GAIN_DB) * half as f32).round() as usize;
+                let (left, right) = if *gain < 0.0 {
+                    (
+                        format!("{}{}", " ".repeat(half - filled), "█".repeat(filled)),
+                        " ".repeat(half),
+                    )
+                } else {
+                    (
+                        " ".repeat(half),
+                        format!("{}{}", "█".repeat(filled), " ".repeat(half - filled)),
+                    )
+                };
+
+                let mut style = Style::default();
+                if !self.equalizer.enabled {
+                    style = style.fg(Color::DarkGray);
+                }
+                if band == selected {
+                    style = style.add_modifier(Modifier::BOLD).fg(Color::Yellow);
+                }
+
+                Line::from(Span::styled(
+                    format!(
+                        "{:>4} {}│{} {:+5.1} dB",
+                        equalizer::band_label(band),
+                        left,
+                        right,
+                        gain
+                    ),
+                    style,
+                ))
+            })
+            .collect();
+
+        let mut title = format!("Equalizer: {}", self.equalizer.preset);
+        if self.equalizer.is_modified(&self.config.eq_presets) {
+            title.push_str(" (modified)");
+        }
+        if !self.equalizer.enabled {
+            title.push_str(" - bypassed");
+        }
+
+        let paragraph =
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(paragraph, area);
+    }
+
+    fn draw_visualizer(&mut self, f: &mut Frame, area: Rect) {
+        let block = Block::default().borders(Borders::ALL).title("Visualizer");
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(2)])
+            .split(inner);
+
+        // Nothing is pulled from the tap while paused, so let the display fall instead
+        let (frames, sample_rate) = if self.playback.is_playing() {
+            self.engine.tap().snapshot()
+        } else {
+            (Vec::new(), 0)
+        };
+        // Bars two cells wide with a one cell gap
+        let bar_count = (rows[0].width as usize).div_ceil(3);
+        self.analyzer.update(&frames, sample_rate, bar_count);
+
+        let bars: Vec<Bar> = self
+            .analyzer
+            .bars()
+            .iter()
+            .map(|height| {
+                Bar::default()
+                    .value((height * 100.0).round() as u64)
+                    .text_value(String::new())
+            })
+            .collect();
+        let chart = BarChart::default()
+            .data(BarGroup::default().bars(&bars))
+            .bar_width(2)
+            .bar_gap(1)
+            .max(100)
+            .bar_style(Style::default().fg(Color::Cyan));
+        f.render_widget(chart, rows[0]);
+
+        let [left, right] = self.analyzer.levels();
+        let meters = vec![
+            meter_line("L", left, rows[1].width),
+            meter_line("R", right, rows[1].width),
+        ];
+        f.render_widget(Paragraph::new(meters), rows[1]);
     }
 
     fn draw_queue(&mut self, f: &mut Frame, area: Rect) {
+        let selection: HashSet<usize> = if self.has_queue_selection() {
+            self.queue_selection().into_iter().collect()
+        } else {
+            HashSet::new()
+        };
         let tracks = self.queue.tracks();
         let current_index = self.queue.current_index();
 
@@ -1115,6 +2387,11 @@ impl App {
             .map(|(i, track)| {
                 let mut style = Style::default();
 
+                let broken = self.problem_tracks.contains(track);
+                if broken {
+                    style = style.fg(Color::Red);
+                }
+
                 if Some(i) == current_index {
                     style = style.fg(Color::Green);
                 }
@@ -1123,14 +2400,16 @@ impl App {
                     style = style.add_modifier(Modifier::BOLD).fg(Color::Yellow);
                 }
 
-                let name = track
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("")
-                    .to_string();
+                if selection.contains(&i) {
+                    style = style.bg(Color::DarkGray);
+                }
+
+                let name = cue::display_name(track);
 
                 let prefix = if Some(i) == current_index {
                     "▶ "
+                } else if broken {
+                    "⚠ "
                 } else {
                     "  "
                 };
@@ -1138,17 +2417,32 @@ impl App {
             })
             .collect();
 
-        let title = format!("Queue ({} tracks)", tracks.len());
+        let title = if selection.is_empty() {
+            format!("Queue ({} tracks)", tracks.len())
+        } else {
+            let mode = if self.visual_anchor.is_some() {
+                " - VISUAL"
+            } else {
+                ""
+            };
+            format!(
+                "Queue ({} tracks, {} selected{})",
+                tracks.len(),
+                selection.len(),
+                mode
+            )
+        };
         let list = List::new(items)
             .block(Block::default().borders(Borders::ALL).title(title))
             .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
 
+        self.list_areas.push((ListArea::Queue, area));
         self.queue_state.select(Some(self.queue_selected));
         f.render_stateful_widget(list, area, &mut self.queue_state);
     }
 
     fn draw_player(&mut self, f: &mut Frame, area: Rect) {
-        let metadata = self.player.current_metadata();
+        let metadata = self.playback.current_metadata();
 
         // Split area with percentage-based layout: 55% for track metadata (left), 45% for album art (right)
         let chunks = Layout::default()
@@ -1162,13 +2456,10 @@ impl App {
         if let Some(ref meta) = metadata {
             // Title
             let title = meta.title.clone().unwrap_or_else(|| {
-                self.player
+                self.playback
                     .current_track()
-                    .and_then(|t| {
-                        t.file_name()
-                            .and_then(|n| n.to_str())
-                            .map(|s| s.to_string())
-                    })
+                    .map(|t| cue::display_name(&t))
+                    .filter(|name| !name.is_empty())
                     .unwrap_or_else(|| "Unknown".to_string())
             });
             text.push(Line::from(vec![
@@ -1241,22 +2532,47 @@ impl App {
                 Span::raw(meta.format_duration()),
             ]));
 
+            // Audio stream, when the file has more than one
+            if meta.streams.len() > 1 {
+                if let Some(index) = self.active_stream(meta) {
+                    text.push(Line::from(vec![
+                        Span::styled("Audio: ", Style::default().fg(Color::Cyan)),
+                        Span::raw(format!(
+                            "{}/{} ({})",
+                            index + 1,
+                            meta.streams.len(),
+                            meta.streams[index].describe()
+                        )),
+                    ]));
+                }
+            }
+
+            // ReplayGain (only when it is being applied)
+            let replaygain_mode = self.config.replaygain.resolve(self.queue.is_random());
+            if replaygain_mode != ReplayGainMode::Off {
+                let gain = meta
+                    .replaygain
+                    .gain_db(replaygain_mode == ReplayGainMode::Album)
+                    .map(|gain| format!("{:+.2} dB", gain + self.config.replaygain_preamp))
+                    .unwrap_or_else(|| "untagged".to_string());
+                text.push(Line::from(vec![
+                    Span::styled("ReplayGain: ", Style::default().fg(Color::Cyan)),
+                    Span::raw(format!("{} ({})", gain, replaygain_mode.as_str())),
+                ]));
+            }
+
             text.push(Line::from(""));
 
             // File path
-            if let Some(track) = self.player.current_track() {
+            if let Some(track) = self.playback.current_track() {
                 text.push(Line::from(vec![
                     Span::styled("Path: ", Style::default().fg(Color::Gray)),
                     Span::raw(track.display().to_string()),
                 ]));
             }
-        } else if let Some(track) = self.player.current_track() {
+        } else if let Some(track) = self.playback.current_track() {
             // No metadata available, show basic info
-            let track_name = track
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("Unknown")
-                .to_string();
+            let track_name = cue::display_name(&track);
 
             text.push(Line::from(vec![
                 Span::styled("Now Playing: ", Style::default().fg(Color::Cyan)),
@@ -1274,9 +2590,9 @@ impl App {
         text.push(Line::from(""));
 
         // Playback status
-        let status = if self.player.is_playing() {
+        let status = if self.playback.is_playing() {
             "▶ Playing"
-        } else if self.player.is_paused() {
+        } else if self.playback.is_paused() {
             "⏸ Paused"
         } else {
             "⏹ Stopped"
@@ -1292,6 +2608,27 @@ impl App {
             Span::raw(format!("{:.0}%", self.config.volume * 100.0)),
         ]));
 
+        if self.equalizer.enabled {
+            text.push(Line::from(vec![
+                Span::styled("Equalizer: ", Style::default().fg(Color::Cyan)),
+                Span::raw(self.equalizer.preset.clone()),
+            ]));
+        }
+
+        if let Some(channels) = self.playback.source_channels() {
+            text.push(Line::from(vec![
+                Span::styled("Channels: ", Style::default().fg(Color::Cyan)),
+                Span::raw(self.channel_settings().describe(channels)),
+            ]));
+        }
+
+        if self.config.dynamics != DynamicsPreset::Off {
+            text.push(Line::from(vec![
+                Span::styled("Dynamics: ", Style::default().fg(Color::Cyan)),
+                Span::raw(self.config.dynamics.as_str().to_string()),
+            ]));
+        }
+
         // Show random mode indicator
         if self.queue.is_random() {
             text.push(Line::from(vec![
@@ -1313,51 +2650,54 @@ impl App {
         let paragraph =
             Paragraph::new(text).block(Block::default().borders(Borders::ALL).title("Player"));
 
-        f.render_widget(paragraph, chunks[0]);
-
-        // Render album art in right section (45% of width)
-        let current_track = self.player.current_track();
+        // Chapters take the lower part of the player panel, when the track has any
+        let chapters = metadata
+            .as_ref()
+            .map(|meta| meta.chapters.as_slice())
+            .unwrap_or_default();
+        if chapters.is_empty() {
+            f.render_widget(paragraph, chunks[0]);
+        } else {
+            let left = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Percentage(40)])
+                .split(chunks[0]);
+            f.render_widget(paragraph, left[0]);
+            Self::draw_chapters(f, left[1], chapters, self.playback.position());
+        }
 
-        // Check if we need to reload album art (track changed)
-        let need_reload = {
-            let last_track = self.last_album_art_track.lock().unwrap();
-            *last_track != current_track
-        };
+        if self.visualizer {
+            self.draw_visualizer(f, chunks[1]);
+            return;
+        }
 
-        if need_reload {
-            // Track changed, reload album art
-            if let Some(ref meta) = metadata {
-                if let Some(ref cover_data) = meta.cover_art {
-                    // Try to load and display the album art
-                    match image::load_from_memory(cover_data) {
-                        Ok(img) => {
-                            let mut picker = self.image_picker.lock().unwrap();
-                            let mut album_art = self.album_art.lock().unwrap();
-
-                            // Create or update the album art protocol
-                            *album_art = Some(picker.new_resize_protocol(img));
-                        }
-                        Err(_) => {
-                            // Failed to decode album art
-                            *self.album_art.lock().unwrap() = None;
-                        }
+        // Render album art in right section (45% of width)
+        let current_track = self.playback.current_track();
+
+        // Check if we need to reload album art (track changed, or its tags came in)
+        let key = current_track
+            .clone()
+            .map(|track| (track, metadata.is_some()));
+        let mut last_track = self.last_album_art_track.lock().unwrap();
+        if *last_track != key {
+            *last_track = key.clone();
+            *self.album_art.lock().unwrap() = match (&key, &metadata) {
+                (None, _) => AlbumArt::Missing,
+                (Some(_), None) => AlbumArt::Loading,
+                (Some(_), Some(meta)) => match meta.cover_art.clone() {
+                    Some(cover_data) => {
+                        self.decode_album_art(key, cover_data);
+                        AlbumArt::Loading
                     }
-                } else {
-                    // No album art available
-                    *self.album_art.lock().unwrap() = None;
-                }
-            } else {
-                // No metadata available
-                *self.album_art.lock().unwrap() = None;
-            }
-
-            // Update last loaded track
-            *self.last_album_art_track.lock().unwrap() = current_track.clone();
+                    None => AlbumArt::Missing,
+                },
+            };
         }
+        drop(last_track);
 
         // Render the album art (whether cached or just loaded)
         let mut album_art = self.album_art.lock().unwrap();
-        if let Some(ref mut protocol) = *album_art {
+        if let AlbumArt::Ready(ref mut protocol) = *album_art {
             // Create a block with borders for the album art
             let block = Block::default().borders(Borders::ALL).title("Album Art");
             let inner_area = block.inner(chunks[1]);
@@ -1367,12 +2707,11 @@ impl App {
             f.render_stateful_widget(image_widget, inner_area, protocol);
         } else {
             // Show appropriate placeholder
-            let (text, color) =
-                if metadata.is_some() && metadata.as_ref().unwrap().cover_art.is_some() {
-                    ("Invalid Album Art", Color::Red)
-                } else {
-                    ("No Album Art", Color::DarkGray)
-                };
+            let (text, color) = match *album_art {
+                AlbumArt::Loading => ("Loading Album Art...", Color::DarkGray),
+                AlbumArt::Invalid => ("Invalid Album Art", Color::Red),
+                _ => ("No Album Art", Color::DarkGray),
+            };
 
             let placeholder = Paragraph::new(text)
                 .block(Block::default().borders(Borders::ALL).title("Album Art"))
@@ -1381,6 +2720,69 @@ impl App {
         }
     }
 
+    // Decodes the cover on a background thread so large images do not hold up drawing.
+    // The result is dropped if the track changed in the meantime.
+    fn decode_album_art(&self, key: Option<(PathBuf, bool)>, cover_data: Arc<[u8]>) {
+        let image_picker = Arc::clone(&self.image_picker);
+        let album_art = Arc::clone(&self.album_art);
+        let last_album_art_track = Arc::clone(&self.last_album_art_track);
+        let redraw = self.event_sender.clone();
+        thread::spawn(move || {
+            let art = match image::load_from_memory(&cover_data) {
+                Ok(img) => AlbumArt::Ready(image_picker.lock().unwrap().new_resize_protocol(img)),
+                Err(_) => AlbumArt::Invalid,
+            };
+            let last_track = last_album_art_track.lock().unwrap();
+            if *last_track == key {
+                *album_art.lock().unwrap() = art;
+                let _ = redraw.send(AppEvent::Redraw);
+            }
+        });
+    }
+
+    fn draw_chapters(
+        f: &mut Frame,
+        area: Rect,
+        chapters: &[chapters::Chapter],
+        position: Duration,
+    ) {
+        let current = chapters::index_at(chapters, position);
+        let items: Vec<ListItem> = chapters
+            .iter()
+            .enumerate()
+            .map(|(i, chapter)| {
+                let (marker, style) = if Some(i) == current {
+                    (
+                        "▶ ",
+                        Style::default()
+                            .fg(Color::Yellow)
+                            .add_modifier(Modifier::BOLD),
+                    )
+                } else {
+                    ("  ", Style::default())
+                };
+                ListItem::new(Line::from(vec![
+                    Span::raw(marker),
+                    Span::styled(
+                        format!("{:>7} ", format_time(chapter.start)),
+                        Style::default().fg(Color::Cyan),
+                    ),
+                    Span::raw(chapter.title.clone()),
+                ]))
+                .style(style)
+            })
+            .collect();
+
+        let title = match current {
+            Some(index) => format!("Chapters ({}/{})", index + 1, chapters.len()),
+            None => format!("Chapters ({})", chapters.len()),
+        };
+        let list = List::new(items).block(Block::default().borders(Borders::ALL).title(title));
+        // Scrolled to keep the current chapter in view
+        let mut state = ListState::default().with_selected(current);
+        f.render_stateful_widget(list, area, &mut state);
+    }
+
     fn draw_playlists(&mut self, f: &mut Frame, area: Rect) {
         let playlists = self.playlist_manager.playlists();
 
@@ -1406,6 +2808,7 @@ impl App {
             .block(Block::default().borders(Borders::ALL).title(title))
             .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
 
+        self.list_areas.push((ListArea::Playlists, area));
         self.playlist_state.select(Some(self.playlist_selected));
         f.render_stateful_widget(list, area, &mut self.playlist_state);
     }
@@ -1455,31 +2858,29 @@ impl App {
             .block(Block::default().borders(Borders::ALL).title(title))
             .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
 
+        self.list_areas.push((ListArea::Search, area));
         self.search_state.select(Some(self.search_result_selected));
         f.render_stateful_widget(list, area, &mut self.search_state);
     }
 
     fn draw_progress_bar(&mut self, f: &mut Frame, area: Rect) {
-        // Store the progress bar area for mouse click detection
-        self.progress_bar_area = Some(area);
-
         // Get playback status icon
-        let status_icon = if self.player.is_playing() {
+        let status_icon = if self.playback.is_playing() {
             "▶"
-        } else if self.player.is_paused() {
+        } else if self.playback.is_paused() {
             "⏸"
         } else {
             "⏹"
         };
 
         // Get position and progress in a single call to minimize mutex locks
-        let (position, progress) = self.player.get_position_and_progress();
+        let (position, progress) = self.playback.position_and_progress();
         let progress = progress.unwrap_or(0.0);
 
-        let position_secs = position.as_secs();
-        let position_str = format!("{}:{:02}", position_secs / 60, position_secs % 60);
+        let position_str = format_time(position);
 
-        let duration_str = if let Some(metadata) = self.player.current_metadata() {
+        let metadata = self.playback.current_metadata();
+        let duration_str = if let Some(metadata) = &metadata {
             metadata.format_duration()
         } else {
             "?:??".to_string()
@@ -1487,21 +2888,88 @@ impl App {
 
         // Build the time label part
         let time_label = format!("{} {} / {} ", status_icon, position_str, duration_str);
-        let time_label_len = time_label.len();
+        let time_label_len = time_label.chars().count();
 
         // Calculate how many characters are available for the progress bar
         let available_width = (area.width as usize).saturating_sub(time_label_len);
         let filled_width = ((available_width as f64 * progress) as usize).min(available_width);
-        let empty_width = available_width.saturating_sub(filled_width);
 
-        let progress_bar = format!(
-            "{}{}{}",
-            time_label,
-            "─".repeat(filled_width),
-            "─".repeat(empty_width)
-        );
+        // The waveform replaces the plain line once it has been computed
+        let waveform = self.waveform.get();
+        let mut bar: Vec<char> = (0..available_width)
+            .map(|column| match &waveform {
+                Some(waveform) => {
+                    let start = column as f64 / available_width as f64;
+                    let end = (column + 1) as f64 / available_width as f64;
+                    waveform_char(waveform.level(start, end))
+                }
+                None => '─',
+            })
+            .collect();
+
+        // Remember where the bar itself sits, so clicks on it can seek
+        let bar_x = area.x + time_label_len.min(area.width as usize) as u16;
+        self.progress_bar_area = Some(Rect::new(
+            bar_x,
+            area.y,
+            available_width as u16,
+            area.height,
+        ));
+
+        // Chapter ticks and loop markers sit where their share of the duration falls on
+        // the bar, the loop markers on top
+        let duration_secs = metadata.as_ref().and_then(|m| m.duration_secs).unwrap_or(0);
+        let (loop_start, loop_end) = self.playback.ab_loop();
+        if duration_secs > 0 && !bar.is_empty() {
+            let chapters = metadata.iter().flat_map(|m| &m.chapters);
+            for chapter in chapters.filter(|chapter| !chapter.start.is_zero()) {
+                let share = chapter.start.as_secs_f64() / duration_secs as f64;
+                let column = ((bar.len() as f64 * share) as usize).min(bar.len() - 1);
+                bar[column] = CHAPTER_TICK;
+            }
+            for (marker, label) in [(loop_start, 'A'), (loop_end, 'B')] {
+                if let Some(marker) = marker {
+                    let share = marker.as_secs_f64() / duration_secs as f64;
+                    let column = ((bar.len() as f64 * share) as usize).min(bar.len() - 1);
+                    bar[column] = label;
+                }
+            }
+        }
+
+        // Played part in green, the rest dimmed when there is a waveform to look at
+        let played_style = Style::default().fg(Color::Green);
+        let upcoming_style = if waveform.is_some() {
+            Style::default().fg(Color::DarkGray)
+        } else {
+            played_style
+        };
+        let marker_style = Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD);
+        let tick_style = Style::default().fg(Color::Cyan);
+
+        let mut spans = vec![Span::styled(time_label, played_style)];
+        let mut run = String::new();
+        let mut run_style = played_style;
+        for (column, c) in bar.into_iter().enumerate() {
+            let style = if c == 'A' || c == 'B' {
+                marker_style
+            } else if c == CHAPTER_TICK {
+                tick_style
+            } else if column < filled_width {
+                played_style
+            } else {
+                upcoming_style
+            };
+            if style != run_style && !run.is_empty() {
+                spans.push(Span::styled(std::mem::take(&mut run), run_style));
+            }
+            run_style = style;
+            run.push(c);
+        }
+        spans.push(Span::styled(run, run_style));
 
-        let paragraph = Paragraph::new(progress_bar).style(Style::default().fg(Color::Green));
+        let paragraph = Paragraph::new(Line::from(spans));
 
         f.render_widget(paragraph, area);
     }
@@ -1542,21 +3010,60 @@ impl App {
             InputMode::Command => format!(":{}", self.command_input),
         };
 
-        let paragraph = Paragraph::new(status_text)
-            .block(Block::default().borders(Borders::ALL).title("Status"));
+        let mut block = Block::default().borders(Borders::ALL).title("Status");
+        if let Some(label) = self.sleep_label() {
+            block = block.title_top(
+                Line::from(Span::styled(label, Style::default().fg(Color::Magenta)))
+                    .right_aligned(),
+            );
+        }
+        let paragraph = Paragraph::new(status_text).block(block);
 
         f.render_widget(paragraph, area);
     }
 
+    // Asks the engine for `track`. The rest follows once it reports the track started.
+    fn play_track(&mut self, track: &Path) {
+        self.engine.send(Command::Play(track.to_path_buf()));
+        // The engine drops whatever was preloaded for the previous track
+        self.gapless_candidate = None;
+    }
+
     fn start_track(&mut self, track: &Path) {
-        *self.track_play_time.lock().unwrap() = Some(SystemTime::now());
+        self.scrobble_pending = true;
+        self.now_playing_pending = Some(track.to_path_buf());
+    }
+
+    // Writes the resume points the engine reported, a little while after they changed so
+    // that a crash loses little, without writing on every track change or pause
+    fn save_resume_positions(&mut self) {
+        if self
+            .resume_changed
+            .is_some_and(|changed| changed.elapsed() >= RESUME_SAVE_DELAY)
+        {
+            let _ = self.playback.resume_positions().save();
+            self.resume_changed = None;
+        }
+    }
+
+    // Updates now playing on Last.fm if enabled, as soon as the tags are in
+    fn update_now_playing(&mut self) {
+        let Some(track) = self.now_playing_pending.clone() else {
+            return;
+        };
+        // Dropped once something else plays
+        if self.playback.current_track().as_ref() != Some(&track) {
+            self.now_playing_pending = None;
+            return;
+        }
+        let Some(metadata) = self.playback.current_metadata() else {
+            return;
+        };
+        self.now_playing_pending = None;
 
-        // Update now playing on Last.fm if enabled
         if self.lastfm_scrobbler.is_enabled() {
-            if let Some(metadata) = self.player.current_metadata() {
-                if let Err(e) = self.lastfm_scrobbler.now_playing(track, &metadata) {
-                    eprintln!("Failed to update now playing on Last.fm: {}", e);
-                }
+            if let Err(e) = self.lastfm_scrobbler.now_playing(&track, &metadata) {
+                eprintln!("Failed to update now playing on Last.fm: {}", e);
             }
         }
     }
@@ -1566,16 +3073,13 @@ impl App {
             return;
         }
 
-        let track_start = self.track_play_time.lock().unwrap().take();
-        if let Some(start_time) = track_start {
-            if let Some(track) = self.player.current_track() {
-                if let Some(metadata) = self.player.current_metadata() {
+        if std::mem::take(&mut self.scrobble_pending) {
+            if let Some(track) = self.playback.current_track() {
+                if let Some(metadata) = self.playback.current_metadata() {
                     // According to Last.fm scrobbling rules:
                     // - Track must have been played for at least half its duration, or 4 minutes
-                    let elapsed = SystemTime::now()
-                        .duration_since(start_time)
-                        .unwrap_or(Duration::from_secs(0))
-                        .as_secs();
+                    // The decoder position is used, so pauses do not count as listening time
+                    let elapsed = self.playback.position().as_secs();
 
                     let should_scrobble = if let Some(duration) = metadata.duration_secs {
                         // Track must be played for at least half its duration or 4 minutes (whichever is lower)
@@ -1617,3 +3121,50 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         ])
         .split(vertical[1])[1]
 }
+
+// Drawn on the progress bar where a chapter starts
+const CHAPTER_TICK: char = '│';
+
+// Block character as tall as `level`, keeping silence visible as a baseline
+fn waveform_char(level: f32) -> char {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    BLOCKS[((level * BLOCKS.len() as f32) as usize).min(BLOCKS.len() - 1)]
+}
+
+// One channel meter: the RMS level as a bar, the peak as a tick and its value in dB
+fn meter_line(label: &str, level: Level, width: u16) -> Line<'static> {
+    let cells = (width as usize).saturating_sub(2 + 9);
+    let position =
+        |db: f32| (((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0) * cells as f32).round() as usize;
+    let color = |db: f32| {
+        if db > -3.0 {
+            Color::Red
+        } else if db > -12.0 {
+            Color::Yellow
+        } else {
+            Color::Green
+        }
+    };
+
+    let rms = position(level.rms_db);
+    let peak = position(level.peak_db);
+    let mut spans = vec![
+        Span::styled(format!("{label} "), Style::default().fg(Color::Cyan)),
+        Span::styled("█".repeat(rms), Style::default().fg(color(level.rms_db))),
+    ];
+    let mut used = rms;
+    if peak > rms {
+        spans.push(Span::raw(" ".repeat(peak - rms - 1)));
+        spans.push(Span::styled("│", Style::default().fg(color(level.peak_db))));
+        used = peak;
+    }
+    spans.push(Span::raw(" ".repeat(cells - used)));
+    spans.push(Span::raw(format!(" {:>5.1} dB", level.peak_db)));
+    Line::from(spans)
+}
+
+// Formats a position as "m:ss"
+fn format_time(position: Duration) -> String {
+    let secs = position.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
@@ -0,0 +1,415 @@
+use rodio::Source;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Frames kept for the UI, enough for one FFT window
+const TAP_FRAMES: usize = FFT_SIZE;
+
+const FFT_SIZE: usize = 2048;
+// Frames collected on the audio thread before handing them over
+const BLOCK_FRAMES: usize = 512;
+// Lowest and highest frequency shown by the spectrum
+const MIN_FREQ: f32 = 40.0;
+const MAX_FREQ: f32 = 16_000.0;
+/// Levels at or below this are drawn empty.
+pub const FLOOR_DB: f32 = -60.0;
+// How much bars and peaks may fall per update, as a fraction of the full range
+const FALLOFF: f32 = 0.06;
+// Window of the peak and RMS meters
+const METER_MS: u32 = 50;
+
+/// Recent stereo output shared with the UI. The audio thread only ever `try_lock`s it,
+/// so a busy UI makes the tap drop a block instead of stalling playback.
+#[derive(Default)]
+pub struct AudioTap {
+    enabled: AtomicBool,
+    buffer: Mutex<TapBuffer>,
+}
+
+#[derive(Default)]
+struct TapBuffer {
+    frames: VecDeque<[f32; 2]>,
+    sample_rate: u32,
+}
+
+impl AudioTap {
+    /// Starts or stops collecting samples. The tap costs nothing while disabled.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.clear();
+        }
+    }
+
+    /// Copies the most recent frames, oldest first, with their sample rate.
+    pub fn snapshot(&self) -> (Vec<[f32; 2]>, u32) {
+        let buffer = self.buffer.lock().unwrap();
+        (buffer.frames.iter().copied().collect(), buffer.sample_rate)
+    }
+
+    pub fn clear(&self) {
+        self.buffer.lock().unwrap().frames.clear();
+    }
+
+    // Called on the audio thread; gives up rather than waiting for the UI
+    fn push(&self, block: &[[f32; 2]], sample_rate: u32) -> bool {
+        let Ok(mut buffer) = self.buffer.try_lock() else {
+            return false;
+        };
+        if buffer.sample_rate != sample_rate {
+            buffer.frames.clear();
+            buffer.sample_rate = sample_rate;
+        }
+        let len = buffer.frames.len();
+        let overflow = (len + block.len()).saturating_sub(TAP_FRAMES);
+        buffer.frames.drain(..overflow.min(len));
+        buffer.frames.extend(block);
+        true
+    }
+}
+
+/// Stereo source copying what passes through into an `AudioTap`.
+pub struct Tap<S> {
+    input: S,
+    shared: Arc<AudioTap>,
+    block: Vec<[f32; 2]>,
+    frame: [f32; 2],
+    channel: usize,
+}
+
+impl<S> Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, shared: Arc<AudioTap>) -> Self {
+        Self {
+            input,
+            shared,
+            block: Vec::with_capacity(BLOCK_FRAMES),
+            frame: [0.0; 2],
+            channel: 0,
+        }
+    }
+}
+
+impl<S> Iterator for Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.input.next()?;
+        if !self.shared.enabled.load(Ordering::Relaxed) {
+            return Some(sample);
+        }
+
+        self.frame[self.channel.min(1)] = sample;
+        self.channel += 1;
+        if self.channel >= self.input.channels() as usize {
+            self.channel = 0;
+            self.block.push(self.frame);
+            if self.block.len() >= BLOCK_FRAMES {
+                // A dropped block only means one stale redraw
+                self.shared.push(&self.block, self.input.sample_rate());
+                self.block.clear();
+            }
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), rodio::source::SeekError> {
+        self.input.try_seek(pos)?;
+        self.block.clear();
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+/// Peak and RMS level of one channel, in dBFS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    pub peak_db: f32,
+    pub rms_db: f32,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            peak_db: FLOOR_DB,
+            rms_db: FLOOR_DB,
+        }
+    }
+}
+
+/// Turns tapped frames into spectrum bars and channel meters for display. Runs on the
+/// UI thread; values fall back gradually so the display does not flicker.
+pub struct Analyzer {
+    window: Vec<f32>,
+    // e^(-2πik/N) for the first half of the FFT
+    twiddles: Vec<(f32, f32)>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    bars: Vec<f32>,
+    levels: [Level; 2],
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        let twiddles = (0..FFT_SIZE / 2)
+            .map(|k| {
+                let (sin, cos) = (-2.0 * PI * k as f32 / FFT_SIZE as f32).sin_cos();
+                (cos, sin)
+            })
+            .collect();
+        Self {
+            window,
+            twiddles,
+            re: vec![0.0; FFT_SIZE],
+            im: vec![0.0; FFT_SIZE],
+            bars: Vec::new(),
+            levels: [Level::default(); 2],
+        }
+    }
+}
+
+impl Analyzer {
+    /// Bar heights from 0.0 (at `FLOOR_DB` or below) to 1.0 (full scale).
+    pub fn bars(&self) -> &[f32] {
+        &self.bars
+    }
+
+    /// Left and right levels.
+    pub fn levels(&self) -> [Level; 2] {
+        self.levels
+    }
+
+    /// Analyzes the latest `frames` into `bar_count` bars. An empty slice lets
+    /// everything fall back to silence.
+    pub fn update(&mut self, frames: &[[f32; 2]], sample_rate: u32, bar_count: usize) {
+        let mut bars = self.spectrum(frames, sample_rate, bar_count);
+        if self.bars.len() == bars.len() {
+            for (bar, old) in bars.iter_mut().zip(&self.bars) {
+                *bar = bar.max(old - FALLOFF);
+            }
+        }
+        self.bars = bars;
+
+        let meter_frames = (sample_rate * METER_MS / 1000) as usize;
+        let recent = &frames[frames.len().saturating_sub(meter_frames)..];
+        let fall_db = FALLOFF * -FLOOR_DB;
+        for (channel, level) in self.levels.iter_mut().enumerate() {
+            let new = measure(recent.iter().map(|frame| frame[channel]));
+            level.peak_db = new.peak_db.max(level.peak_db - fall_db);
+            level.rms_db = new.rms_db.max(level.rms_db - fall_db);
+        }
+    }
+
+    fn spectrum(&mut self, frames: &[[f32; 2]], sample_rate: u32, bar_count: usize) -> Vec<f32> {
+        if frames.is_empty() || sample_rate == 0 || bar_count == 0 {
+            return vec![0.0; bar_count];
+        }
+
+        // Latest window of the mid signal, zero-padded at the front when short
+        let recent = &frames[frames.len().saturating_sub(FFT_SIZE)..];
+        let offset = FFT_SIZE - recent.len();
+        self.re.fill(0.0);
+        self.im.fill(0.0);
+        for (i, frame) in recent.iter().enumerate() {
+            let j = offset + i;
+            self.re[j] = f32::midpoint(frame[0], frame[1]) * self.window[j];
+        }
+        self.fft();
+
+        // A full-scale sine reads 0 dB
+        let window_sum: f32 = self.window.iter().sum();
+        let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+        let max_freq = MAX_FREQ.min(sample_rate as f32 / 2.0);
+        let ratio = max_freq / MIN_FREQ;
+
+        (0..bar_count)
+            .map(|bar| {
+                let low = MIN_FREQ * ratio.powf(bar as f32 / bar_count as f32);
+                let high = MIN_FREQ * ratio.powf((bar + 1) as f32 / bar_count as f32);
+                let first = (low / bin_width).round() as usize;
+                let last = ((high / bin_width).round() as usize)
+                    .max(first + 1)
+                    .min(FFT_SIZE / 2);
+                let magnitude = (first..last)
+                    .map(|k| self.re[k].hypot(self.im[k]))
+                    .fold(0.0f32, f32::max);
+                let db = 20.0 * (2.0 * magnitude / window_sum).max(1e-9).log10();
+                ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+            })
+            .collect()
+    }
+
+    // In-place iterative radix-2 FFT of `re` and `im`
+    fn fft(&mut self) {
+        let n = FFT_SIZE;
+        let mut j = 0;
+        for i in 1..n {
+            let mut bit = n >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                self.re.swap(i, j);
+                self.im.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= n {
+            let half = len / 2;
+            let step = n / len;
+            for start in (0..n).step_by(len) {
+                for k in 0..half {
+                    let (w_re, w_im) = self.twiddles[k * step];
+                    let a = start + k;
+                    let b = a + half;
+                    let t_re = self.re[b] * w_re - self.im[b] * w_im;
+                    let t_im = self.re[b] * w_im + self.im[b] * w_re;
+                    self.re[b] = self.re[a] - t_re;
+                    self.im[b] = self.im[a] - t_im;
+                    self.re[a] += t_re;
+                    self.im[a] += t_im;
+                }
+            }
+            len <<= 1;
+        }
+    }
+}
+
+fn measure(samples: impl Iterator<Item = f32>) -> Level {
+    let mut peak = 0.0f32;
+    let mut sum = 0.0f32;
+    let mut count = 0;
+    for sample in samples {
+        peak = peak.max(sample.abs());
+        sum += sample * sample;
+        count += 1;
+    }
+    if count == 0 {
+        return Level::default();
+    }
+    let to_db = |value: f32| (20.0 * value.max(1e-9).log10()).max(FLOOR_DB);
+    Level {
+        peak_db: to_db(peak),
+        rms_db: to_db((sum / count as f32).sqrt()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn sine(freq: f32, amplitude: f32, frames: usize) -> Vec<[f32; 2]> {
+        (0..frames)
+            .map(|i| {
+                let s = amplitude * (2.0 * PI * freq * i as f32 / 48_000.0).sin();
+                [s, s * 0.5]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tap_passes_samples_through() {
+        let samples: Vec<f32> = (0..4096).map(|i| i as f32 / 4096.0).collect();
+        let tap = Arc::new(AudioTap::default());
+
+        let output: Vec<f32> = Tap::new(
+            SamplesBuffer::new(2, 48_000, samples.clone()),
+            Arc::clone(&tap),
+        )
+        .collect();
+        assert_eq!(output, samples);
+        assert!(tap.snapshot().0.is_empty());
+
+        tap.set_enabled(true);
+        let output: Vec<f32> = Tap::new(
+            SamplesBuffer::new(2, 48_000, samples.clone()),
+            Arc::clone(&tap),
+        )
+        .collect();
+        assert_eq!(output, samples);
+        let (frames, sample_rate) = tap.snapshot();
+        assert_eq!(sample_rate, 48_000);
+        assert_eq!(frames.len(), 2048);
+        assert_eq!(frames[0], [samples[0], samples[1]]);
+    }
+
+    #[test]
+    fn test_tap_keeps_the_latest_frames() {
+        let tap = AudioTap::default();
+        for i in 0..10 {
+            assert!(tap.push(&[[i as f32, 0.0]; BLOCK_FRAMES], 44_100));
+        }
+        let (frames, _) = tap.snapshot();
+        assert_eq!(frames.len(), TAP_FRAMES);
+        assert_eq!(frames.last().unwrap()[0], 9.0);
+    }
+
+    #[test]
+    fn test_spectrum_peaks_at_the_tone() {
+        let mut analyzer = Analyzer::default();
+        analyzer.update(&sine(1_000.0, 1.0, FFT_SIZE), 48_000, 24);
+
+        let bars = analyzer.bars();
+        let loudest = (0..bars.len())
+            .max_by(|&a, &b| bars[a].total_cmp(&bars[b]))
+            .unwrap();
+        // 1 kHz sits in bar 11 of 24 between 40 Hz and 16 kHz
+        let ratio: f32 = MAX_FREQ / MIN_FREQ;
+        let expected = ((1_000.0 / MIN_FREQ).ln() / ratio.ln() * 24.0) as usize;
+        assert_eq!(loudest, expected);
+        assert!(bars[loudest] > 0.9);
+        assert!(bars[0] < 0.3);
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut analyzer = Analyzer::default();
+        analyzer.update(&sine(1_000.0, 1.0, 4800), 48_000, 16);
+        let [left, right] = analyzer.levels();
+        assert!(left.peak_db.abs() < 0.1);
+        assert!((left.rms_db + 3.01).abs() < 0.1);
+        assert!((right.peak_db + 6.02).abs() < 0.1);
+
+        // Silence lets the meters fall instead of dropping at once
+        analyzer.update(&[], 48_000, 16);
+        let [left, _] = analyzer.levels();
+        assert!(left.peak_db < 0.0 && left.peak_db > FLOOR_DB);
+        assert!(analyzer.bars().iter().all(|&bar| bar < 1.0));
+    }
+}
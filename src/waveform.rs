@@ -0,0 +1,268 @@
+use crate::cue;
+use crate::logger;
+use crate::player::Player;
+use crate::radio;
+use anyhow::{Context, Result};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+// Number of points kept per track, more than any terminal is wide
+const RESOLUTION: usize = 1000;
+
+// Frames summarized by each peak while decoding
+const BLOCK_FRAMES: usize = 1024;
+
+/// Amplitude overview of a track, scaled so its loudest part is 255.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Waveform {
+    path: PathBuf,
+    // Modification time of the audio file, in seconds since the epoch
+    modified: u64,
+    peaks: Vec<u8>,
+}
+
+impl Waveform {
+    /// Highest level between `start` and `end`, given as fractions of the track,
+    /// from 0.0 to 1.0.
+    pub fn level(&self, start: f64, end: f64) -> f32 {
+        let len = self.peaks.len();
+        if len == 0 {
+            return 0.0;
+        }
+        let first = ((start * len as f64) as usize).min(len - 1);
+        let last = ((end * len as f64).ceil() as usize).clamp(first + 1, len);
+        let peak = self.peaks[first..last].iter().copied().max().unwrap_or(0);
+        f32::from(peak) / 255.0
+    }
+
+    /// Reads the waveform of `path` from `cache_dir`, or decodes the file and caches
+    /// the result. Returns `None` when `cancelled` got set along the way.
+    pub fn load_or_compute(
+        path: &Path,
+        cache_dir: &Path,
+        cancelled: &AtomicBool,
+    ) -> Result<Option<Self>> {
+        let modified = modified_secs(path).unwrap_or(0);
+        let cache_file = cache_dir.join(cache_name(path));
+        if let Some(waveform) = fs::read_to_string(&cache_file)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Waveform>(&content).ok())
+            .filter(|waveform| waveform.path == path && waveform.modified == modified)
+        {
+            return Ok(Some(waveform));
+        }
+
+        let Some(peaks) = compute_peaks(path, cancelled)? else {
+            return Ok(None);
+        };
+        let waveform = Waveform {
+            path: path.to_path_buf(),
+            modified,
+            peaks,
+        };
+
+        fs::create_dir_all(cache_dir).with_context(|| {
+            format!(
+                "Failed to create waveform directory {}",
+                cache_dir.display()
+            )
+        })?;
+        fs::write(&cache_file, serde_json::to_string(&waveform)?).with_context(|| {
+            format!("Failed to write waveform file at {}", cache_file.display())
+        })?;
+        Ok(Some(waveform))
+    }
+
+    pub fn cache_dir() -> PathBuf {
+        dirs::cache_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("impulse")
+            .join("waveforms")
+    }
+}
+
+// One file per track; the path and mtime inside tell whether it is still valid
+// MD5 of the path, which unlike the std hasher stays the same across Rust releases
+fn cache_name(path: &Path) -> String {
+    format!(
+        "{:x}.json",
+        md5::compute(path.as_os_str().as_encoded_bytes())
+    )
+}
+
+// Modification time of the file holding the audio, the image file for CUE tracks
+fn modified_secs(path: &Path) -> Option<u64> {
+    let file = match cue::resolve(path) {
+        Some((_, track)) => track.file,
+        None => path.to_path_buf(),
+    };
+    let modified = fs::metadata(file).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+// Decodes the whole track, keeping the peak of every block, then folds the blocks
+// into at most `RESOLUTION` points
+fn compute_peaks(path: &Path, cancelled: &AtomicBool) -> Result<Option<Vec<u8>>> {
+    let source = Player::decode_for_analysis(path)?;
+    let block_len = BLOCK_FRAMES * source.channels().max(1) as usize;
+
+    let mut blocks = Vec::new();
+    let mut peak = 0.0f32;
+    let mut count = 0;
+    for sample in source {
+        peak = peak.max(sample.abs());
+        count += 1;
+        if count == block_len {
+            blocks.push(peak);
+            peak = 0.0;
+            count = 0;
+            if cancelled.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+        }
+    }
+    if count > 0 {
+        blocks.push(peak);
+    }
+
+    let points = blocks.len().min(RESOLUTION);
+    let folded: Vec<f32> = (0..points)
+        .map(|i| {
+            let first = i * blocks.len() / points;
+            let last = ((i + 1) * blocks.len() / points).max(first + 1);
+            blocks[first..last].iter().copied().fold(0.0, f32::max)
+        })
+        .collect();
+
+    let loudest = folded.iter().copied().fold(0.0, f32::max);
+    let scale = if loudest > 0.0 { 255.0 / loudest } else { 0.0 };
+    Ok(Some(
+        folded
+            .iter()
+            .map(|peak| (peak * scale).round() as u8)
+            .collect(),
+    ))
+}
+
+/// Computes waveforms on a background thread, one track at a time. Asking for another
+/// track abandons the one in progress.
+pub struct WaveformLoader {
+    requested: Option<PathBuf>,
+    cancelled: Arc<AtomicBool>,
+    ready: Arc<Mutex<Option<Waveform>>>,
+}
+
+impl Default for WaveformLoader {
+    fn default() -> Self {
+        Self {
+            requested: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            ready: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl WaveformLoader {
+    /// Starts on the waveform of `path` unless it is the one already asked for.
+    pub fn request(&mut self, path: Option<&Path>) {
+        // Streams never end, so there is nothing to draw for them
+        let path = path.filter(|path| !radio::is_url(path));
+        if self.requested.as_deref() == path {
+            return;
+        }
+
+        // Set before clearing, so a thread finishing in between drops its result
+        self.cancelled.store(true, Ordering::Relaxed);
+        *self.ready.lock().unwrap() = None;
+        self.requested = path.map(Path::to_path_buf);
+        let Some(path) = self.requested.clone() else {
+            return;
+        };
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.cancelled = Arc::clone(&cancelled);
+        let ready = Arc::clone(&self.ready);
+        thread::spawn(move || {
+            match Waveform::load_or_compute(&path, &Waveform::cache_dir(), &cancelled) {
+                Ok(Some(waveform)) => {
+                    let mut ready = ready.lock().unwrap();
+                    if !cancelled.load(Ordering::Relaxed) {
+                        *ready = Some(waveform);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => logger::log(&format!(
+                    "Failed to compute the waveform of {}: {e:#}",
+                    path.display()
+                )),
+            }
+        });
+    }
+
+    /// Waveform of the requested track, once it is ready.
+    pub fn get(&self) -> Option<Waveform> {
+        self.ready.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::tests::write_wav_with;
+    use tempfile::TempDir;
+
+    // 16-bit mono WAV with a quiet first half and a loud second half
+    fn write_wav(path: &Path, sample_rate: u32, frames: u32) {
+        write_wav_with(path, 1, sample_rate, frames, |frame| {
+            if frame < frames / 2 { 1000 } else { 20000 }
+        });
+    }
+
+    #[test]
+    fn test_waveform_follows_the_level() {
+        let temp_dir = TempDir::new().unwrap();
+        let track = temp_dir.path().join("track.wav");
+        write_wav(&track, 8000, 8000 * 4);
+        let cache_dir = temp_dir.path().join("cache");
+
+        let waveform = Waveform::load_or_compute(&track, &cache_dir, &AtomicBool::new(false))
+            .unwrap()
+            .unwrap();
+        assert_eq!(waveform.level(0.6, 1.0), 1.0);
+        let quiet = waveform.level(0.0, 0.4);
+        assert!((quiet - 0.05).abs() < 0.01, "{quiet}");
+
+        // The second lookup comes from the cache
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+        let cached = Waveform::load_or_compute(&track, &cache_dir, &AtomicBool::new(true))
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached, waveform);
+    }
+
+    #[test]
+    fn test_cancelled_waveform_is_not_cached() {
+        let temp_dir = TempDir::new().unwrap();
+        let track = temp_dir.path().join("track.wav");
+        write_wav(&track, 8000, 8000 * 4);
+        let cache_dir = temp_dir.path().join("cache");
+
+        let result = Waveform::load_or_compute(&track, &cache_dir, &AtomicBool::new(true)).unwrap();
+        assert!(result.is_none());
+        assert!(!cache_dir.exists());
+    }
+
+    #[test]
+    fn test_cache_name_is_stable() {
+        assert_eq!(
+            cache_name(Path::new("/music/a.flac")),
+            "897108bf80a69ea6b4649dc7153d79cd.json"
+        );
+    }
+}